@@ -105,7 +105,7 @@ pub type hxa_uint64 = ::std::os::raw::c_ulonglong;
 
 #[allow(non_camel_case_types)]
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum HXANodeType {
     /// node only containing meta data.
     HXA_NT_META_ONLY = 0,
@@ -119,7 +119,7 @@ pub enum HXANodeType {
 
 #[allow(non_camel_case_types)]
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum HXAImageType {
     /// 6 sided qube, in the order of: +x, -x, +y, -y, +z, -z.
     HXA_IT_CUBE_IMAGE = 0,
@@ -133,7 +133,7 @@ pub enum HXAImageType {
 
 #[allow(non_camel_case_types)]
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum HXAMetaDataType {
     HXA_MDT_INT64 = 0,
     HXA_MDT_DOUBLE = 1,
@@ -176,7 +176,7 @@ pub union HXAMetaValue {
 
 #[allow(non_camel_case_types)]
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum HXALayerDataType {
     /// 8bit unsigned integer
     HXA_LDT_UINT8 = 0,
@@ -313,7 +313,6 @@ mod tests {
     use super::*;
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXAMetaValue() {
         assert_eq!(
@@ -327,7 +326,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXAMetaValue))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMetaValue>())).int64_value as *const _ as usize },
+            ::std::mem::offset_of!(HXAMetaValue, int64_value),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -337,7 +336,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMetaValue>())).double_value as *const _ as usize },
+            ::std::mem::offset_of!(HXAMetaValue, double_value),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -347,7 +346,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMetaValue>())).node_value as *const _ as usize },
+            ::std::mem::offset_of!(HXAMetaValue, node_value),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -357,7 +356,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMetaValue>())).text_value as *const _ as usize },
+            ::std::mem::offset_of!(HXAMetaValue, text_value),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -367,7 +366,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMetaValue>())).bin_value as *const _ as usize },
+            ::std::mem::offset_of!(HXAMetaValue, bin_value),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -377,7 +376,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMetaValue>())).array_of_meta as *const _ as usize },
+            ::std::mem::offset_of!(HXAMetaValue, array_of_meta),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -389,7 +388,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXAMeta() {
         assert_eq!(
@@ -403,7 +401,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXAMeta))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMeta>())).name as *const _ as usize },
+            ::std::mem::offset_of!(HXAMeta, name),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -413,7 +411,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMeta>())).type_ as *const _ as usize },
+            ::std::mem::offset_of!(HXAMeta, type_),
             256usize,
             concat!(
                 "Offset of field: ",
@@ -423,7 +421,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMeta>())).array_length as *const _ as usize },
+            ::std::mem::offset_of!(HXAMeta, array_length),
             260usize,
             concat!(
                 "Offset of field: ",
@@ -433,7 +431,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAMeta>())).value as *const _ as usize },
+            ::std::mem::offset_of!(HXAMeta, value),
             264usize,
             concat!(
                 "Offset of field: ",
@@ -445,7 +443,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXALayerData() {
         assert_eq!(
@@ -459,7 +456,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXALayerData))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayerData>())).uint8_data as *const _ as usize },
+            ::std::mem::offset_of!(HXALayerData, uint8_data),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -469,7 +466,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayerData>())).int32_data as *const _ as usize },
+            ::std::mem::offset_of!(HXALayerData, int32_data),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -479,7 +476,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayerData>())).float_data as *const _ as usize },
+            ::std::mem::offset_of!(HXALayerData, float_data),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -489,7 +486,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayerData>())).double_data as *const _ as usize },
+            ::std::mem::offset_of!(HXALayerData, double_data),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -501,7 +498,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXALayer() {
         assert_eq!(
@@ -515,7 +511,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXALayer))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayer>())).name as *const _ as usize },
+            ::std::mem::offset_of!(HXALayer, name),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -525,7 +521,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayer>())).components as *const _ as usize },
+            ::std::mem::offset_of!(HXALayer, components),
             256usize,
             concat!(
                 "Offset of field: ",
@@ -535,7 +531,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayer>())).type_ as *const _ as usize },
+            ::std::mem::offset_of!(HXALayer, type_),
             260usize,
             concat!(
                 "Offset of field: ",
@@ -545,7 +541,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayer>())).data as *const _ as usize },
+            ::std::mem::offset_of!(HXALayer, data),
             264usize,
             concat!(
                 "Offset of field: ",
@@ -557,7 +553,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXALayerStack() {
         assert_eq!(
@@ -571,7 +566,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXALayerStack))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayerStack>())).layer_count as *const _ as usize },
+            ::std::mem::offset_of!(HXALayerStack, layer_count),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -581,7 +576,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXALayerStack>())).layers as *const _ as usize },
+            ::std::mem::offset_of!(HXALayerStack, layers),
             8usize,
             concat!(
                 "Offset of field: ",
@@ -593,7 +588,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXANodeContentGeometry() {
         assert_eq!(
@@ -607,9 +601,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXANodeContentGeometry))
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).vertex_count as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, vertex_count),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -619,9 +611,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).vertex_stack as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, vertex_stack),
             8usize,
             concat!(
                 "Offset of field: ",
@@ -631,10 +621,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).edge_corner_count as *const _
-                    as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, edge_corner_count),
             24usize,
             concat!(
                 "Offset of field: ",
@@ -644,9 +631,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).corner_stack as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, corner_stack),
             32usize,
             concat!(
                 "Offset of field: ",
@@ -656,9 +641,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).edge_stack as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, edge_stack),
             48usize,
             concat!(
                 "Offset of field: ",
@@ -668,9 +651,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).face_count as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, face_count),
             64usize,
             concat!(
                 "Offset of field: ",
@@ -680,9 +661,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentGeometry>())).face_stack as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentGeometry, face_stack),
             72usize,
             concat!(
                 "Offset of field: ",
@@ -694,7 +673,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXANodeContentImage() {
         assert_eq!(
@@ -708,7 +686,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXANodeContentImage))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANodeContentImage>())).type_ as *const _ as usize },
+            ::std::mem::offset_of!(HXANodeContentImage, type_),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -718,9 +696,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentImage>())).resolution as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentImage, resolution),
             4usize,
             concat!(
                 "Offset of field: ",
@@ -730,9 +706,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe {
-                &(*(::std::ptr::null::<HXANodeContentImage>())).image_stack as *const _ as usize
-            },
+            ::std::mem::offset_of!(HXANodeContentImage, image_stack),
             16usize,
             concat!(
                 "Offset of field: ",
@@ -744,7 +718,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXANodeContent() {
         assert_eq!(
@@ -758,7 +731,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXANodeContent))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANodeContent>())).geometry as *const _ as usize },
+            ::std::mem::offset_of!(HXANodeContent, geometry),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -768,7 +741,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANodeContent>())).image as *const _ as usize },
+            ::std::mem::offset_of!(HXANodeContent, image),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -780,7 +753,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXANode() {
         assert_eq!(
@@ -794,7 +766,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXANode))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANode>())).type_ as *const _ as usize },
+            ::std::mem::offset_of!(HXANode, type_),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -804,7 +776,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANode>())).meta_data_count as *const _ as usize },
+            ::std::mem::offset_of!(HXANode, meta_data_count),
             4usize,
             concat!(
                 "Offset of field: ",
@@ -814,7 +786,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANode>())).meta_data as *const _ as usize },
+            ::std::mem::offset_of!(HXANode, meta_data),
             8usize,
             concat!(
                 "Offset of field: ",
@@ -824,7 +796,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXANode>())).content as *const _ as usize },
+            ::std::mem::offset_of!(HXANode, content),
             16usize,
             concat!(
                 "Offset of field: ",
@@ -836,7 +808,6 @@ mod tests {
     }
 
     #[test]
-    #[allow(deref_nullptr)]
     #[allow(non_snake_case)]
     fn bindgen_test_layout_HXAFile() {
         assert_eq!(
@@ -850,7 +821,7 @@ mod tests {
             concat!("Alignment of ", stringify!(HXAFile))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAFile>())).version as *const _ as usize },
+            ::std::mem::offset_of!(HXAFile, version),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -860,7 +831,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAFile>())).node_count as *const _ as usize },
+            ::std::mem::offset_of!(HXAFile, node_count),
             4usize,
             concat!(
                 "Offset of field: ",
@@ -870,7 +841,7 @@ mod tests {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<HXAFile>())).node_array as *const _ as usize },
+            ::std::mem::offset_of!(HXAFile, node_array),
             8usize,
             concat!(
                 "Offset of field: ",
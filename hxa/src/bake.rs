@@ -0,0 +1,919 @@
+//! Baking surface data into image nodes.
+//!
+//! Bakers rasterize the triangles of a geometry node in UV space, compute a value for the
+//! surface point at the center of every covered texel and store the result in an image node.
+//! Texel rows go from V = 0 to V = 1. Texels outside of the UV charts are filled from their
+//! covered neighbours, so filtering near chart borders does not pick up the background.
+
+use std::f64::consts::PI;
+
+#[cfg(feature = "bvh")]
+use crate::bvh::Bvh;
+use crate::math::{
+    add, closest_on_triangle, cross, dot, intersect_triangle, length, normalize, scale, sub,
+    triangle_normal, Vec3,
+};
+use crate::{
+    convention, Error, File, GeometryNode, HXAImageType, ImageNode, Layer, LayerData, Meta,
+    MetaValue, Node, Result,
+};
+
+/// Settings of a bake.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BakeOptions {
+    /// Width and height of the image in texels.
+    pub resolution: [u32; 2],
+    /// The UV set the image is mapped with.
+    pub uv_set: usize,
+    /// Rays cast per texel.
+    pub samples: u32,
+    /// Occluders further away than this do not count.
+    pub max_distance: f64,
+    /// How far in front of and behind the low surface the high surface is searched for.
+    pub cage_distance: f64,
+    /// Distance rays start above the surface, to avoid hitting the face they start on.
+    pub bias: f64,
+    /// Rings of texels around the charts filled from their neighbours.
+    pub dilation: u32,
+}
+
+impl BakeOptions {
+    /// Bake a square image of `resolution` texels using the lightmap UVs in `uv1`.
+    pub fn new(resolution: u32) -> Self {
+        Self {
+            resolution: [resolution; 2],
+            uv_set: 1,
+            samples: 64,
+            max_distance: f64::INFINITY,
+            cage_distance: 0.1,
+            bias: 1e-4,
+            dilation: 2,
+        }
+    }
+}
+
+impl GeometryNode {
+    /// Bake ambient occlusion into an image with an `ambient_occlusion` layer.
+    ///
+    /// Every texel holds the fraction of cosine weighted rays over the front of the surface that
+    /// escape, so 1 is fully open and 0 fully occluded.
+    pub fn bake_ambient_occlusion(&self, options: &BakeOptions) -> Result<ImageNode> {
+        let surface = Surface::for_queries(self)?;
+        let uvs = self.corner_uvs(options.uv_set)?;
+        let [width, height] = options.resolution.map(|r| r as usize);
+        let mut values = vec![1.0f32; width * height];
+        let mut covered = vec![false; width * height];
+
+        let samples = options.samples.max(1);
+        surface.rasterize(&uvs, options.resolution, |triangle, barycentric, texel| {
+            let normal = surface.normals[triangle];
+            let origin = add(
+                surface.interpolate(triangle, barycentric),
+                scale(normal, options.bias),
+            );
+            let (tangent, bitangent) = tangent_frame(normal);
+            let rotation = hash(texel);
+            let open = (0..samples)
+                .filter(|&sample| {
+                    let [x, y, z] = cosine_direction(sample, samples, rotation);
+                    let direction = add(
+                        add(scale(tangent, x), scale(bitangent, y)),
+                        scale(normal, z),
+                    );
+                    surface
+                        .intersect(origin, direction, options.max_distance)
+                        .is_none()
+                })
+                .count();
+            values[texel] = open as f32 / samples as f32;
+            covered[texel] = true;
+        });
+        dilate(
+            &mut values,
+            1,
+            &mut covered,
+            options.resolution,
+            options.dilation,
+        );
+
+        let mut image = ImageNode::new(
+            HXAImageType::HXA_IT_2D_IMAGE,
+            [options.resolution[0], options.resolution[1], 1],
+        );
+        image.image_stack.push(Layer::new(
+            convention::AMBIENT_OCCLUSION,
+            1,
+            LayerData::Float(values),
+        ));
+
+        Ok(image)
+    }
+
+    /// Bake the detail of a high resolution node onto this node, into an image with a
+    /// `displacement` and a `normal` layer.
+    ///
+    /// Rays start `cage_distance` in front of this surface and travel along its normal through
+    /// it, up to `cage_distance` behind it. The displacement is the signed distance from this
+    /// surface to the first hit, positive in front. The normal of the high surface at the hit is
+    /// stored in tangent space, with the tangent following U and the bitangent following V.
+    /// Texels where nothing is hit get no displacement and an unchanged normal.
+    ///
+    /// Both nodes use their `normal` layer, from the corner or the vertex stack, if they have
+    /// one, and smooth normals otherwise. This node uses its `tangent` layer the same way, or
+    /// tangents derived from the UVs of every triangle. The bitangent points along its
+    /// `binormal` layer, or is flipped by the sign of the fourth component of a 4 component
+    /// `tangent` layer, or follows V, so mirrored UV shells keep their green channel.
+    pub fn bake_displacement(
+        &self,
+        high: &GeometryNode,
+        options: &BakeOptions,
+    ) -> Result<ImageNode> {
+        let low_surface = Surface::new(self)?;
+        let high_surface = Surface::for_queries(high)?;
+        let low_normals = low_surface.corner_vectors(self, convention::LAYER_NORMALS)?;
+        let high_normals = high_surface.corner_vectors(high, convention::LAYER_NORMALS)?;
+        let tangents = match self
+            .corner_stack
+            .find(convention::LAYER_TANGENT)
+            .or_else(|| self.vertex_stack.find(convention::LAYER_TANGENT))
+        {
+            Some(_) => Some(low_surface.corner_vectors(self, convention::LAYER_TANGENT)?),
+            None => None,
+        };
+        let binormals = match self
+            .corner_stack
+            .find(convention::LAYER_BINORMAL)
+            .or_else(|| self.vertex_stack.find(convention::LAYER_BINORMAL))
+        {
+            Some(_) => Some(low_surface.corner_vectors(self, convention::LAYER_BINORMAL)?),
+            None => None,
+        };
+        let handedness = low_surface.corner_component(self, convention::LAYER_TANGENT, 3)?;
+        let uvs = self.corner_uvs(options.uv_set)?;
+        let [width, height] = options.resolution.map(|r| r as usize);
+        let mut displacement = vec![0.0f32; width * height];
+        let mut normals = [0.0f32, 0.0, 1.0].repeat(width * height);
+        let mut covered = vec![false; width * height];
+
+        let cage = options.cage_distance;
+        low_surface.rasterize(&uvs, options.resolution, |triangle, barycentric, texel| {
+            covered[texel] = true;
+            let normal = normalize(weighted(&low_normals[triangle], barycentric));
+            let origin = add(
+                low_surface.interpolate(triangle, barycentric),
+                scale(normal, cage),
+            );
+            let Some(hit) = high_surface.intersect(origin, scale(normal, -1.0), 2.0 * cage) else {
+                return;
+            };
+            displacement[texel] = (cage - hit.distance) as f32;
+
+            let uv_frame = || {
+                let corners = low_surface.corners[triangle].map(|corner| uvs[corner]);
+                uv_tangents(low_surface.points[triangle], corners)
+            };
+            let (tangent, bitangent) = match &tangents {
+                Some(tangents) => {
+                    let tangent = weighted(&tangents[triangle], barycentric);
+                    let bitangent = match (&binormals, &handedness) {
+                        (Some(binormals), _) => weighted(&binormals[triangle], barycentric),
+                        (None, Some(handedness)) => {
+                            let sign: f64 = handedness[triangle]
+                                .iter()
+                                .zip(barycentric)
+                                .map(|(w, weight)| w * weight)
+                                .sum();
+                            scale(cross(normal, tangent), sign)
+                        }
+                        (None, None) => uv_frame().1,
+                    };
+                    (tangent, bitangent)
+                }
+                None => uv_frame(),
+            };
+            let tangent = normalize(sub(tangent, scale(normal, dot(tangent, normal))));
+            let mut bitangent_sign = 1.0;
+            if dot(cross(normal, tangent), bitangent) < 0.0 {
+                bitangent_sign = -1.0;
+            }
+            let bitangent = scale(cross(normal, tangent), bitangent_sign);
+            let high_normal = normalize(weighted(&high_normals[hit.triangle], hit.barycentric));
+            let local = [
+                dot(high_normal, tangent),
+                dot(high_normal, bitangent),
+                dot(high_normal, normal),
+            ];
+            for (target, value) in normals[texel * 3..][..3].iter_mut().zip(local) {
+                *target = value as f32;
+            }
+        });
+        let mut normals_covered = covered.clone();
+        dilate(
+            &mut displacement,
+            1,
+            &mut covered,
+            options.resolution,
+            options.dilation,
+        );
+        dilate(
+            &mut normals,
+            3,
+            &mut normals_covered,
+            options.resolution,
+            options.dilation,
+        );
+
+        let mut image = ImageNode::new(
+            HXAImageType::HXA_IT_2D_IMAGE,
+            [options.resolution[0], options.resolution[1], 1],
+        );
+        image.image_stack.push(Layer::new(
+            convention::DISPLACEMENT,
+            1,
+            LayerData::Float(displacement),
+        ));
+        image.image_stack.push(Layer::new(
+            convention::LAYER_NORMALS,
+            3,
+            LayerData::Float(normals),
+        ));
+
+        Ok(image)
+    }
+}
+
+impl File {
+    /// Bake ambient occlusion of a geometry node into a new image node and return its index.
+    ///
+    /// The image is named after the node with an `_ambient_occlusion` suffix.
+    pub fn bake_ambient_occlusion(&mut self, node: usize, options: &BakeOptions) -> Result<usize> {
+        let image = self
+            .unquantized_geometry(node)?
+            .bake_ambient_occlusion(options)?;
+        self.push_baked_image(node, image, convention::AMBIENT_OCCLUSION)
+    }
+
+    /// Bake the detail of a high resolution geometry node onto a low resolution one, into a new
+    /// image node, and return its index. See [`GeometryNode::bake_displacement`].
+    ///
+    /// The image is named after the low node with a `_displacement` suffix.
+    pub fn bake_displacement(
+        &mut self,
+        low: usize,
+        high: usize,
+        options: &BakeOptions,
+    ) -> Result<usize> {
+        let image = self
+            .unquantized_geometry(low)?
+            .bake_displacement(self.unquantized_geometry(high)?, options)?;
+        self.push_baked_image(low, image, convention::DISPLACEMENT)
+    }
+
+    pub(crate) fn push_baked_image(
+        &mut self,
+        node: usize,
+        image: ImageNode,
+        suffix: &str,
+    ) -> Result<usize> {
+        let mut image = Node::from(image);
+        if let Some(name) = self.node(node)?.name() {
+            image.set_meta(Meta::new(
+                convention::NAME,
+                MetaValue::Text(format!("{}_{}", name, suffix)),
+            ));
+        }
+        self.nodes.push(image);
+
+        Ok(self.nodes.len() - 1)
+    }
+}
+
+/// The triangles of a geometry node, ready for rasterizing and ray casting.
+pub(crate) struct Surface {
+    /// The face of every triangle.
+    #[cfg(feature = "bvh")]
+    faces: Vec<usize>,
+    /// The corners of every triangle.
+    pub(crate) corners: Vec<[usize; 3]>,
+    pub(crate) points: Vec<[Vec3; 3]>,
+    /// The unit normal of every triangle, following the winding of its polygon.
+    pub(crate) normals: Vec<Vec3>,
+    /// Only built for surfaces that are queried.
+    #[cfg(feature = "bvh")]
+    bvh: Option<Bvh>,
+}
+
+/// A ray hit or closest point on a triangle of a [`Surface`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SurfaceHit {
+    pub(crate) triangle: usize,
+    pub(crate) distance: f64,
+    pub(crate) barycentric: [f64; 3],
+}
+
+impl Surface {
+    pub(crate) fn new(node: &GeometryNode) -> Result<Self> {
+        let positions = node.positions()?;
+        let corner_vertices = node.corner_vertices()?;
+        let triangles = node.corner_triangles()?;
+        let corners: Vec<[usize; 3]> = triangles.iter().map(|&(_, corners)| corners).collect();
+        let points: Vec<[Vec3; 3]> = corners
+            .iter()
+            .map(|corners| corners.map(|corner| positions[corner_vertices[corner]]))
+            .collect();
+        let normals = points
+            .iter()
+            .map(|&[a, b, c]| normalize(triangle_normal(a, b, c)))
+            .collect();
+
+        Ok(Self {
+            #[cfg(feature = "bvh")]
+            faces: triangles.iter().map(|&(face, _)| face).collect(),
+            corners,
+            points,
+            normals,
+            #[cfg(feature = "bvh")]
+            bvh: None,
+        })
+    }
+
+    /// A surface to cast rays against with [`intersect`](Self::intersect) or find points on with
+    /// [`closest_point`](Self::closest_point), which builds a [`Bvh`](crate::bvh::Bvh) over the
+    /// triangles with the `bvh` feature.
+    pub(crate) fn for_queries(node: &GeometryNode) -> Result<Self> {
+        let surface = Self::new(node)?;
+        #[cfg(feature = "bvh")]
+        let surface = {
+            let triangles = (0..surface.corners.len()).map(|triangle| {
+                (
+                    surface.faces[triangle],
+                    surface.corners[triangle],
+                    surface.points[triangle],
+                )
+            });
+            Self {
+                bvh: Some(Bvh::from_triangles(triangles)),
+                ..surface
+            }
+        };
+
+        Ok(surface)
+    }
+
+    /// A 3 component vector for every corner of every triangle, read from the first 3
+    /// components of a corner or vertex layer of the node. Without such a layer, smooth normals
+    /// are computed from the triangles.
+    ///
+    /// Fails with [`Error::LayerComponents`] if the layer has less than 3 components.
+    pub(crate) fn corner_vectors(&self, node: &GeometryNode, name: &str) -> Result<Vec<[Vec3; 3]>> {
+        let layer = corner_or_vertex_layer(node, name)?;
+        if let Some((layer, _)) = layer.filter(|(layer, _)| layer.components < 3) {
+            return Err(Error::LayerComponents {
+                name: name.to_string(),
+                expected: 3,
+                actual: layer.components,
+            });
+        }
+        let values: Vec<Vec3> = if let Some((layer, true)) = layer {
+            (0..layer.len())
+                .map(|corner| std::array::from_fn(|i| layer.get(corner, i)))
+                .collect()
+        } else if let Some((layer, false)) = layer {
+            node.corner_vertices()?
+                .into_iter()
+                .map(|vertex| std::array::from_fn(|i| layer.get(vertex, i)))
+                .collect()
+        } else {
+            let corner_vertices = node.corner_vertices()?;
+            let mut normals = vec![[0.0; 3]; node.vertex_count()];
+            for (corners, &[a, b, c]) in self.corners.iter().zip(&self.points) {
+                let normal = triangle_normal(a, b, c);
+                for corner in corners {
+                    let vertex = corner_vertices[*corner];
+                    normals[vertex] = add(normals[vertex], normal);
+                }
+            }
+            corner_vertices
+                .into_iter()
+                .map(|vertex| normals[vertex])
+                .collect()
+        };
+
+        Ok(self
+            .corners
+            .iter()
+            .map(|corners| corners.map(|corner| values[corner]))
+            .collect())
+    }
+
+    /// One component of a corner or vertex layer of the node for every corner of every
+    /// triangle, or `None` if the node has no such layer with enough components.
+    pub(crate) fn corner_component(
+        &self,
+        node: &GeometryNode,
+        name: &str,
+        component: usize,
+    ) -> Result<Option<Vec<[f64; 3]>>> {
+        let values: Vec<f64> = match corner_or_vertex_layer(node, name)? {
+            Some((layer, _)) if layer.components as usize <= component => return Ok(None),
+            Some((layer, true)) => (0..layer.len())
+                .map(|corner| layer.get(corner, component))
+                .collect(),
+            Some((layer, false)) => node
+                .corner_vertices()?
+                .into_iter()
+                .map(|vertex| layer.get(vertex, component))
+                .collect(),
+            None => return Ok(None),
+        };
+
+        Ok(Some(
+            self.corners
+                .iter()
+                .map(|corners| corners.map(|corner| values[corner]))
+                .collect(),
+        ))
+    }
+
+    /// The point at barycentric coordinates of a triangle.
+    pub(crate) fn interpolate(&self, triangle: usize, barycentric: [f64; 3]) -> Vec3 {
+        let [a, b, c] = self.points[triangle];
+        add(
+            add(scale(a, barycentric[0]), scale(b, barycentric[1])),
+            scale(c, barycentric[2]),
+        )
+    }
+
+    /// The closest hit of a ray against both sides of the triangles, up to `max_distance`.
+    pub(crate) fn intersect(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f64,
+    ) -> Option<SurfaceHit> {
+        #[cfg(feature = "bvh")]
+        if let Some(bvh) = &self.bvh {
+            return bvh
+                .intersect_ray(origin, direction, max_distance)
+                .map(|hit| SurfaceHit {
+                    triangle: hit.triangle,
+                    distance: hit.distance,
+                    barycentric: hit.barycentric,
+                });
+        }
+
+        let mut closest: Option<SurfaceHit> = None;
+        for (triangle, &points) in self.points.iter().enumerate() {
+            let limit = closest.map_or(max_distance, |hit| hit.distance);
+            let Some((distance, u, v)) = intersect_triangle(origin, direction, points) else {
+                continue;
+            };
+            if distance > 0.0 && distance < limit {
+                closest = Some(SurfaceHit {
+                    triangle,
+                    distance,
+                    barycentric: [1.0 - u - v, u, v],
+                });
+            }
+        }
+        closest
+    }
+
+    /// The closest point on any triangle to a point.
+    pub(crate) fn closest_point(&self, point: Vec3) -> Option<SurfaceHit> {
+        #[cfg(feature = "bvh")]
+        if let Some(bvh) = &self.bvh {
+            return bvh
+                .closest_point(point, f64::INFINITY)
+                .map(|hit| SurfaceHit {
+                    triangle: hit.triangle,
+                    distance: hit.distance,
+                    barycentric: hit.barycentric,
+                });
+        }
+
+        let mut closest: Option<SurfaceHit> = None;
+        for (triangle, &points) in self.points.iter().enumerate() {
+            let (position, barycentric) = closest_on_triangle(point, points);
+            let distance = length(sub(position, point));
+            if closest.is_none_or(|hit| distance < hit.distance) {
+                closest = Some(SurfaceHit {
+                    triangle,
+                    distance,
+                    barycentric,
+                });
+            }
+        }
+        closest
+    }
+
+    /// Call `texel` with the triangle and barycentric coordinates of every texel center covered
+    /// by the triangles in UV space, along with the index of the texel.
+    pub(crate) fn rasterize(
+        &self,
+        uvs: &[[f64; 2]],
+        resolution: [u32; 2],
+        mut texel: impl FnMut(usize, [f64; 3], usize),
+    ) {
+        let [width, height] = resolution.map(|r| r as usize);
+        for (triangle, corners) in self.corners.iter().enumerate() {
+            let [a, b, c] = corners.map(|corner| {
+                [
+                    uvs[corner][0] * width as f64,
+                    uvs[corner][1] * height as f64,
+                ]
+            });
+            let area = edge(a, b, c);
+            if area == 0.0 {
+                continue;
+            }
+            let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as usize;
+            let max_x = (a[0].max(b[0]).max(c[0]).ceil() as usize).min(width);
+            let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as usize;
+            let max_y = (a[1].max(b[1]).max(c[1]).ceil() as usize).min(height);
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    let p = [x as f64 + 0.5, y as f64 + 0.5];
+                    let barycentric = [
+                        edge(b, c, p) / area,
+                        edge(c, a, p) / area,
+                        edge(a, b, p) / area,
+                    ];
+                    if barycentric.iter().all(|&w| w >= 0.0) {
+                        texel(triangle, barycentric, y * width + x);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The sum of three vectors weighted by barycentric coordinates.
+fn weighted(values: &[Vec3; 3], barycentric: [f64; 3]) -> Vec3 {
+    values
+        .iter()
+        .zip(barycentric)
+        .fold([0.0; 3], |sum, (&value, weight)| {
+            add(sum, scale(value, weight))
+        })
+}
+
+/// The directions of increasing U and V over a triangle.
+fn uv_tangents(points: [Vec3; 3], uvs: [[f64; 2]; 3]) -> (Vec3, Vec3) {
+    let (e1, e2) = (sub(points[1], points[0]), sub(points[2], points[0]));
+    let (du1, dv1) = (uvs[1][0] - uvs[0][0], uvs[1][1] - uvs[0][1]);
+    let (du2, dv2) = (uvs[2][0] - uvs[0][0], uvs[2][1] - uvs[0][1]);
+    let determinant = du1 * dv2 - du2 * dv1;
+    if determinant == 0.0 {
+        return tangent_frame(normalize(cross(e1, e2)));
+    }
+    (
+        scale(sub(scale(e1, dv2), scale(e2, dv1)), 1.0 / determinant),
+        scale(sub(scale(e2, du1), scale(e1, du2)), 1.0 / determinant),
+    )
+}
+
+/// Twice the signed area of a 2D triangle.
+fn edge(a: [f64; 2], b: [f64; 2], p: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Fill uncovered texels with the average of their covered neighbours, one ring at a time.
+pub(crate) fn dilate(
+    values: &mut [f32],
+    components: usize,
+    covered: &mut [bool],
+    resolution: [u32; 2],
+    rings: u32,
+) {
+    let [width, height] = resolution.map(|r| r as usize);
+    for _ in 0..rings {
+        let mut filled = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if covered[y * width + x] {
+                    continue;
+                }
+                let mut sum = vec![0.0f32; components];
+                let mut count = 0;
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                        continue;
+                    }
+                    let neighbour = ny as usize * width + nx as usize;
+                    if covered[neighbour] {
+                        for (sum, value) in sum
+                            .iter_mut()
+                            .zip(&values[neighbour * components..][..components])
+                        {
+                            *sum += value;
+                        }
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    let count = count as f32;
+                    filled.push((y * width + x, sum.into_iter().map(move |s| s / count)));
+                }
+            }
+        }
+        for (texel, value) in filled {
+            for (target, value) in values[texel * components..][..components]
+                .iter_mut()
+                .zip(value)
+            {
+                *target = value;
+            }
+            covered[texel] = true;
+        }
+    }
+}
+
+/// Two unit vectors perpendicular to a normal and to each other.
+fn tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let helper = if normal[0].abs() < 0.9 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    };
+    let tangent = normalize(cross(helper, normal));
+    (tangent, cross(normal, tangent))
+}
+
+/// A cosine weighted direction around +z from a Hammersley sequence, rotated around z by a
+/// fraction of a turn so neighbouring texels do not share their sample pattern.
+fn cosine_direction(sample: u32, samples: u32, rotation: f64) -> Vec3 {
+    let u = (sample as f64 + 0.5) / samples as f64;
+    let angle = 2.0 * PI * (sample.reverse_bits() as f64 / 2f64.powi(32) + rotation);
+    let radius = u.sqrt();
+    let direction = [radius * angle.cos(), radius * angle.sin(), (1.0 - u).sqrt()];
+    scale(direction, 1.0 / length(direction))
+}
+
+/// A fraction in [0, 1) hashed from an index.
+fn hash(index: usize) -> f64 {
+    ((index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 11) as f64 / 2f64.powi(53)
+}
+
+/// The corner layer called `name`, or else the vertex layer, and whether it is the corner layer.
+///
+/// Fails with [`Error::LayerLength`] if the layer does not have a value for every corner or
+/// vertex.
+fn corner_or_vertex_layer<'a>(
+    node: &'a GeometryNode,
+    name: &str,
+) -> Result<Option<(&'a Layer, bool)>> {
+    let (layer, expected, per_corner) = match node.corner_stack.find(name) {
+        Some(layer) => (layer, node.corner_count(), true),
+        None => match node.vertex_stack.find(name) {
+            Some(layer) => (layer, node.vertex_count(), false),
+            None => return Ok(None),
+        },
+    };
+    if layer.len() != expected {
+        return Err(Error::LayerLength {
+            name: name.to_string(),
+            expected,
+            actual: layer.len(),
+        });
+    }
+
+    Ok(Some((layer, per_corner)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::cube;
+    use crate::LightmapOptions;
+
+    /// A unit floor facing up under a large ceiling facing down.
+    fn covered_floor() -> GeometryNode {
+        let mut node = GeometryNode::from_polygons(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [-50.0, -50.0, 0.1],
+                [50.0, -50.0, 0.1],
+                [50.0, 50.0, 0.1],
+                [-50.0, 50.0, 0.1],
+            ],
+            &[vec![0, 1, 2, 3], vec![7, 6, 5, 4]],
+        );
+        // Only the floor is mapped, the ceiling collapses to a point.
+        let mut uvs = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        uvs.extend([[0.0, 0.0]; 4]);
+        node.set_corner_uvs(1, &uvs).unwrap();
+        node
+    }
+
+    #[test]
+    fn open_surfaces_are_unoccluded() {
+        let mut file = File::new();
+        let mut node = cube();
+        node.generate_lightmap_uvs(&LightmapOptions::new(32))
+            .unwrap();
+        let mut node = Node::from(node);
+        node.set_name("box");
+        file.nodes.push(node);
+
+        let index = file
+            .bake_ambient_occlusion(0, &BakeOptions::new(32))
+            .unwrap();
+        let image = file.nodes[index].image().unwrap();
+        assert_eq!(file.nodes[index].name(), Some("box_ambient_occlusion"));
+        assert_eq!(image.resolution, [32, 32, 1]);
+        let layer = image
+            .image_stack
+            .get(convention::AMBIENT_OCCLUSION)
+            .unwrap();
+        assert_eq!(layer.len(), image.pixel_count());
+        assert!((0..layer.len()).all(|texel| layer.get(texel, 0) == 1.0));
+    }
+
+    #[test]
+    fn ceiling_occludes_floor() {
+        let node = covered_floor();
+        let options = BakeOptions {
+            samples: 32,
+            ..BakeOptions::new(8)
+        };
+        let image = node.bake_ambient_occlusion(&options).unwrap();
+        let layer = image
+            .image_stack
+            .get(convention::AMBIENT_OCCLUSION)
+            .unwrap();
+        assert!((0..layer.len()).all(|texel| layer.get(texel, 0) < 0.1));
+
+        // A short ray length ignores the ceiling.
+        let options = BakeOptions {
+            max_distance: 0.05,
+            ..options
+        };
+        let image = node.bake_ambient_occlusion(&options).unwrap();
+        let layer = image
+            .image_stack
+            .get(convention::AMBIENT_OCCLUSION)
+            .unwrap();
+        assert!((0..layer.len()).all(|texel| layer.get(texel, 0) == 1.0));
+    }
+
+    /// A unit quad with planar UVs, displaced to `height(x)`.
+    fn plane(height: impl Fn(f32) -> f32) -> GeometryNode {
+        let corners = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let positions: Vec<[f32; 3]> = corners.iter().map(|&[x, y]| [x, y, height(x)]).collect();
+        let mut node = GeometryNode::from_polygons(&positions, &[[0, 1, 2, 3]]);
+        let uvs = corners.map(|[u, v]| [u as f64, v as f64]);
+        node.set_corner_uvs(1, &uvs).unwrap();
+        node
+    }
+
+    #[test]
+    fn displacement_and_normals_from_high_surface() {
+        let mut file = File::new();
+        file.nodes.push(plane(|_| 0.0).into());
+        file.nodes.push(plane(|x| 0.02 + 0.1 * x).into());
+        let options = BakeOptions {
+            cage_distance: 0.5,
+            ..BakeOptions::new(4)
+        };
+        let index = file.bake_displacement(0, 1, &options).unwrap();
+        let image = file.nodes[index].image().unwrap();
+
+        let displacement = image.image_stack.get(convention::DISPLACEMENT).unwrap();
+        let normals = image.image_stack.get(convention::LAYER_NORMALS).unwrap();
+        let expected_normal = normalize([-0.1, 0.0, 1.0]);
+        for texel in 0..16 {
+            let x = (texel % 4) as f64 / 4.0 + 0.125;
+            assert!((displacement.get(texel, 0) - (0.02 + 0.1 * x)).abs() < 1e-6);
+            for (axis, &expected) in expected_normal.iter().enumerate() {
+                assert!((normals.get(texel, axis) - expected).abs() < 1e-6);
+            }
+        }
+
+        // Surfaces out of reach of the cage are not baked.
+        let options = BakeOptions {
+            cage_distance: 0.01,
+            ..options
+        };
+        let image = file
+            .geometry(0)
+            .unwrap()
+            .bake_displacement(file.geometry(1).unwrap(), &options)
+            .unwrap();
+        let normals = image.image_stack.get(convention::LAYER_NORMALS).unwrap();
+        assert_eq!(
+            image
+                .image_stack
+                .get(convention::DISPLACEMENT)
+                .unwrap()
+                .data,
+            LayerData::Float(vec![0.0; 16])
+        );
+        assert_eq!(normals.get(5, 2), 1.0);
+    }
+
+    #[test]
+    fn mirrored_shells_keep_their_bitangent() {
+        // The low quad maps U along -X, so its tangent frame is left handed.
+        let mut low = plane(|_| 0.0);
+        let mirrored = [[1.0, 0.0], [0.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+        low.set_corner_uvs(1, &mirrored).unwrap();
+        // The high surface rises along Y, tilting its normal towards -Y.
+        let positions = [
+            [0.0, 0.0, 0.02],
+            [1.0, 0.0, 0.02],
+            [1.0, 1.0, 0.12],
+            [0.0, 1.0, 0.12],
+        ];
+        let high = GeometryNode::from_polygons(&positions, &[[0, 1, 2, 3]]);
+        let options = BakeOptions {
+            cage_distance: 0.5,
+            ..BakeOptions::new(4)
+        };
+        let green = |low: &GeometryNode| {
+            let image = low.bake_displacement(&high, &options).unwrap();
+            let normals = image.image_stack.get(convention::LAYER_NORMALS).unwrap();
+            (0..16)
+                .map(|texel| normals.get(texel, 1))
+                .collect::<Vec<f64>>()
+        };
+        let expected = normalize([0.0, -0.1, 1.0])[1];
+        let assert_green = |values: Vec<f64>| {
+            for value in values {
+                assert!((value - expected).abs() < 1e-6, "{}", value);
+            }
+        };
+
+        assert_green(green(&low));
+        let tangents = [-1.0f32, 0.0, 0.0, -1.0].repeat(4);
+        low.corner_stack.push(Layer::new(
+            convention::LAYER_TANGENT,
+            4,
+            LayerData::Float(tangents),
+        ));
+        assert_green(green(&low));
+
+        // The binormal layer takes precedence over the sign.
+        low.corner_stack
+            .get_mut(convention::LAYER_TANGENT)
+            .unwrap()
+            .data = LayerData::Float([-1.0f32, 0.0, 0.0, 1.0].repeat(4));
+        low.vertex_stack.push(Layer::new(
+            convention::LAYER_BINORMAL,
+            3,
+            LayerData::Float([0.0f32, 1.0, 0.0].repeat(4)),
+        ));
+        assert_green(green(&low));
+    }
+
+    #[test]
+    fn malformed_vector_layers_are_rejected() {
+        let high = plane(|_| 0.02);
+        let options = BakeOptions {
+            cage_distance: 0.5,
+            ..BakeOptions::new(4)
+        };
+        let mut low = plane(|_| 0.0);
+        low.vertex_stack.push(Layer::new(
+            convention::LAYER_NORMALS,
+            2,
+            LayerData::Float(vec![0.0; 8]),
+        ));
+        assert_eq!(
+            low.bake_displacement(&high, &options),
+            Err(Error::LayerComponents {
+                name: convention::LAYER_NORMALS.to_string(),
+                expected: 3,
+                actual: 2
+            })
+        );
+
+        let mut low = plane(|_| 0.0);
+        low.corner_stack.push(Layer::new(
+            convention::LAYER_TANGENT,
+            4,
+            LayerData::Float([1.0f32, 0.0, 0.0, 1.0].repeat(3)),
+        ));
+        assert_eq!(
+            low.bake_displacement(&high, &options),
+            Err(Error::LayerLength {
+                name: convention::LAYER_TANGENT.to_string(),
+                expected: 4,
+                actual: 3
+            })
+        );
+    }
+
+    #[test]
+    fn dilation_fills_neighbours() {
+        let mut values = vec![0.0, 4.0, 0.0, 0.0];
+        let mut covered = vec![false, true, false, false];
+        dilate(&mut values, 1, &mut covered, [4, 1], 1);
+        assert_eq!(values, [4.0, 4.0, 4.0, 0.0]);
+        assert_eq!(covered, [true, true, true, false]);
+    }
+}
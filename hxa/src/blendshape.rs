@@ -0,0 +1,278 @@
+//! Blend shapes, also known as morph targets.
+//!
+//! Shapes are stored as 3 component vertex layers. The first absolute shape is named
+//! `blendshape`, followed by `blendshape1`, `blendshape2` and so on, and additive shapes are
+//! named the same way starting from `addblendshape`. An absolute shape holds the target position
+//! of every vertex, while an additive shape holds an offset from the base positions.
+
+use crate::math::{add, cross, dot, normalize, polygon_normal, scale, sub, Vec3};
+use crate::{convention, Error, GeometryNode, Layer, LayerData, Result};
+
+/// How the values of a blend shape are applied to the base positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendShapeKind {
+    /// The values are target positions, blended towards by the weight.
+    Absolute,
+    /// The values are offsets, scaled by the weight and added.
+    Additive,
+}
+
+impl BlendShapeKind {
+    /// The name of the vertex layer holding the shape with the given index.
+    pub fn layer_name(self, index: usize) -> String {
+        let base = match self {
+            BlendShapeKind::Absolute => convention::LAYER_BLENDSHAPE,
+            BlendShapeKind::Additive => convention::LAYER_ADD_BLENDSHAPE,
+        };
+        match index {
+            0 => base.to_string(),
+            index => format!("{}{}", base, index),
+        }
+    }
+}
+
+/// A blend shape read from the vertex stack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlendShape {
+    pub kind: BlendShapeKind,
+    /// The index of the shape among the shapes of the same kind.
+    pub index: usize,
+    /// The target position or offset of every vertex.
+    pub values: Vec<[f64; 3]>,
+}
+
+impl GeometryNode {
+    /// All blend shapes of the node, the absolute shapes first and then the additive ones.
+    ///
+    /// Shapes of each kind are read from index 0 up to the first missing layer.
+    pub fn blendshapes(&self) -> Result<Vec<BlendShape>> {
+        let mut shapes = Vec::new();
+        for kind in [BlendShapeKind::Absolute, BlendShapeKind::Additive] {
+            for index in 0.. {
+                let Some(layer) = self.vertex_stack.find(&kind.layer_name(index)) else {
+                    break;
+                };
+                shapes.push(BlendShape {
+                    kind,
+                    index,
+                    values: layer.to_vec3()?,
+                });
+            }
+        }
+
+        Ok(shapes)
+    }
+
+    /// Write a blend shape, replacing the layer of the same kind and index if there is one.
+    ///
+    /// The shape is stored with the same type as the vertex positions.
+    pub fn set_blendshape(
+        &mut self,
+        kind: BlendShapeKind,
+        index: usize,
+        values: &[[f64; 3]],
+    ) -> Result<()> {
+        let name = kind.layer_name(index);
+        if values.len() != self.vertex_count() {
+            return Err(Error::LayerLength {
+                name,
+                expected: self.vertex_count(),
+                actual: values.len(),
+            });
+        }
+        let values = values.iter().flatten().copied();
+        let data = if matches!(self.vertex_layer()?.data, LayerData::Double(_)) {
+            LayerData::Double(values.collect())
+        } else {
+            LayerData::Float(values.map(|v| v as f32).collect())
+        };
+        self.vertex_stack.insert(Layer::new(name, 3, data));
+
+        Ok(())
+    }
+
+    /// The vertex positions and normals with the blend shapes applied.
+    ///
+    /// `weights` holds one weight per shape in the order of [`GeometryNode::blendshapes`].
+    /// Shapes without a weight are not applied. The normals are the smooth normals of the deformed
+    /// faces, weighted by face area. When the vertex stack has a `normal` layer, its normals are
+    /// deformed instead, each rotated as much as the smooth normal of its vertex. The layer is
+    /// never written, and a `normal` layer in the corner stack, as used for hard edges, is not
+    /// read.
+    pub fn evaluate_blendshapes(&self, weights: &[f64]) -> Result<(Vec<Vec3>, Vec<Vec3>)> {
+        let base = self.positions()?;
+        let mut positions = base.clone();
+        for (shape, &weight) in self.blendshapes()?.iter().zip(weights) {
+            if shape.values.len() != base.len() {
+                return Err(Error::LayerLength {
+                    name: shape.kind.layer_name(shape.index),
+                    expected: base.len(),
+                    actual: shape.values.len(),
+                });
+            }
+            if weight == 0.0 {
+                continue;
+            }
+            for ((position, &base), &value) in positions.iter_mut().zip(&base).zip(&shape.values) {
+                let offset = match shape.kind {
+                    BlendShapeKind::Absolute => sub(value, base),
+                    BlendShapeKind::Additive => value,
+                };
+                *position = add(*position, scale(offset, weight));
+            }
+        }
+
+        let normals = self.smooth_normals(&positions)?;
+        let Some(stored) = self.vertex_stack.find(convention::LAYER_NORMALS) else {
+            return Ok((positions, normals));
+        };
+        stored.expect_components(3)?;
+        if stored.len() != base.len() {
+            return Err(Error::LayerLength {
+                name: stored.name.clone(),
+                expected: base.len(),
+                actual: stored.len(),
+            });
+        }
+        let normals = self
+            .smooth_normals(&base)?
+            .into_iter()
+            .zip(normals)
+            .enumerate()
+            .map(|(vertex, (from, to))| {
+                let normal = [0, 1, 2].map(|component| stored.get(vertex, component));
+                normalize(rotate_between(normal, from, to))
+            })
+            .collect();
+
+        Ok((positions, normals))
+    }
+
+    /// The area weighted normals of the faces around every vertex.
+    fn smooth_normals(&self, positions: &[Vec3]) -> Result<Vec<Vec3>> {
+        let mut normals = vec![[0.0; 3]; positions.len()];
+        for polygon in self.polygons()? {
+            let points: Vec<Vec3> = polygon.iter().map(|&v| positions[v]).collect();
+            let normal = polygon_normal(&points);
+            for &vertex in &polygon {
+                normals[vertex] = add(normals[vertex], normal);
+            }
+        }
+
+        Ok(normals.into_iter().map(normalize).collect())
+    }
+}
+
+/// Rotate `v` by the rotation taking the unit vector `from` to the unit vector `to`. Vectors
+/// are left as is when either direction is unknown, and flipped when they are opposite.
+fn rotate_between(v: Vec3, from: Vec3, to: Vec3) -> Vec3 {
+    if from == [0.0; 3] || to == [0.0; 3] {
+        return v;
+    }
+    let cos = dot(from, to);
+    if cos <= -1.0 + 1e-12 {
+        return scale(v, -1.0);
+    }
+    let axis = cross(from, to);
+    add(
+        add(scale(v, cos), cross(axis, v)),
+        scale(axis, dot(axis, v) / (1.0 + cos)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+    use crate::HXALayerDataType;
+
+    #[test]
+    fn layer_names() {
+        assert_eq!(BlendShapeKind::Absolute.layer_name(0), "blendshape");
+        assert_eq!(BlendShapeKind::Absolute.layer_name(2), "blendshape2");
+        assert_eq!(BlendShapeKind::Additive.layer_name(1), "addblendshape1");
+    }
+
+    #[test]
+    fn shapes_match_the_position_type() {
+        let mut node = quad_and_triangle();
+        let offsets = vec![[0.1, 0.0, 0.0]; 5];
+        node.set_blendshape(BlendShapeKind::Additive, 0, &offsets)
+            .unwrap();
+        let layer = node.vertex_stack.get("addblendshape").unwrap();
+        assert_eq!(layer.data_type(), HXALayerDataType::HXA_LDT_FLOAT);
+
+        let double = node
+            .vertex_layer()
+            .unwrap()
+            .convert_to(HXALayerDataType::HXA_LDT_DOUBLE);
+        node.vertex_stack.layers[0] = double;
+        node.set_blendshape(BlendShapeKind::Additive, 0, &offsets)
+            .unwrap();
+        assert_eq!(node.blendshapes().unwrap()[0].values, offsets);
+    }
+
+    #[test]
+    fn absolute_and_additive_shapes() {
+        let mut node = quad_and_triangle();
+        let base = node.positions().unwrap();
+        let lifted: Vec<[f64; 3]> = base.iter().map(|&[x, y, z]| [x, y, z + 2.0]).collect();
+        let offsets = vec![[1.0, 0.0, 0.0]; base.len()];
+        node.set_blendshape(BlendShapeKind::Additive, 0, &offsets)
+            .unwrap();
+        node.set_blendshape(BlendShapeKind::Absolute, 0, &lifted)
+            .unwrap();
+        // Not read, as there is no shape with index 1 before it.
+        node.set_blendshape(BlendShapeKind::Absolute, 2, &base)
+            .unwrap();
+        assert!(node
+            .set_blendshape(BlendShapeKind::Absolute, 1, &base[1..])
+            .is_err());
+
+        let shapes = node.blendshapes().unwrap();
+        assert_eq!(shapes.len(), 2);
+        assert_eq!(shapes[0].kind, BlendShapeKind::Absolute);
+        assert_eq!(shapes[1].kind, BlendShapeKind::Additive);
+
+        let (positions, normals) = node.evaluate_blendshapes(&[0.5, 2.0]).unwrap();
+        for (position, base) in positions.iter().zip(&base) {
+            assert_eq!(*position, [base[0] + 2.0, base[1], base[2] + 1.0]);
+        }
+        assert!(normals.iter().all(|n| n == &[0.0, 0.0, 1.0]));
+
+        let (positions, _) = node.evaluate_blendshapes(&[]).unwrap();
+        assert_eq!(positions, base);
+    }
+
+    #[test]
+    fn stored_normals_are_deformed() {
+        let mut node = quad_and_triangle();
+        // Turn the flat base a quarter turn around the x axis.
+        let turned: Vec<[f64; 3]> = node
+            .positions()
+            .unwrap()
+            .iter()
+            .map(|&[x, y, z]| [x, -z, y])
+            .collect();
+        node.set_blendshape(BlendShapeKind::Absolute, 0, &turned)
+            .unwrap();
+        let tilted = [0.0, 0.6, 0.8];
+        let stored = Layer::new(
+            convention::LAYER_NORMALS,
+            3,
+            LayerData::Float(tilted.map(|v| v as f32).repeat(5)),
+        );
+        node.vertex_stack.push(stored.clone());
+
+        let (_, normals) = node.evaluate_blendshapes(&[1.0]).unwrap();
+        for normal in normals {
+            for (value, expected) in normal.into_iter().zip([0.0, -0.8, 0.6]) {
+                assert!((value - expected).abs() < 1e-6);
+            }
+        }
+        assert_eq!(
+            node.vertex_stack.get(convention::LAYER_NORMALS),
+            Ok(&stored)
+        );
+    }
+}
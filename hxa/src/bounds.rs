@@ -0,0 +1,432 @@
+//! Bounding volumes and surface statistics of geometry nodes.
+
+use std::collections::HashMap;
+
+use crate::geometry::next_corner;
+use crate::math::{add, cross, dot, length, polygon_normal, scale, sub, Vec3};
+use crate::{convention, GeometryNode, Meta, MetaValue, Node, Result};
+
+/// An axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl Aabb {
+    /// The smallest box containing all points, or `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = [f64; 3]>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut aabb = Self {
+            min: first,
+            max: first,
+        };
+        for point in points {
+            aabb.extend(point);
+        }
+        Some(aabb)
+    }
+
+    /// Grow the box to contain a point.
+    pub fn extend(&mut self, point: [f64; 3]) {
+        self.min = std::array::from_fn(|axis| self.min[axis].min(point[axis]));
+        self.max = std::array::from_fn(|axis| self.max[axis].max(point[axis]));
+    }
+
+    pub fn center(&self) -> [f64; 3] {
+        scale(add(self.min, self.max), 0.5)
+    }
+
+    pub fn size(&self) -> [f64; 3] {
+        sub(self.max, self.min)
+    }
+
+    /// Check if the boxes share any point, including touching faces.
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: [f64; 3],
+    pub radius: f64,
+}
+
+/// A bounding box rotated to follow the shape of the geometry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obb {
+    pub center: [f64; 3],
+    /// Orthonormal axes of the box, from the longest to the shortest.
+    pub axes: [[f64; 3]; 3],
+    /// Half the size of the box along each axis.
+    pub half_extents: [f64; 3],
+}
+
+impl GeometryNode {
+    /// The axis aligned bounding box of the vertices, or `None` if the node has none.
+    pub fn aabb(&self) -> Result<Option<Aabb>> {
+        Ok(Aabb::from_points(self.positions()?))
+    }
+
+    /// A bounding sphere of the vertices, or `None` if the node has none.
+    ///
+    /// Uses Ritter's algorithm, so the sphere is close to, but not always, the smallest one.
+    pub fn bounding_sphere(&self) -> Result<Option<BoundingSphere>> {
+        let positions = self.positions()?;
+        let Some(&first) = positions.first() else {
+            return Ok(None);
+        };
+        let farthest = |from: Vec3| {
+            positions
+                .iter()
+                .copied()
+                .max_by(|&a, &b| length(sub(a, from)).total_cmp(&length(sub(b, from))))
+                .unwrap_or(from)
+        };
+        let a = farthest(first);
+        let b = farthest(a);
+        let mut center = scale(add(a, b), 0.5);
+        let mut radius = length(sub(b, a)) / 2.0;
+        for &point in &positions {
+            let distance = length(sub(point, center));
+            if distance > radius {
+                let grow = (distance - radius) / 2.0;
+                radius += grow;
+                center = add(center, scale(sub(point, center), grow / distance));
+            }
+        }
+
+        Ok(Some(BoundingSphere { center, radius }))
+    }
+
+    /// A bounding box aligned to the principal axes of the vertices, or `None` if the node has
+    /// none.
+    pub fn oriented_bounding_box(&self) -> Result<Option<Obb>> {
+        let positions = self.positions()?;
+        if positions.is_empty() {
+            return Ok(None);
+        }
+        let mean = scale(
+            positions.iter().fold([0.0; 3], |sum, &p| add(sum, p)),
+            1.0 / positions.len() as f64,
+        );
+        let mut covariance = [[0.0; 3]; 3];
+        for &point in &positions {
+            let d = sub(point, mean);
+            for i in 0..3 {
+                for j in 0..3 {
+                    covariance[i][j] += d[i] * d[j];
+                }
+            }
+        }
+        let (values, vectors) = symmetric_eigen(covariance);
+        let mut order = [0, 1, 2];
+        order.sort_by(|&a, &b| values[b].total_cmp(&values[a]));
+        let mut axes = order.map(|i| [vectors[0][i], vectors[1][i], vectors[2][i]]);
+        // Keep the axes right handed.
+        axes[2] = cross(axes[0], axes[1]);
+
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for &point in &positions {
+            for (axis, direction) in axes.iter().enumerate() {
+                let t = dot(point, *direction);
+                min[axis] = min[axis].min(t);
+                max[axis] = max[axis].max(t);
+            }
+        }
+        let center = (0..3).fold([0.0; 3], |center, axis| {
+            add(center, scale(axes[axis], (min[axis] + max[axis]) / 2.0))
+        });
+
+        Ok(Some(Obb {
+            center,
+            axes,
+            half_extents: [0, 1, 2].map(|axis| (max[axis] - min[axis]) / 2.0),
+        }))
+    }
+
+    /// The total area of all faces.
+    pub fn surface_area(&self) -> Result<f64> {
+        let positions = self.positions()?;
+        Ok(self
+            .polygons()?
+            .iter()
+            .map(|polygon| {
+                let points: Vec<Vec3> = polygon.iter().map(|&v| positions[v]).collect();
+                length(polygon_normal(&points)) / 2.0
+            })
+            .sum())
+    }
+
+    /// Check if every edge is shared by exactly two faces with opposite winding.
+    pub fn is_closed(&self) -> Result<bool> {
+        let corner_vertices = self.corner_vertices()?;
+        let mut edges: HashMap<(usize, usize), i32> = HashMap::new();
+        for face in self.faces()? {
+            for corner in face.clone() {
+                let a = corner_vertices[corner];
+                let b = corner_vertices[next_corner(&face, corner)];
+                *edges.entry((a, b)).or_default() += 1;
+            }
+        }
+
+        Ok(edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1)))
+    }
+
+    /// The enclosed volume, or `None` if the node is not closed.
+    ///
+    /// The volume is negative if the faces wind inwards.
+    pub fn volume(&self) -> Result<Option<f64>> {
+        if !self.is_closed()? {
+            return Ok(None);
+        }
+        let positions = self.positions()?;
+        let mut volume = 0.0;
+        for polygon in self.polygons()? {
+            let first = positions[polygon[0]];
+            for pair in polygon[1..].windows(2) {
+                volume += dot(first, cross(positions[pair[0]], positions[pair[1]])) / 6.0;
+            }
+        }
+
+        Ok(Some(volume))
+    }
+
+    /// The center of the surface, weighting every face by its area, or `None` if the node has no
+    /// area.
+    pub fn centroid(&self) -> Result<Option<[f64; 3]>> {
+        let positions = self.positions()?;
+        let mut sum = [0.0; 3];
+        let mut total = 0.0;
+        for polygon in self.polygons()? {
+            let first = positions[polygon[0]];
+            for pair in polygon[1..].windows(2) {
+                let (b, c) = (positions[pair[0]], positions[pair[1]]);
+                let area = length(cross(sub(b, first), sub(c, first))) / 2.0;
+                let center = scale(add(add(first, b), c), 1.0 / 3.0);
+                sum = add(sum, scale(center, area));
+                total += area;
+            }
+        }
+
+        Ok((total > 0.0).then(|| scale(sum, 1.0 / total)))
+    }
+}
+
+impl Node {
+    /// Compute the bounds of a geometry node and store them in its meta.
+    ///
+    /// Writes `bounds`, `bounding_sphere` and `surface_area`, and `volume` if the geometry is
+    /// closed. Entries that no longer apply, like the bounds of a node without vertices or the
+    /// volume of an open surface, are removed. See [`convention`] for the encoding.
+    ///
+    /// Fails with [`Error::QuantizedPositions`](crate::Error::QuantizedPositions) if the
+    /// positions are quantized, and does nothing for other nodes.
+    pub fn update_bounds(&mut self) -> Result<()> {
+        let Some(geometry) = self.geometry() else {
+            return Ok(());
+        };
+        self.expect_unquantized()?;
+        let aabb = geometry.aabb()?;
+        let sphere = geometry.bounding_sphere()?;
+        let area = geometry.surface_area()?;
+        let volume = geometry.volume()?;
+
+        match aabb {
+            Some(Aabb { min, max }) => {
+                let value = MetaValue::Double(min.into_iter().chain(max).collect());
+                self.set_meta(Meta::new(convention::BOUNDS, value));
+            }
+            None => self.meta.retain(|meta| meta.name != convention::BOUNDS),
+        }
+        match sphere {
+            Some(BoundingSphere { center, radius }) => {
+                let value = MetaValue::Double(center.into_iter().chain([radius]).collect());
+                self.set_meta(Meta::new(convention::BOUNDING_SPHERE, value));
+            }
+            None => self
+                .meta
+                .retain(|meta| meta.name != convention::BOUNDING_SPHERE),
+        }
+        self.set_meta(Meta::new(
+            convention::SURFACE_AREA,
+            MetaValue::Double(vec![area]),
+        ));
+        match volume {
+            Some(volume) => self.set_meta(Meta::new(
+                convention::VOLUME,
+                MetaValue::Double(vec![volume]),
+            )),
+            None => self.meta.retain(|meta| meta.name != convention::VOLUME),
+        }
+
+        Ok(())
+    }
+
+    /// The bounding box stored in the `bounds` meta. See [`GeometryNode::aabb`] to compute it.
+    pub fn stored_bounds(&self) -> Option<Aabb> {
+        match self.find_meta(convention::BOUNDS)?.as_double()? {
+            &[x0, y0, z0, x1, y1, z1] => Some(Aabb {
+                min: [x0, y0, z0],
+                max: [x1, y1, z1],
+            }),
+            _ => None,
+        }
+    }
+
+    /// The sphere stored in the `bounding_sphere` meta. See
+    /// [`GeometryNode::bounding_sphere`] to compute it.
+    pub fn stored_bounding_sphere(&self) -> Option<BoundingSphere> {
+        match self.find_meta(convention::BOUNDING_SPHERE)?.as_double()? {
+            &[x, y, z, radius] => Some(BoundingSphere {
+                center: [x, y, z],
+                radius,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Eigenvalues and eigenvectors, as columns, of a symmetric matrix using Jacobi rotations.
+fn symmetric_eigen(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..32 {
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .into_iter()
+            .max_by(|&(i, j), &(k, l)| a[i][j].abs().total_cmp(&a[k][l].abs()))
+            .unwrap_or((0, 1));
+        if a[p][q].abs() < 1e-15 {
+            break;
+        }
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let t = if theta == 0.0 { 1.0 } else { t };
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+        for row in &mut a {
+            let (rp, rq) = (row[p], row[q]);
+            row[p] = c * rp - s * rq;
+            row[q] = s * rp + c * rq;
+        }
+        let (row_p, row_q) = (a[p], a[q]);
+        a[p] = std::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+        a[q] = std::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+        for row in &mut v {
+            let (vp, vq) = (row[p], row[q]);
+            row[p] = c * vp - s * vq;
+            row[q] = s * vp + c * vq;
+        }
+    }
+
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, triangle_grid};
+
+    fn assert_near(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn cube_bounds_and_stats() {
+        let node = cube();
+        let aabb = node.aabb().unwrap().unwrap();
+        assert_eq!(aabb.min, [0.0; 3]);
+        assert_eq!(aabb.max, [1.0; 3]);
+        assert_eq!(aabb.center(), [0.5; 3]);
+
+        let sphere = node.bounding_sphere().unwrap().unwrap();
+        assert_eq!(sphere.center, [0.5; 3]);
+        assert_near(sphere.radius, 3.0f64.sqrt() / 2.0);
+
+        assert_eq!(node.surface_area().unwrap(), 6.0);
+        assert!(node.is_closed().unwrap());
+        assert_near(node.volume().unwrap().unwrap(), 1.0);
+        assert_eq!(node.centroid().unwrap(), Some([0.5; 3]));
+
+        let open = triangle_grid(2);
+        assert!(!open.is_closed().unwrap());
+        assert_eq!(open.volume().unwrap(), None);
+        let empty = GeometryNode::from_polygons::<[u32; 3]>(&[], &[]);
+        assert_eq!(empty.aabb().unwrap(), None);
+        assert_eq!(empty.centroid().unwrap(), None);
+    }
+
+    #[test]
+    fn oriented_box_follows_rotation() {
+        // A 4 x 2 x 1 box rotated 45 degrees around z.
+        let mut node = cube();
+        let (s, c) = std::f64::consts::FRAC_PI_4.sin_cos();
+        let positions: Vec<[f64; 3]> = node
+            .positions()
+            .unwrap()
+            .into_iter()
+            .map(|[x, y, z]| {
+                let (x, y) = (x * 4.0, y * 2.0);
+                [x * c - y * s, x * s + y * c, z]
+            })
+            .collect();
+        node.set_positions(&positions).unwrap();
+
+        let obb = node.oriented_bounding_box().unwrap().unwrap();
+        for (half, expected) in obb.half_extents.into_iter().zip([2.0, 1.0, 0.5]) {
+            assert!((half - expected).abs() < 1e-5, "{:?}", obb.half_extents);
+        }
+        assert!(dot(obb.axes[0], [c, s, 0.0]).abs() > 0.99999);
+        let center = [c * 2.0 - s, s * 2.0 + c, 0.5];
+        assert!(length(sub(obb.center, center)) < 1e-5, "{:?}", obb.center);
+    }
+
+    #[test]
+    fn bounds_meta_round_trip() {
+        let mut node: Node = cube().into();
+        node.update_bounds().unwrap();
+
+        assert_eq!(
+            node.stored_bounds(),
+            Some(Aabb {
+                min: [0.0; 3],
+                max: [1.0; 3]
+            })
+        );
+        assert_eq!(node.stored_bounding_sphere().unwrap().center, [0.5; 3]);
+        assert_eq!(
+            node.find_meta(convention::SURFACE_AREA)
+                .unwrap()
+                .as_double(),
+            Some(&[6.0][..])
+        );
+        assert!(node.find_meta(convention::VOLUME).is_some());
+    }
+
+    #[test]
+    fn stale_bounds_meta_is_removed() {
+        let mut node: Node = cube().into();
+        node.update_bounds().unwrap();
+
+        // Opening the cube drops the volume.
+        let geometry = node.geometry_mut().unwrap();
+        let faces = geometry.faces().unwrap();
+        *geometry = geometry
+            .rebuild_faces(&[(0, faces[0].clone().collect())])
+            .unwrap();
+        node.update_bounds().unwrap();
+        assert!(node.stored_bounds().is_some());
+        assert!(node.find_meta(convention::VOLUME).is_none());
+
+        let geometry = node.geometry_mut().unwrap();
+        *geometry = geometry.rebuild_faces(&[]).unwrap();
+        geometry.remove_unused_vertices().unwrap();
+        node.update_bounds().unwrap();
+        assert_eq!(node.stored_bounds(), None);
+        assert_eq!(node.stored_bounding_sphere(), None);
+    }
+}
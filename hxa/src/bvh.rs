@@ -0,0 +1,462 @@
+//! Bounding volume hierarchy over the triangles of a geometry node.
+//!
+//! A [`Bvh`] answers ray, closest point and box queries in logarithmic time. Polygons are split
+//! into triangles the same way [`GeometryNode::triangulate`] does, and query results point back
+//! at the face and the corners of the node, so any layer can be sampled at the result with
+//! [`SurfacePoint::interpolate`].
+//!
+//! This module is only available with the `bvh` feature, which is enabled by default.
+
+use crate::math::{add, closest_on_triangle, cross, dot, intersect_triangle, scale, sub, Vec3};
+use crate::{Aabb, GeometryNode, Layer, Result};
+
+/// Most triangles stored in a leaf of the hierarchy.
+const LEAF_SIZE: usize = 4;
+
+/// A bounding volume hierarchy over the triangles of a geometry node.
+///
+/// The hierarchy is a snapshot, it has to be rebuilt when the node changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+}
+
+/// A node of the hierarchy. Leaves hold `count` triangles from `start`, other nodes have their
+/// first child right after them and their second child at `second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BvhNode {
+    bounds: Aabb,
+    start: usize,
+    count: usize,
+    second: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Triangle {
+    points: [Vec3; 3],
+    /// The index of the triangle in the order the polygons were split.
+    index: usize,
+    face: usize,
+    corners: [usize; 3],
+}
+
+/// A point on the surface of a geometry node found by a [`Bvh`] query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfacePoint {
+    /// The face the point is on.
+    pub face: usize,
+    /// The corners of the triangle of the face the point is on.
+    pub corners: [usize; 3],
+    /// The weights of the corners at the point, adding up to 1.
+    pub barycentric: [f64; 3],
+    pub position: [f64; 3],
+    /// The distance to the point from the ray origin or the query point.
+    pub distance: f64,
+    /// The index of the triangle in the order the polygons were split.
+    pub(crate) triangle: usize,
+}
+
+impl SurfacePoint {
+    /// Sample a layer of the node at the point, as a layer with a single element.
+    ///
+    /// The layer is looked up in the corner, vertex and face stacks, in that order. Float layers
+    /// are interpolated with the barycentric weights, integer layers take the value of the
+    /// corner with the largest weight. See [`Layer::interpolate`].
+    pub fn interpolate(&self, node: &GeometryNode, name: &str) -> Result<Layer> {
+        if let Some(layer) = node.corner_stack.find(name) {
+            Ok(layer.interpolate(&[self.weights(self.corners)]))
+        } else if let Some(layer) = node.vertex_stack.find(name) {
+            let corner_vertices = node.corner_vertices()?;
+            let vertices = self.corners.map(|corner| corner_vertices[corner]);
+            Ok(layer.interpolate(&[self.weights(vertices)]))
+        } else {
+            let layer = node.face_stack.get(name)?;
+            Ok(layer.interpolate(&[vec![(self.face, 1.0)]]))
+        }
+    }
+
+    fn weights(&self, elements: [usize; 3]) -> Vec<(usize, f64)> {
+        elements.into_iter().zip(self.barycentric).collect()
+    }
+}
+
+impl Bvh {
+    /// Build the hierarchy over the triangles of a node.
+    pub fn new(node: &GeometryNode) -> Result<Self> {
+        let positions = node.positions()?;
+        let corner_vertices = node.corner_vertices()?;
+        let triangles = node.corner_triangles()?.into_iter().map(|(face, corners)| {
+            let points = corners.map(|corner| positions[corner_vertices[corner]]);
+            (face, corners, points)
+        });
+
+        Ok(Self::from_triangles(triangles))
+    }
+
+    /// Build the hierarchy over triangles already split from the faces of a node, given as
+    /// their face, corners and points in the order of [`GeometryNode::corner_triangles`].
+    pub(crate) fn from_triangles(
+        triangles: impl IntoIterator<Item = (usize, [usize; 3], [Vec3; 3])>,
+    ) -> Self {
+        let mut triangles: Vec<Triangle> = triangles
+            .into_iter()
+            .enumerate()
+            .map(|(index, (face, corners, points))| Triangle {
+                points,
+                index,
+                face,
+                corners,
+            })
+            .collect();
+
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            build(&mut nodes, &mut triangles, 0);
+        }
+
+        Self { nodes, triangles }
+    }
+
+    /// The bounds of all triangles, or `None` if there are none.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bounds)
+    }
+
+    /// The closest point where a ray hits either side of a triangle, up to `max_distance`.
+    ///
+    /// Distances are measured in multiples of `direction`, which is the actual distance when
+    /// it has unit length.
+    pub fn intersect_ray(
+        &self,
+        origin: [f64; 3],
+        direction: [f64; 3],
+        max_distance: f64,
+    ) -> Option<SurfacePoint> {
+        let inverse = direction.map(|d| 1.0 / d);
+        let mut closest: Option<(f64, f64, f64, &Triangle)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let limit = closest.map_or(max_distance, |hit| hit.0);
+            if !ray_hits_box(&node.bounds, origin, inverse, limit) {
+                continue;
+            }
+            if node.count > 0 {
+                for triangle in &self.triangles[node.start..node.start + node.count] {
+                    let limit = closest.map_or(max_distance, |hit| hit.0);
+                    if let Some((distance, u, v)) =
+                        intersect_triangle(origin, direction, triangle.points)
+                    {
+                        if distance > 0.0 && distance < limit {
+                            closest = Some((distance, u, v, triangle));
+                        }
+                    }
+                }
+            } else {
+                stack.push(node.second);
+                stack.push(index + 1);
+            }
+        }
+
+        closest.map(|(distance, u, v, triangle)| {
+            let barycentric = [1.0 - u - v, u, v];
+            SurfacePoint {
+                face: triangle.face,
+                corners: triangle.corners,
+                barycentric,
+                position: add(origin, scale(direction, distance)),
+                distance,
+                triangle: triangle.index,
+            }
+        })
+    }
+
+    /// The closest point on any triangle to a point, if it is at most `max_distance` away.
+    pub fn closest_point(&self, point: [f64; 3], max_distance: f64) -> Option<SurfacePoint> {
+        let mut best = max_distance * max_distance;
+        let mut closest: Option<(Vec3, [f64; 3], &Triangle)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if box_distance_squared(&node.bounds, point) > best {
+                continue;
+            }
+            if node.count > 0 {
+                for triangle in &self.triangles[node.start..node.start + node.count] {
+                    let (position, barycentric) = closest_on_triangle(point, triangle.points);
+                    let offset = sub(position, point);
+                    let distance = dot(offset, offset);
+                    if distance <= best {
+                        best = distance;
+                        closest = Some((position, barycentric, triangle));
+                    }
+                }
+            } else {
+                // Visit the nearer child first so the other one is more likely to be pruned.
+                let (first, second) = (index + 1, node.second);
+                let near_first = box_distance_squared(&self.nodes[first].bounds, point)
+                    <= box_distance_squared(&self.nodes[second].bounds, point);
+                match near_first {
+                    true => stack.extend([second, first]),
+                    false => stack.extend([first, second]),
+                }
+            }
+        }
+
+        closest.map(|(position, barycentric, triangle)| SurfacePoint {
+            face: triangle.face,
+            corners: triangle.corners,
+            barycentric,
+            position,
+            distance: best.sqrt(),
+            triangle: triangle.index,
+        })
+    }
+
+    /// The faces with a triangle touching a box, in increasing order.
+    pub fn overlap_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut faces = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.overlaps(aabb) {
+                continue;
+            }
+            if node.count > 0 {
+                faces.extend(
+                    self.triangles[node.start..node.start + node.count]
+                        .iter()
+                        .filter(|triangle| triangle_overlaps_box(triangle.points, aabb))
+                        .map(|triangle| triangle.face),
+                );
+            } else {
+                stack.extend([node.second, index + 1]);
+            }
+        }
+        faces.sort_unstable();
+        faces.dedup();
+        faces
+    }
+}
+
+impl GeometryNode {
+    /// Build a bounding volume hierarchy over the triangles of the node. See [`Bvh::new`].
+    pub fn bvh(&self) -> Result<Bvh> {
+        Bvh::new(self)
+    }
+}
+
+/// Add the node for a range of triangles and its children, splitting at the median of the
+/// longest axis of the triangle centers.
+fn build(nodes: &mut Vec<BvhNode>, triangles: &mut [Triangle], start: usize) {
+    let bounds =
+        Aabb::from_points(triangles.iter().flat_map(|t| t.points)).expect("nodes have triangles");
+    let index = nodes.len();
+    nodes.push(BvhNode {
+        bounds,
+        start,
+        count: triangles.len(),
+        second: 0,
+    });
+    if triangles.len() <= LEAF_SIZE {
+        return;
+    }
+
+    let center = |t: &Triangle| scale(add(add(t.points[0], t.points[1]), t.points[2]), 1.0 / 3.0);
+    let centers = Aabb::from_points(triangles.iter().map(center)).expect("nodes have triangles");
+    let size = centers.size();
+    let axis = (0..3)
+        .max_by(|&a, &b| size[a].total_cmp(&size[b]))
+        .unwrap_or(0);
+    let middle = triangles.len() / 2;
+    triangles.select_nth_unstable_by(middle, |a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+
+    let (first, second) = triangles.split_at_mut(middle);
+    nodes[index].count = 0;
+    build(nodes, first, start);
+    nodes[index].second = nodes.len();
+    build(nodes, second, start + middle);
+}
+
+/// Check if a ray enters a box before `max_distance`, using the slab method.
+fn ray_hits_box(aabb: &Aabb, origin: Vec3, inverse: Vec3, max_distance: f64) -> bool {
+    let (mut near, mut far) = (0.0f64, max_distance);
+    for axis in 0..3 {
+        let a = (aabb.min[axis] - origin[axis]) * inverse[axis];
+        let b = (aabb.max[axis] - origin[axis]) * inverse[axis];
+        // NaN comes from a zero direction with the origin on the slab, which is inside.
+        let (a, b) = (a.min(b), a.max(b));
+        if !a.is_nan() {
+            near = near.max(a);
+        }
+        if !b.is_nan() {
+            far = far.min(b);
+        }
+        if near > far {
+            return false;
+        }
+    }
+    true
+}
+
+fn box_distance_squared(aabb: &Aabb, point: Vec3) -> f64 {
+    (0..3)
+        .map(|axis| {
+            let d = (aabb.min[axis] - point[axis])
+                .max(point[axis] - aabb.max[axis])
+                .max(0.0);
+            d * d
+        })
+        .sum()
+}
+
+/// Check if a triangle touches a box with the separating axis test.
+fn triangle_overlaps_box(points: [Vec3; 3], aabb: &Aabb) -> bool {
+    let center = aabb.center();
+    let half = scale(aabb.size(), 0.5);
+    let [a, b, c] = points.map(|p| sub(p, center));
+    let edges = [sub(b, a), sub(c, b), sub(a, c)];
+    let box_axes = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    let separated = |axis: Vec3| {
+        let projections = [dot(a, axis), dot(b, axis), dot(c, axis)];
+        let radius: f64 = (0..3).map(|i| half[i] * axis[i].abs()).sum();
+        let min = projections.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = projections
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        min > radius || max < -radius
+    };
+
+    !(box_axes.iter().any(|&axis| separated(axis))
+        || separated(cross(edges[0], edges[1]))
+        || box_axes
+            .iter()
+            .flat_map(|&axis| edges.map(|edge| cross(axis, edge)))
+            .any(separated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle, triangle_grid};
+    use crate::{convention, LayerData};
+
+    #[test]
+    fn ray_hits_match_brute_force() {
+        let node = triangle_grid(16);
+        let bvh = node.bvh().unwrap();
+        assert_eq!(bvh.bounds().unwrap().max, [16.0, 16.0, 0.0]);
+
+        let hit = bvh
+            .intersect_ray([3.25, 5.5, 2.0], [0.0, 0.0, -1.0], f64::INFINITY)
+            .unwrap();
+        assert_eq!(hit.distance, 2.0);
+        assert_eq!(hit.position, [3.25, 5.5, 0.0]);
+        // The quad at (3, 5) is split into faces 2 * (5 * 16 + 3) and the one after it.
+        assert_eq!(hit.face, 167);
+        let positions = node.positions().unwrap();
+        let corner_vertices = node.corner_vertices().unwrap();
+        let interpolated = hit
+            .corners
+            .iter()
+            .zip(hit.barycentric)
+            .fold([0.0; 3], |sum, (&corner, weight)| {
+                add(sum, scale(positions[corner_vertices[corner]], weight))
+            });
+        assert!(sub(interpolated, hit.position)
+            .iter()
+            .all(|d| d.abs() < 1e-12));
+
+        assert!(bvh
+            .intersect_ray([3.25, 5.5, 2.0], [0.0, 0.0, -1.0], 1.0)
+            .is_none());
+        assert!(bvh
+            .intersect_ray([3.25, 5.5, 2.0], [0.0, 0.0, 1.0], f64::INFINITY)
+            .is_none());
+        assert!(bvh
+            .intersect_ray([-1.0, 0.5, 0.0], [1.0, 0.0, 0.0], f64::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn closest_points() {
+        let bvh = cube().bvh().unwrap();
+        let inside = bvh.closest_point([0.5, 0.5, 0.9], f64::INFINITY).unwrap();
+        assert_eq!(inside.face, 1);
+        assert!((inside.distance - 0.1).abs() < 1e-12);
+
+        let corner = bvh.closest_point([2.0, 2.0, 2.0], f64::INFINITY).unwrap();
+        assert_eq!(corner.position, [1.0, 1.0, 1.0]);
+        assert!(bvh.closest_point([2.0, 2.0, 2.0], 1.0).is_none());
+    }
+
+    #[test]
+    fn overlapping_faces() {
+        let bvh = cube().bvh().unwrap();
+        let aabb = Aabb {
+            min: [0.25, 0.25, -0.5],
+            max: [0.75, 0.75, 0.5],
+        };
+        assert_eq!(bvh.overlap_aabb(&aabb), vec![0]);
+        let aabb = Aabb {
+            min: [0.9, 0.9, 0.9],
+            max: [2.0, 2.0, 2.0],
+        };
+        assert_eq!(bvh.overlap_aabb(&aabb), vec![1, 3, 4]);
+        let aabb = Aabb {
+            min: [0.25, 0.25, 0.25],
+            max: [0.75, 0.75, 0.75],
+        };
+        assert!(bvh.overlap_aabb(&aabb).is_empty());
+    }
+
+    #[test]
+    fn interpolate_layers_at_hits() {
+        let mut node = quad_and_triangle();
+        node.corner_stack.push(Layer::new(
+            "uv",
+            2,
+            LayerData::Float(vec![
+                0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0,
+            ]),
+        ));
+        node.vertex_stack.push(Layer::new(
+            "weight",
+            1,
+            LayerData::Double(vec![0.0, 1.0, 1.0, 0.0, 5.0]),
+        ));
+        node.face_stack.push(Layer::new(
+            convention::LAYER_MATERIAL_ID,
+            1,
+            LayerData::Int32(vec![3, 8]),
+        ));
+        let hit = node
+            .bvh()
+            .unwrap()
+            .intersect_ray([0.25, 0.75, 1.0], [0.0, 0.0, -1.0], f64::INFINITY)
+            .unwrap();
+
+        let uv = hit.interpolate(&node, "uv").unwrap();
+        assert!((uv.get(0, 0) - 0.25).abs() < 1e-6 && (uv.get(0, 1) - 0.75).abs() < 1e-6);
+        assert!((hit.interpolate(&node, "weight").unwrap().get(0, 0) - 0.25).abs() < 1e-12);
+        assert_eq!(
+            hit.interpolate(&node, convention::LAYER_MATERIAL_ID)
+                .unwrap()
+                .data,
+            LayerData::Int32(vec![3])
+        );
+        assert!(hit.interpolate(&node, "missing").is_err());
+    }
+}
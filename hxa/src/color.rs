@@ -0,0 +1,370 @@
+//! Typed access to the `color` layer.
+//!
+//! Colors are stored per vertex or per corner with 3 (RGB) or 4 (RGBA) components, as uint8 values
+//! from 0 to 255 or as floating point values, from 0 to 1 or beyond for HDR colors. They are read
+//! and written here as RGBA doubles, with an alpha of 1 for RGB layers. The `color_space` text meta
+//! of the node records whether the RGB values are sRGB encoded or linear, alpha is always linear.
+
+use hxa_sys::HXALayerDataType;
+
+use crate::{convention, Error, GeometryNode, Layer, LayerData, Meta, MetaValue, Node, Result};
+
+/// How the RGB values of a color layer are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// The sRGB transfer function, as painted and displayed.
+    Srgb,
+    /// Linear light, as shaders blend it.
+    Linear,
+}
+
+impl ColorSpace {
+    /// The value of the `color_space` meta.
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::Linear => "linear",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" => Some(ColorSpace::Linear),
+            _ => None,
+        }
+    }
+
+    /// Convert a value from this space to another.
+    pub fn convert(self, to: ColorSpace, value: f64) -> f64 {
+        match (self, to) {
+            (ColorSpace::Srgb, ColorSpace::Linear) => srgb_to_linear(value),
+            (ColorSpace::Linear, ColorSpace::Srgb) => linear_to_srgb(value),
+            _ => value,
+        }
+    }
+}
+
+/// How a color layer is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorFormat {
+    /// `HXA_LDT_UINT8` for values from 0 to 255, or `HXA_LDT_FLOAT` or `HXA_LDT_DOUBLE` for
+    /// values from 0 to 1, or beyond for HDR colors.
+    pub data_type: HXALayerDataType,
+    /// Store 4 components instead of 3.
+    pub alpha: bool,
+}
+
+impl ColorFormat {
+    pub fn new(data_type: HXALayerDataType, alpha: bool) -> Self {
+        Self { data_type, alpha }
+    }
+
+    /// The format of an existing color layer.
+    pub fn of(layer: &Layer) -> Result<Self> {
+        match layer.components {
+            3 | 4 => Ok(Self::new(layer.data_type(), layer.components == 4)),
+            actual => Err(Error::LayerComponents {
+                name: layer.name.clone(),
+                expected: 4,
+                actual,
+            }),
+        }
+    }
+
+    fn components(self) -> u8 {
+        if self.alpha {
+            4
+        } else {
+            3
+        }
+    }
+}
+
+impl GeometryNode {
+    /// The color of every corner, read from the corner stack or through the vertices of the
+    /// corners from the vertex stack.
+    pub fn corner_colors(&self) -> Result<Vec<[f64; 4]>> {
+        if let Some(layer) = self.corner_stack.find(convention::LAYER_COLOR) {
+            return read_colors(layer);
+        }
+        let colors = read_colors(self.vertex_stack.get(convention::LAYER_COLOR)?)?;
+
+        Ok(self
+            .corner_vertices()?
+            .into_iter()
+            .map(|vertex| colors[vertex])
+            .collect())
+    }
+
+    /// The color of every vertex.
+    ///
+    /// Colors stored per corner fail with [`Error::Discontinuous`] if corners sharing a vertex
+    /// differ. Vertices that are not used by any corner get transparent black.
+    pub fn vertex_colors(&self) -> Result<Vec<[f64; 4]>> {
+        let name = convention::LAYER_COLOR;
+        if self.corner_stack.find(name).is_none() {
+            return read_colors(self.vertex_stack.get(name)?);
+        }
+        if !self.is_corner_layer_continuous(name)? {
+            return Err(Error::Discontinuous {
+                name: name.to_string(),
+            });
+        }
+        let mut colors = vec![[0.0; 4]; self.vertex_count()];
+        for (vertex, color) in self
+            .corner_vertices()?
+            .into_iter()
+            .zip(self.corner_colors()?)
+        {
+            colors[vertex] = color;
+        }
+
+        Ok(colors)
+    }
+
+    /// Store a color per vertex, replacing the colors in either stack.
+    pub fn set_vertex_colors(&mut self, colors: &[[f64; 4]], format: ColorFormat) -> Result<()> {
+        let layer = write_colors(colors, format, self.vertex_count())?;
+        self.corner_stack.remove(convention::LAYER_COLOR);
+        self.vertex_stack.insert(layer);
+
+        Ok(())
+    }
+
+    /// Store a color per corner, replacing the colors in either stack.
+    pub fn set_corner_colors(&mut self, colors: &[[f64; 4]], format: ColorFormat) -> Result<()> {
+        let layer = write_colors(colors, format, self.reference()?.len())?;
+        self.vertex_stack.remove(convention::LAYER_COLOR);
+        self.corner_stack.insert(layer);
+
+        Ok(())
+    }
+
+    /// Change the type and number of components of the color layer, keeping it in its stack.
+    ///
+    /// Removing the alpha component drops it, adding one makes every color opaque.
+    pub fn convert_colors(&mut self, format: ColorFormat) -> Result<()> {
+        let stack = match self.corner_stack.find(convention::LAYER_COLOR) {
+            Some(_) => &mut self.corner_stack,
+            None => &mut self.vertex_stack,
+        };
+        let layer = stack.get_mut(convention::LAYER_COLOR)?;
+        *layer = write_colors(&read_colors(layer)?, format, layer.len())?;
+
+        Ok(())
+    }
+}
+
+impl Node {
+    /// The color space recorded in the `color_space` meta.
+    pub fn color_space(&self) -> Option<ColorSpace> {
+        ColorSpace::from_name(self.find_meta(convention::COLOR_SPACE)?.as_text()?)
+    }
+
+    /// Record the color space of the color layer, without changing its values.
+    pub fn set_color_space(&mut self, space: ColorSpace) {
+        self.set_meta(Meta::new(
+            convention::COLOR_SPACE,
+            MetaValue::Text(space.name().to_string()),
+        ));
+    }
+
+    /// Convert the RGB values of the color layers of a geometry node to another color space and
+    /// record it.
+    ///
+    /// Nodes without a `color_space` meta are taken to be sRGB, the encoding colors are painted
+    /// in. Uint8 layers are rounded to the nearest value, so converting back and forth loses
+    /// precision in the darks. Does nothing for other nodes.
+    pub fn convert_color_space(&mut self, space: ColorSpace) -> Result<()> {
+        let from = self.color_space().unwrap_or(ColorSpace::Srgb);
+        let Some(geometry) = self.geometry_mut() else {
+            return Ok(());
+        };
+        if from != space {
+            for stack in [&mut geometry.vertex_stack, &mut geometry.corner_stack] {
+                let Some(layer) = stack.find_mut(convention::LAYER_COLOR) else {
+                    continue;
+                };
+                let format = ColorFormat::of(layer)?;
+                let mut colors = read_colors(layer)?;
+                for color in &mut colors {
+                    for value in &mut color[..3] {
+                        *value = from.convert(space, *value);
+                    }
+                }
+                *layer = write_colors(&colors, format, colors.len())?;
+            }
+        }
+        self.set_color_space(space);
+
+        Ok(())
+    }
+}
+
+/// Read a 3 or 4 component color layer as RGBA values, mapping uint8 values to 0 to 1.
+fn read_colors(layer: &Layer) -> Result<Vec<[f64; 4]>> {
+    let format = ColorFormat::of(layer)?;
+    let scale = match format.data_type {
+        HXALayerDataType::HXA_LDT_UINT8 => 1.0 / 255.0,
+        HXALayerDataType::HXA_LDT_FLOAT | HXALayerDataType::HXA_LDT_DOUBLE => 1.0,
+        actual => {
+            return Err(Error::LayerType {
+                name: layer.name.clone(),
+                expected: HXALayerDataType::HXA_LDT_FLOAT,
+                actual,
+            })
+        }
+    };
+
+    Ok((0..layer.len())
+        .map(|color| {
+            let mut rgba = [1.0; 4];
+            for (channel, value) in rgba[..format.components() as usize].iter_mut().enumerate() {
+                *value = layer.get(color, channel) * scale;
+            }
+            rgba
+        })
+        .collect())
+}
+
+/// Build a color layer from RGBA values, checking there is one for each element.
+///
+/// Values are clamped from 0 to 1 for uint8 layers and stored as they are in floating point ones.
+fn write_colors(colors: &[[f64; 4]], format: ColorFormat, expected: usize) -> Result<Layer> {
+    let name = convention::LAYER_COLOR;
+    if colors.len() != expected {
+        return Err(Error::LayerLength {
+            name: name.to_string(),
+            expected,
+            actual: colors.len(),
+        });
+    }
+    let encode: fn(f64) -> f64 = match format.data_type {
+        HXALayerDataType::HXA_LDT_UINT8 => |value| value.clamp(0.0, 1.0) * 255.0,
+        HXALayerDataType::HXA_LDT_FLOAT | HXALayerDataType::HXA_LDT_DOUBLE => |value| value,
+        actual => {
+            return Err(Error::LayerType {
+                name: name.to_string(),
+                expected: HXALayerDataType::HXA_LDT_FLOAT,
+                actual,
+            })
+        }
+    };
+    let components = format.components() as usize;
+    let mut data = LayerData::with_capacity(format.data_type, colors.len() * components);
+    for color in colors {
+        for &value in &color[..components] {
+            data.push_f64(encode(value));
+        }
+    }
+
+    Ok(Layer::new(name, format.components(), data))
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+
+    const UINT8_RGB: ColorFormat = ColorFormat {
+        data_type: HXALayerDataType::HXA_LDT_UINT8,
+        alpha: false,
+    };
+    const FLOAT_RGBA: ColorFormat = ColorFormat {
+        data_type: HXALayerDataType::HXA_LDT_FLOAT,
+        alpha: true,
+    };
+
+    #[test]
+    fn colors_are_read_from_either_stack() {
+        let mut node = quad_and_triangle();
+        let colors: Vec<[f64; 4]> = (0..5).map(|v| [v as f64 / 4.0, 0.0, 1.0, 0.5]).collect();
+        node.set_vertex_colors(&colors, UINT8_RGB).unwrap();
+        let layer = node.vertex_stack.get(convention::LAYER_COLOR).unwrap();
+        assert_eq!(layer.data_type(), HXALayerDataType::HXA_LDT_UINT8);
+        assert_eq!(layer.get(1, 0), 64.0);
+        assert_eq!(node.vertex_colors().unwrap()[4], [1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(node.corner_colors().unwrap()[5], [1.0, 0.0, 1.0, 1.0]);
+
+        node.convert_colors(FLOAT_RGBA).unwrap();
+        let layer = node.vertex_stack.get(convention::LAYER_COLOR).unwrap();
+        assert_eq!(ColorFormat::of(layer).unwrap(), FLOAT_RGBA);
+        let color = node.vertex_colors().unwrap()[2];
+        assert!((color[0] - 128.0 / 255.0).abs() < 1e-6);
+        assert_eq!(color[1..], [0.0, 1.0, 1.0]);
+
+        let mut corners = node.corner_colors().unwrap();
+        node.set_corner_colors(&corners, FLOAT_RGBA).unwrap();
+        assert!(node.vertex_stack.find(convention::LAYER_COLOR).is_none());
+        assert!((node.vertex_colors().unwrap()[2][0] - 128.0 / 255.0).abs() < 1e-6);
+        corners[4][1] = 1.0;
+        node.set_corner_colors(&corners, FLOAT_RGBA).unwrap();
+        assert!(node.vertex_colors().is_err());
+        assert!(node.set_corner_colors(&colors, FLOAT_RGBA).is_err());
+    }
+
+    #[test]
+    fn hdr_colors_are_only_clamped_in_uint8() {
+        let mut node = Node::from(quad_and_triangle());
+        let colors = [[4.0, 2.0, 0.5, 1.0]; 5];
+        let geometry = node.geometry_mut().unwrap();
+        geometry.set_vertex_colors(&colors, FLOAT_RGBA).unwrap();
+        assert_eq!(geometry.vertex_colors().unwrap(), colors);
+
+        node.set_color_space(ColorSpace::Linear);
+        node.convert_color_space(ColorSpace::Srgb).unwrap();
+        node.convert_color_space(ColorSpace::Linear).unwrap();
+        let geometry = node.geometry_mut().unwrap();
+        let linear = geometry.vertex_colors().unwrap();
+        assert!((linear[0][0] - 4.0).abs() < 1e-6);
+        assert!((linear[0][1] - 2.0).abs() < 1e-6);
+
+        geometry.convert_colors(UINT8_RGB).unwrap();
+        assert_eq!(
+            geometry.vertex_colors().unwrap()[0],
+            [1.0, 1.0, 128.0 / 255.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn color_spaces_convert_and_are_recorded() {
+        let mut node = Node::from(quad_and_triangle());
+        let colors = [[0.0, 0.5, 1.0, 0.5]; 5];
+        let geometry = node.geometry_mut().unwrap();
+        geometry.set_vertex_colors(&colors, FLOAT_RGBA).unwrap();
+        assert_eq!(node.color_space(), None);
+
+        node.convert_color_space(ColorSpace::Linear).unwrap();
+        assert_eq!(node.color_space(), Some(ColorSpace::Linear));
+        let linear = node.geometry().unwrap().vertex_colors().unwrap();
+        assert_eq!(linear[0][0], 0.0);
+        assert!((linear[0][1] - 0.214041).abs() < 1e-6);
+        assert_eq!(linear[0][2], 1.0);
+        assert_eq!(linear[0][3], 0.5);
+
+        node.convert_color_space(ColorSpace::Srgb).unwrap();
+        assert_eq!(
+            node.find_meta(convention::COLOR_SPACE).unwrap().as_text(),
+            Some("srgb")
+        );
+        let srgb = node.geometry().unwrap().vertex_colors().unwrap();
+        assert!((srgb[0][1] - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,153 @@
+//! Layer and meta names defined by the HxA conventions.
+//!
+//! These mirror the `HXA_CONVENTION_*` constants in `hxa-sys`, without the trailing nul, so
+//! they can be compared against [`Layer::name`](crate::Layer::name) and
+//! [`Meta::name`](crate::Meta::name) directly.
+
+/// Name of the first layer in the vertex stack.
+pub const BASE_VERTEX_LAYER_NAME: &str = "vertex";
+/// Number of components of the base vertex layer.
+pub const BASE_VERTEX_LAYER_COMPONENTS: u8 = 3;
+/// Name of the first layer in the corner stack.
+pub const BASE_CORNER_LAYER_NAME: &str = "reference";
+/// Number of components of the base corner layer.
+pub const BASE_CORNER_LAYER_COMPONENTS: u8 = 1;
+/// Name of the edge layer storing the opposite corner of each edge.
+pub const EDGE_NEIGHBOUR_LAYER_NAME: &str = "neighbour";
+
+pub const LAYER_SEQUENCE0: &str = "sequence";
+pub const LAYER_UV0: &str = "uv";
+pub const LAYER_NORMALS: &str = "normal";
+pub const LAYER_BINORMAL: &str = "binormal";
+pub const LAYER_TANGENT: &str = "tangent";
+pub const LAYER_COLOR: &str = "color";
+pub const LAYER_CREASES: &str = "creases";
+pub const LAYER_SELECTION: &str = "select";
+pub const LAYER_SKIN_WEIGHT: &str = "skining_weight";
+pub const LAYER_SKIN_REFERENCE: &str = "skining_reference";
+pub const LAYER_BLENDSHAPE: &str = "blendshape";
+pub const LAYER_ADD_BLENDSHAPE: &str = "addblendshape";
+pub const LAYER_MATERIAL_ID: &str = "material";
+pub const LAYER_GROUP_ID: &str = "group";
+
+pub const ALBEDO: &str = "albedo";
+pub const LIGHT: &str = "light";
+pub const DISPLACEMENT: &str = "displacement";
+pub const DISTORTION: &str = "distortion";
+pub const AMBIENT_OCCLUSION: &str = "ambient_occlusion";
+
+pub const NAME: &str = "name";
+/// Double meta holding the 4x4 transform matrix of a node as 16 values in column major order,
+/// with the translation in values 12, 13 and 14. See [`Mat4`](crate::Mat4).
+pub const TRANSFORM: &str = "transform";
+
+// Conventions used by this crate that are not yet part of the format.
+
+/// Node meta on a geometry node referencing its levels of detail, from most to least detailed.
+pub const LOD: &str = "lod";
+/// Double meta on a level of detail node, holding the largest collapse error of the
+/// simplification. See [`SimplifyOptions::max_error`](crate::SimplifyOptions::max_error).
+pub const LOD_ERROR: &str = "lod_error";
+/// Node reference pointing at no node, left in place of references to removed nodes by
+/// [`ReferencePolicy::Null`](crate::ReferencePolicy::Null).
+pub const NULL_REFERENCE: u32 = u32::MAX;
+/// Node meta listing the child nodes of a node in the scene hierarchy.
+pub const CHILDREN: &str = "children";
+/// Node meta holding the single parent node of a node in the scene hierarchy.
+pub const PARENT: &str = "parent";
+/// Double meta on a geometry node with its bounding box, as the minimum x, y, z followed by the
+/// maximum x, y, z.
+pub const BOUNDS: &str = "bounds";
+/// Double meta on a geometry node with its bounding sphere, as the center x, y, z and the radius.
+pub const BOUNDING_SPHERE: &str = "bounding_sphere";
+/// Double meta on a geometry node with the total area of its faces.
+pub const SURFACE_AREA: &str = "surface_area";
+/// Double meta on a closed geometry node with its enclosed volume.
+pub const VOLUME: &str = "volume";
+/// Text meta on a geometry node with the encoding of the RGB values of its `color` layer, either
+/// `srgb` or `linear`. See [`ColorSpace`](crate::ColorSpace).
+pub const COLOR_SPACE: &str = "color_space";
+/// Double meta on a geometry node with int32 positions, holding the box they are quantized in as
+/// the minimum x, y, z followed by the maximum x, y, z. A position is the minimum plus its value
+/// times the `quantization_scale`.
+pub const QUANTIZATION_BOUNDS: &str = "quantization_bounds";
+/// Double meta on a geometry node with int32 positions, holding the size of one step along x, y
+/// and z.
+pub const QUANTIZATION_SCALE: &str = "quantization_scale";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sys(name: &[u8]) -> &str {
+        std::ffi::CStr::from_bytes_with_nul(name)
+            .unwrap()
+            .to_str()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_sys_conventions() {
+        use hxa_sys::*;
+
+        assert_eq!(
+            BASE_VERTEX_LAYER_NAME,
+            sys(HXA_CONVENTION_HARD_BASE_VERTEX_LAYER_NAME)
+        );
+        assert_eq!(
+            BASE_VERTEX_LAYER_COMPONENTS as u32,
+            HXA_CONVENTION_HARD_BASE_VERTEX_LAYER_COMPONENTS
+        );
+        assert_eq!(
+            BASE_CORNER_LAYER_NAME,
+            sys(HXA_CONVENTION_HARD_BASE_CORNER_LAYER_NAME)
+        );
+        assert_eq!(
+            BASE_CORNER_LAYER_COMPONENTS as u32,
+            HXA_CONVENTION_HARD_BASE_CORNER_LAYER_COMPONENTS
+        );
+        assert_eq!(
+            EDGE_NEIGHBOUR_LAYER_NAME,
+            sys(HXA_CONVENTION_HARD_EDGE_NEIGHBOUR_LAYER_NAME)
+        );
+
+        assert_eq!(LAYER_SEQUENCE0, sys(HXA_CONVENTION_SOFT_LAYER_SEQUENCE0));
+        assert_eq!(LAYER_UV0, sys(HXA_CONVENTION_SOFT_LAYER_UV0));
+        assert_eq!(LAYER_NORMALS, sys(HXA_CONVENTION_SOFT_LAYER_NORMALS));
+        assert_eq!(LAYER_BINORMAL, sys(HXA_CONVENTION_SOFT_LAYER_BINORMAL));
+        assert_eq!(LAYER_TANGENT, sys(HXA_CONVENTION_SOFT_LAYER_TANGENT));
+        assert_eq!(LAYER_COLOR, sys(HXA_CONVENTION_SOFT_LAYER_COLOR));
+        assert_eq!(LAYER_CREASES, sys(HXA_CONVENTION_SOFT_LAYER_CREASES));
+        assert_eq!(LAYER_SELECTION, sys(HXA_CONVENTION_SOFT_LAYER_SELECTION));
+        assert_eq!(
+            LAYER_SKIN_WEIGHT,
+            sys(HXA_CONVENTION_SOFT_LAYER_SKIN_WEIGHT)
+        );
+        assert_eq!(
+            LAYER_SKIN_REFERENCE,
+            sys(HXA_CONVENTION_SOFT_LAYER_SKIN_REFERENCE)
+        );
+        assert_eq!(LAYER_BLENDSHAPE, sys(HXA_CONVENTION_SOFT_LAYER_BLENDSHAPE));
+        assert_eq!(
+            LAYER_ADD_BLENDSHAPE,
+            sys(HXA_CONVENTION_SOFT_LAYER_ADD_BLENDSHAPE)
+        );
+        assert_eq!(
+            LAYER_MATERIAL_ID,
+            sys(HXA_CONVENTION_SOFT_LAYER_MATERIAL_ID)
+        );
+        assert_eq!(LAYER_GROUP_ID, sys(HXA_CONVENTION_SOFT_LAYER_GROUP_ID));
+
+        assert_eq!(ALBEDO, sys(HXA_CONVENTION_SOFT_ALBEDO));
+        assert_eq!(LIGHT, sys(HXA_CONVENTION_SOFT_LIGHT));
+        assert_eq!(DISPLACEMENT, sys(HXA_CONVENTION_SOFT_DISPLACEMENT));
+        assert_eq!(DISTORTION, sys(HXA_CONVENTION_SOFT_DISTORTION));
+        assert_eq!(
+            AMBIENT_OCCLUSION,
+            sys(HXA_CONVENTION_SOFT_AMBIENT_OCCLUSION)
+        );
+
+        assert_eq!(NAME, sys(HXA_CONVENTION_SOFT_NAME));
+        assert_eq!(TRANSFORM, sys(HXA_CONVENTION_SOFT_TRANSFORM));
+    }
+}
@@ -0,0 +1,385 @@
+//! Converting layers between data types, and quantizing positions.
+//!
+//! Integer layers can hold normalized values: unorm maps the full range of the type to 0 to 1,
+//! and snorm maps it to -1 to 1. Uint8 has no sign, so snorm uint8 stores `(v + 1) / 2`, as
+//! normal maps do. Int32 covers -2147483647 to 2147483647, leaving out the smallest value so 0
+//! stays exact.
+
+use hxa_sys::HXALayerDataType;
+
+use crate::{convention, Aabb, Error, Layer, LayerData, Meta, MetaValue, Node, Result};
+
+/// How integer values map to floating point values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Normalization {
+    /// Integers keep their value.
+    None,
+    Unorm,
+    Snorm,
+}
+
+/// How floating point values are rounded when stored as integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    Nearest,
+    Floor,
+    Ceil,
+    TowardZero,
+}
+
+/// Settings of a layer conversion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConvertOptions {
+    /// Applies to the integer side of the conversion, or to both sides between integer types.
+    pub normalization: Normalization,
+    pub rounding: Rounding,
+    /// Clamp values to a range, after reading and normalizing them. Integers saturate at the
+    /// limits of their type either way.
+    pub clamp: Option<[f64; 2]>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            normalization: Normalization::None,
+            rounding: Rounding::Nearest,
+            clamp: None,
+        }
+    }
+}
+
+impl Normalization {
+    /// The value an integer of a type stands for.
+    fn decode(self, data_type: HXALayerDataType, value: f64) -> f64 {
+        match (self, data_type) {
+            (Normalization::Unorm, HXALayerDataType::HXA_LDT_UINT8) => value / 255.0,
+            (Normalization::Snorm, HXALayerDataType::HXA_LDT_UINT8) => value / 255.0 * 2.0 - 1.0,
+            (Normalization::Unorm | Normalization::Snorm, HXALayerDataType::HXA_LDT_INT32) => {
+                value / i32::MAX as f64
+            }
+            _ => value,
+        }
+    }
+
+    /// The integer of a type standing for a value, before rounding.
+    fn encode(self, data_type: HXALayerDataType, value: f64) -> f64 {
+        match (self, data_type) {
+            (Normalization::Unorm, HXALayerDataType::HXA_LDT_UINT8) => {
+                value.clamp(0.0, 1.0) * 255.0
+            }
+            (Normalization::Snorm, HXALayerDataType::HXA_LDT_UINT8) => {
+                (value.clamp(-1.0, 1.0) + 1.0) / 2.0 * 255.0
+            }
+            (Normalization::Unorm, HXALayerDataType::HXA_LDT_INT32) => {
+                value.clamp(0.0, 1.0) * i32::MAX as f64
+            }
+            (Normalization::Snorm, HXALayerDataType::HXA_LDT_INT32) => {
+                value.clamp(-1.0, 1.0) * i32::MAX as f64
+            }
+            _ => value,
+        }
+    }
+}
+
+impl Rounding {
+    fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::Nearest => value.round(),
+            Rounding::Floor => value.floor(),
+            Rounding::Ceil => value.ceil(),
+            Rounding::TowardZero => value.trunc(),
+        }
+    }
+}
+
+impl Layer {
+    /// Convert the layer to another data type, rounding to the nearest integer and saturating
+    /// at the limits of integer types. See [`convert_with`](Self::convert_with).
+    pub fn convert_to(&self, data_type: HXALayerDataType) -> Layer {
+        self.convert_with(data_type, &ConvertOptions::default())
+    }
+
+    /// Convert the layer to another data type.
+    ///
+    /// Every value is read, normalized if it is an integer, clamped, then normalized and rounded
+    /// if the new type is an integer type. Normalized values outside of their range clamp to it.
+    pub fn convert_with(&self, data_type: HXALayerDataType, options: &ConvertOptions) -> Layer {
+        let from = self.data_type();
+        let integer = matches!(
+            data_type,
+            HXALayerDataType::HXA_LDT_UINT8 | HXALayerDataType::HXA_LDT_INT32
+        );
+        let mut data = LayerData::with_capacity(data_type, self.data.len());
+        for index in 0..self.data.len() {
+            let mut value = options.normalization.decode(from, self.data.get_f64(index));
+            if let Some([min, max]) = options.clamp {
+                value = value.clamp(min, max);
+            }
+            value = options.normalization.encode(data_type, value);
+            if integer {
+                value = options.rounding.apply(value);
+            }
+            data.push_f64(value);
+        }
+
+        Layer::new(self.name.clone(), self.components, data)
+    }
+}
+
+impl Node {
+    /// Store the positions of a geometry node as int32 values relative to its bounding box.
+    ///
+    /// Each axis of the box is split in `2^bits - 1` steps, with `bits` clamped to 1 to 31. The
+    /// box goes into the `quantization_bounds` meta and the size of the steps into
+    /// `quantization_scale`, see [`convention`]. Geometry methods reading positions see the
+    /// steps until [`dequantize_positions`](Self::dequantize_positions) is called, while node and
+    /// file operations storing results derived from positions, like bounds, levels of detail or
+    /// baked images, fail with [`Error::QuantizedPositions`]. Positions already quantized are
+    /// dequantized first. Does nothing for other nodes.
+    pub fn quantize_positions(&mut self, bits: u32) -> Result<()> {
+        self.dequantize_positions(HXALayerDataType::HXA_LDT_DOUBLE)?;
+        let Some(geometry) = self.geometry_mut() else {
+            return Ok(());
+        };
+        let positions = geometry.positions()?;
+        let Some(Aabb { min, max }) = Aabb::from_points(positions.iter().copied()) else {
+            return Ok(());
+        };
+        let steps = ((1u64 << bits.clamp(1, 31)) - 1) as f64;
+        let scale: [f64; 3] = std::array::from_fn(|axis| (max[axis] - min[axis]) / steps);
+
+        let mut layer = geometry
+            .vertex_layer()?
+            .convert_to(HXALayerDataType::HXA_LDT_INT32);
+        for (vertex, position) in positions.iter().enumerate() {
+            for axis in 0..3 {
+                let step = if scale[axis] > 0.0 {
+                    ((position[axis] - min[axis]) / scale[axis]).round()
+                } else {
+                    0.0
+                };
+                layer.set(vertex, axis, step);
+            }
+        }
+        geometry.vertex_stack.layers[0] = layer;
+        self.set_meta(Meta::new(
+            convention::QUANTIZATION_BOUNDS,
+            MetaValue::Double(min.into_iter().chain(max).collect()),
+        ));
+        self.set_meta(Meta::new(
+            convention::QUANTIZATION_SCALE,
+            MetaValue::Double(scale.to_vec()),
+        ));
+
+        Ok(())
+    }
+
+    /// Restore quantized positions to a floating point type and remove the quantization meta.
+    /// Does nothing for nodes without quantized positions.
+    pub fn dequantize_positions(&mut self, data_type: HXALayerDataType) -> Result<()> {
+        let Some((min, scale)) = self.quantization() else {
+            return Ok(());
+        };
+        let Some(geometry) = self.geometry_mut() else {
+            return Ok(());
+        };
+        let steps = geometry.positions()?;
+        let mut layer = geometry.vertex_layer()?.convert_to(data_type);
+        for (vertex, step) in steps.iter().enumerate() {
+            for axis in 0..3 {
+                layer.set(vertex, axis, min[axis] + step[axis] * scale[axis]);
+            }
+        }
+        geometry.vertex_stack.layers[0] = layer;
+        self.meta.retain(|meta| {
+            meta.name != convention::QUANTIZATION_BOUNDS
+                && meta.name != convention::QUANTIZATION_SCALE
+        });
+
+        Ok(())
+    }
+
+    /// Fail with [`Error::QuantizedPositions`] if the node has quantized positions.
+    pub(crate) fn expect_unquantized(&self) -> Result<()> {
+        match self.quantization() {
+            Some(_) => Err(Error::QuantizedPositions),
+            None => Ok(()),
+        }
+    }
+
+    /// The minimum corner of the quantization box and the size of a step on each axis.
+    fn quantization(&self) -> Option<([f64; 3], [f64; 3])> {
+        let bounds = self
+            .find_meta(convention::QUANTIZATION_BOUNDS)?
+            .as_double()?;
+        let scale = self
+            .find_meta(convention::QUANTIZATION_SCALE)?
+            .as_double()?;
+        match (bounds, scale) {
+            (&[x, y, z, ..], &[sx, sy, sz]) if bounds.len() == 6 => Some(([x, y, z], [sx, sy, sz])),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::cube;
+
+    fn layer(data: LayerData) -> Layer {
+        Layer::new("value", 1, data)
+    }
+
+    #[test]
+    fn conversions_between_types() {
+        let doubles = layer(LayerData::Double(vec![0.25, -1.5, 300.0]));
+        assert_eq!(
+            doubles.convert_to(HXALayerDataType::HXA_LDT_FLOAT).data,
+            LayerData::Float(vec![0.25, -1.5, 300.0])
+        );
+        assert_eq!(
+            doubles.convert_to(HXALayerDataType::HXA_LDT_UINT8).data,
+            LayerData::Uint8(vec![0, 0, 255])
+        );
+        let options = ConvertOptions {
+            rounding: Rounding::TowardZero,
+            ..ConvertOptions::default()
+        };
+        assert_eq!(
+            doubles
+                .convert_with(HXALayerDataType::HXA_LDT_INT32, &options)
+                .data,
+            LayerData::Int32(vec![0, -1, 300])
+        );
+        let options = ConvertOptions {
+            rounding: Rounding::Floor,
+            clamp: Some([-1.0, 100.0]),
+            ..ConvertOptions::default()
+        };
+        assert_eq!(
+            doubles
+                .convert_with(HXALayerDataType::HXA_LDT_INT32, &options)
+                .data,
+            LayerData::Int32(vec![0, -1, 100])
+        );
+    }
+
+    #[test]
+    fn normalized_integers() {
+        let unorm = ConvertOptions {
+            normalization: Normalization::Unorm,
+            ..ConvertOptions::default()
+        };
+        let floats = layer(LayerData::Float(vec![0.0, 0.5, 1.0, 2.0]));
+        let bytes = floats.convert_with(HXALayerDataType::HXA_LDT_UINT8, &unorm);
+        assert_eq!(bytes.data, LayerData::Uint8(vec![0, 128, 255, 255]));
+        assert_eq!(
+            bytes
+                .convert_with(HXALayerDataType::HXA_LDT_INT32, &unorm)
+                .data,
+            LayerData::Int32(vec![0, 1077952576, i32::MAX, i32::MAX])
+        );
+        let floor = ConvertOptions {
+            rounding: Rounding::Floor,
+            ..unorm
+        };
+        assert_eq!(
+            floats
+                .convert_with(HXALayerDataType::HXA_LDT_UINT8, &floor)
+                .data,
+            LayerData::Uint8(vec![0, 127, 255, 255])
+        );
+
+        let snorm = ConvertOptions {
+            normalization: Normalization::Snorm,
+            ..ConvertOptions::default()
+        };
+        let bytes = layer(LayerData::Uint8(vec![0, 255]));
+        assert_eq!(
+            bytes
+                .convert_with(HXALayerDataType::HXA_LDT_DOUBLE, &snorm)
+                .data,
+            LayerData::Double(vec![-1.0, 1.0])
+        );
+        let normals = layer(LayerData::Double(vec![-1.0, 0.0, 1.0]));
+        assert_eq!(
+            normals
+                .convert_with(HXALayerDataType::HXA_LDT_INT32, &snorm)
+                .data,
+            LayerData::Int32(vec![-i32::MAX, 0, i32::MAX])
+        );
+    }
+
+    #[test]
+    fn quantized_positions_round_trip() {
+        let mut geometry = cube();
+        let scaled: Vec<[f64; 3]> = geometry
+            .positions()
+            .unwrap()
+            .iter()
+            .map(|&[x, y, z]| [x * 3.0 - 1.0, y * 0.1, z * 0.0 + 2.0])
+            .collect();
+        geometry.set_positions(&scaled).unwrap();
+        let mut node = Node::from(geometry);
+
+        node.quantize_positions(16).unwrap();
+        let geometry = node.geometry().unwrap();
+        assert_eq!(
+            geometry.vertex_layer().unwrap().data_type(),
+            HXALayerDataType::HXA_LDT_INT32
+        );
+        assert_eq!(geometry.positions().unwrap()[6], [65535.0, 65535.0, 0.0]);
+        assert_eq!(
+            node.find_meta(convention::QUANTIZATION_BOUNDS)
+                .unwrap()
+                .as_double(),
+            Some(&[-1.0, 0.0, 2.0, 2.0, 0.10000000149011612, 2.0][..])
+        );
+        let scale = node
+            .find_meta(convention::QUANTIZATION_SCALE)
+            .unwrap()
+            .as_double()
+            .unwrap();
+        assert_eq!(scale[0], 3.0 / 65535.0);
+
+        node.quantize_positions(8).unwrap();
+        assert_eq!(
+            node.geometry().unwrap().positions().unwrap()[6],
+            [255.0, 255.0, 0.0]
+        );
+
+        node.dequantize_positions(HXALayerDataType::HXA_LDT_FLOAT)
+            .unwrap();
+        assert!(node.find_meta(convention::QUANTIZATION_SCALE).is_none());
+        let geometry = node.geometry().unwrap();
+        assert_eq!(
+            geometry.vertex_layer().unwrap().data_type(),
+            HXALayerDataType::HXA_LDT_FLOAT
+        );
+        for (position, expected) in geometry.positions().unwrap().iter().zip(&scaled) {
+            for axis in 0..3 {
+                assert!((position[axis] - expected[axis]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn quantized_positions_are_not_used_as_positions() {
+        let mut node = Node::from(cube());
+        node.quantize_positions(16).unwrap();
+        assert_eq!(node.update_bounds(), Err(Error::QuantizedPositions));
+        let mut file = crate::File::new();
+        file.nodes.push(node);
+        assert_eq!(
+            file.split_node(0, crate::SplitBy::Group),
+            Err(Error::QuantizedPositions)
+        );
+        assert_eq!(file.nodes.len(), 1);
+
+        file.nodes[0]
+            .dequantize_positions(HXALayerDataType::HXA_LDT_DOUBLE)
+            .unwrap();
+        file.nodes[0].update_bounds().unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+//! Moving attributes between the corner and vertex stacks.
+//!
+//! The same attribute, like `uv` or `color`, can be stored per corner or per vertex. Per corner
+//! storage can hold seams, where corners sharing a vertex have different values, which is what
+//! editors prefer. GPUs need everything per vertex, which means vertices have to be split along
+//! the seams.
+
+use std::collections::HashMap;
+
+use crate::{Error, GeometryNode, Layer, Result};
+
+impl GeometryNode {
+    /// Check if all corners sharing a vertex hold the same value in a corner layer.
+    pub fn is_corner_layer_continuous(&self, name: &str) -> Result<bool> {
+        let layer = self.attribute_corner_layer(name)?;
+        let corner_vertices = self.corner_vertices()?;
+        let components = layer.components as usize;
+
+        let mut first_corner = vec![None; self.vertex_count()];
+        for (corner, &vertex) in corner_vertices.iter().enumerate() {
+            match first_corner[vertex] {
+                None => first_corner[vertex] = Some(corner),
+                Some(first) => {
+                    if !layer.data.element_eq(components, first, corner) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Move a continuous corner layer to the vertex stack.
+    ///
+    /// Vertices that are not used by any corner get zeros. Fails with [`Error::Discontinuous`] if
+    /// the layer has seams, in which case [`split_vertices`](Self::split_vertices) has to be used.
+    pub fn promote_corner_layer(&mut self, name: &str) -> Result<()> {
+        if self.vertex_stack.find(name).is_some() {
+            return Err(Error::DuplicateLayer {
+                name: name.to_string(),
+            });
+        }
+        if !self.is_corner_layer_continuous(name)? {
+            return Err(Error::Discontinuous {
+                name: name.to_string(),
+            });
+        }
+
+        let mut first_corner = vec![None; self.vertex_count()];
+        for (corner, vertex) in self.corner_vertices()?.into_iter().enumerate() {
+            first_corner[vertex].get_or_insert(corner);
+        }
+
+        let layer = self
+            .corner_stack
+            .remove(name)
+            .expect("layer was found above");
+        let vertex_layer = layer.gather_optional(&first_corner);
+        self.vertex_stack.push(vertex_layer);
+
+        Ok(())
+    }
+
+    /// Move a vertex layer to the corner stack, copying the value of each vertex to its corners.
+    pub fn demote_vertex_layer(&mut self, name: &str) -> Result<()> {
+        if self.corner_stack.find(name).is_some() {
+            return Err(Error::DuplicateLayer {
+                name: name.to_string(),
+            });
+        }
+        match self.vertex_stack.position(name) {
+            Some(0) | None => {
+                return Err(Error::MissingLayer {
+                    name: name.to_string(),
+                })
+            }
+            Some(_) => {}
+        }
+
+        let corner_vertices = self.corner_vertices()?;
+        let layer = self
+            .vertex_stack
+            .remove(name)
+            .expect("layer was found above");
+        self.corner_stack.push(layer.gather(&corner_vertices));
+
+        Ok(())
+    }
+
+    /// Split vertices along seams so that every corner attribute can be stored per vertex.
+    ///
+    /// All corner layers except the reference layer are moved to the vertex stack. A vertex is
+    /// duplicated for every distinct combination of corner values it is used with. Original
+    /// vertices keep their index, new copies are appended after them.
+    ///
+    /// Returns the original vertex of every vertex in the node.
+    pub fn split_vertices(&mut self) -> Result<Vec<usize>> {
+        for layer in self.corner_stack.iter().skip(1) {
+            if self.vertex_stack.find(&layer.name).is_some() {
+                return Err(Error::DuplicateLayer {
+                    name: layer.name.clone(),
+                });
+            }
+        }
+
+        let corner_vertices = self.corner_vertices()?;
+        let vertex_count = self.vertex_count();
+        let corner_layers = &self.corner_stack.layers[1..];
+
+        let mut source_vertex: Vec<usize> = (0..vertex_count).collect();
+        let mut source_corner = vec![None; vertex_count];
+        let mut corner_targets = Vec::with_capacity(corner_vertices.len());
+        let mut keys: HashMap<Vec<u64>, usize> = HashMap::new();
+        for (corner, &vertex) in corner_vertices.iter().enumerate() {
+            let mut key = vec![vertex as u64];
+            for layer in corner_layers {
+                layer
+                    .data
+                    .push_element_bits(layer.components as usize, corner, &mut key);
+            }
+
+            let target = match keys.get(&key) {
+                Some(&target) => target,
+                None => {
+                    let target = if source_corner[vertex].is_none() {
+                        vertex
+                    } else {
+                        source_vertex.push(vertex);
+                        source_corner.push(None);
+                        source_vertex.len() - 1
+                    };
+                    source_corner[target] = Some(corner);
+                    keys.insert(key, target);
+                    target
+                }
+            };
+            corner_targets.push(target);
+        }
+
+        self.vertex_stack = self.vertex_stack.gather(&source_vertex);
+        let corner_layers: Vec<Layer> = self.corner_stack.layers.drain(1..).collect();
+        for layer in &corner_layers {
+            self.vertex_stack
+                .push(layer.gather_optional(&source_corner));
+        }
+        for (r, &target) in self.reference_mut()?.iter_mut().zip(&corner_targets) {
+            *r = if *r < 0 {
+                -(target as i32) - 1
+            } else {
+                target as i32
+            };
+        }
+
+        Ok(source_vertex)
+    }
+
+    fn attribute_corner_layer(&self, name: &str) -> Result<&Layer> {
+        match self.corner_stack.position(name) {
+            Some(0) | None => Err(Error::MissingLayer {
+                name: name.to_string(),
+            }),
+            Some(index) => Ok(&self.corner_stack.layers[index]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+    use crate::LayerData;
+
+    fn with_corner_layer(name: &str, components: u8, data: LayerData) -> GeometryNode {
+        let mut node = quad_and_triangle();
+        node.corner_stack.push(Layer::new(name, components, data));
+        node
+    }
+
+    #[test]
+    fn promote_continuous_layer() {
+        // Corners: [0, 1, 2, 3] [1, 4, 2]
+        let mut node = with_corner_layer(
+            "color",
+            1,
+            LayerData::Uint8(vec![10, 11, 12, 13, 11, 14, 12]),
+        );
+        assert!(node.is_corner_layer_continuous("color").unwrap());
+        node.promote_corner_layer("color").unwrap();
+
+        assert!(node.corner_stack.find("color").is_none());
+        assert_eq!(
+            node.vertex_stack.get("color").unwrap().data,
+            LayerData::Uint8(vec![10, 11, 12, 13, 14])
+        );
+        node.validate().unwrap();
+
+        node.demote_vertex_layer("color").unwrap();
+        assert_eq!(
+            node.corner_stack.get("color").unwrap().data,
+            LayerData::Uint8(vec![10, 11, 12, 13, 11, 14, 12])
+        );
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn promote_rejects_seams() {
+        let mut node = with_corner_layer(
+            "uv",
+            1,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 5.0, 4.0, 2.0]),
+        );
+        assert!(!node.is_corner_layer_continuous("uv").unwrap());
+        assert_eq!(
+            node.promote_corner_layer("uv"),
+            Err(Error::Discontinuous {
+                name: "uv".to_string()
+            })
+        );
+        assert!(node.corner_stack.find("uv").is_some());
+        assert_eq!(
+            node.promote_corner_layer("reference"),
+            Err(Error::MissingLayer {
+                name: "reference".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn split_vertices_along_seam() {
+        // Vertex 1 is a seam between the two faces, vertex 2 is continuous.
+        let mut node = with_corner_layer(
+            "uv",
+            1,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 5.0, 4.0, 2.0]),
+        );
+        let source = node.split_vertices().unwrap();
+
+        assert_eq!(source, vec![0, 1, 2, 3, 4, 1]);
+        assert_eq!(node.corner_stack.len(), 1);
+        assert_eq!(node.reference().unwrap(), &[0, 1, 2, -4, 5, 4, -3]);
+        assert_eq!(
+            node.vertex_stack.get("uv").unwrap().data,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0])
+        );
+        assert_eq!(node.positions().unwrap()[5], [1.0, 0.0, 0.0]);
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn split_vertices_keeps_unused_vertices() {
+        let mut node = GeometryNode::from_polygons(
+            &[[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [5.0; 3]],
+            &[vec![0, 1, 2]],
+        );
+        node.corner_stack
+            .push(Layer::new("uv", 1, LayerData::Double(vec![1.0, 2.0, 3.0])));
+        let source = node.split_vertices().unwrap();
+
+        assert_eq!(source, vec![0, 1, 2, 3]);
+        assert_eq!(
+            node.vertex_stack.get("uv").unwrap().data,
+            LayerData::Double(vec![1.0, 2.0, 3.0, 0.0])
+        );
+    }
+}
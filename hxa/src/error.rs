@@ -0,0 +1,210 @@
+use hxa_sys::{HXALayerDataType, HXANodeType};
+
+use crate::SelectionDomain;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors produced when HxA data does not follow the structure or conventions an operation needs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A layer the operation requires is not present in the stack.
+    MissingLayer { name: String },
+    /// A layer with the same name already exists in the destination stack.
+    DuplicateLayer { name: String },
+    /// A layer is stored with a type the operation cannot use.
+    LayerType {
+        name: String,
+        expected: HXALayerDataType,
+        actual: HXALayerDataType,
+    },
+    /// A layer has a component count the operation cannot use.
+    LayerComponents {
+        name: String,
+        expected: u8,
+        actual: u8,
+    },
+    /// A layer does not have the same number of elements as the rest of its stack.
+    LayerLength {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// A corner references a vertex outside of the vertex stack.
+    InvalidReference { corner: usize, vertex: i64 },
+    /// The reference layer does not end with a negative value, so its last polygon never closes.
+    UnterminatedPolygon,
+    /// A corner layer holds different values for corners sharing a vertex, so it cannot be stored
+    /// per vertex.
+    Discontinuous { name: String },
+    /// An entry of a GPU vertex layout could not be parsed.
+    InvalidLayout { entry: String },
+    /// The mesh has more vertices than the requested index format can address.
+    IndexOverflow { vertex_count: usize },
+    /// The operation only works on triangles, but a face has a different number of corners.
+    NotTriangulated { face: usize },
+    /// A node index is outside of the node array.
+    InvalidNode { node: usize },
+    /// A node is of a different type than the operation needs.
+    NodeType {
+        node: usize,
+        expected: HXANodeType,
+        actual: HXANodeType,
+    },
+    /// A node reference meta points at a node that is being removed.
+    RemovedReference {
+        node: usize,
+        meta: String,
+        reference: u32,
+    },
+    /// A new node order does not list every node exactly once.
+    InvalidNodeOrder,
+    /// A node has the same `name` as a node already in the file.
+    NameClash { name: String },
+    /// Two geometry nodes do not have the same layers, with the same types and components.
+    SchemaMismatch { name: String },
+    /// A skinning influence references a node that does not exist or is not a bone.
+    InvalidBone { vertex: usize, bone: i32 },
+    /// A skinning weight is negative or not a number.
+    InvalidWeight { vertex: usize },
+    /// A skin has more influences per vertex than a layer has components.
+    TooManyInfluences { influences: usize },
+    /// A node reference meta points at a node outside of the node array.
+    DanglingReference {
+        node: usize,
+        meta: String,
+        reference: u32,
+    },
+    /// A node is one of its own ancestors in the scene hierarchy.
+    HierarchyCycle { node: usize },
+    /// A node is listed as a child by more than one node, or its `parent` meta disagrees with the
+    /// `children` meta of another node.
+    ConflictingParent { node: usize },
+    /// Two selections, or a selection and an operation, are over different kinds of elements.
+    SelectionDomain {
+        expected: SelectionDomain,
+        actual: SelectionDomain,
+    },
+    /// A geometry node has no triangles to find surface points on.
+    EmptySurface,
+    /// A geometry node stores quantized positions, which the operation would read as steps
+    /// instead of positions. See [`Node::dequantize_positions`](crate::Node::dequantize_positions).
+    QuantizedPositions,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingLayer { name } => write!(f, "missing layer {:?}", name),
+            Error::DuplicateLayer { name } => write!(f, "layer {:?} already exists", name),
+            Error::LayerType {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "layer {:?} has type {:?}, expected {:?}",
+                name, actual, expected
+            ),
+            Error::LayerComponents {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "layer {:?} has {} components, expected {}",
+                name, actual, expected
+            ),
+            Error::LayerLength {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "layer {:?} has {} elements, expected {}",
+                name, actual, expected
+            ),
+            Error::InvalidReference { corner, vertex } => write!(
+                f,
+                "corner {} references vertex {} which does not exist",
+                corner, vertex
+            ),
+            Error::UnterminatedPolygon => write!(f, "the last polygon is not terminated"),
+            Error::Discontinuous { name } => write!(
+                f,
+                "layer {:?} has different values on corners sharing a vertex",
+                name
+            ),
+            Error::InvalidLayout { entry } => write!(f, "invalid vertex layout entry {:?}", entry),
+            Error::IndexOverflow { vertex_count } => write!(
+                f,
+                "{} vertices do not fit in the index format",
+                vertex_count
+            ),
+            Error::NotTriangulated { face } => write!(f, "face {} is not a triangle", face),
+            Error::InvalidNode { node } => write!(f, "node {} does not exist", node),
+            Error::NodeType {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {} has type {:?}, expected {:?}",
+                node, actual, expected
+            ),
+            Error::RemovedReference {
+                node,
+                meta,
+                reference,
+            } => write!(
+                f,
+                "meta {:?} of node {} references removed node {}",
+                meta, node, reference
+            ),
+            Error::InvalidNodeOrder => write!(f, "the node order is not a permutation"),
+            Error::NameClash { name } => write!(f, "a node named {:?} already exists", name),
+            Error::SchemaMismatch { name } => {
+                write!(
+                    f,
+                    "layer {:?} does not match between the geometry nodes",
+                    name
+                )
+            }
+            Error::InvalidBone { vertex, bone } => write!(
+                f,
+                "vertex {} is influenced by node {} which is not a bone",
+                vertex, bone
+            ),
+            Error::InvalidWeight { vertex } => {
+                write!(f, "vertex {} has an invalid skinning weight", vertex)
+            }
+            Error::TooManyInfluences { influences } => write!(
+                f,
+                "skin has {} influences per vertex, layers hold at most {}",
+                influences,
+                u8::MAX
+            ),
+            Error::DanglingReference {
+                node,
+                meta,
+                reference,
+            } => write!(
+                f,
+                "meta {:?} of node {} references node {} which does not exist",
+                meta, node, reference
+            ),
+            Error::HierarchyCycle { node } => write!(f, "node {} is its own ancestor", node),
+            Error::ConflictingParent { node } => {
+                write!(f, "node {} has more than one parent", node)
+            }
+            Error::SelectionDomain { expected, actual } => write!(
+                f,
+                "selection is over {:?} elements, expected {:?}",
+                actual, expected
+            ),
+            Error::EmptySurface => write!(f, "the geometry has no triangles"),
+            Error::QuantizedPositions => write!(f, "the positions are quantized"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,399 @@
+use hxa_sys::HXANodeType;
+
+use crate::{convention, Error, GeometryNode, Meta, MetaValue, Node, Result};
+
+/// What to do with node references to nodes that are removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReferencePolicy {
+    /// Fail with [`Error::RemovedReference`] and leave the file unchanged.
+    Fail,
+    /// Drop the references from their meta entries. Later references in the same entry move
+    /// forward, so entries where the position of a reference matters, like texture slots, can
+    /// change meaning.
+    Remove,
+    /// Replace the references with [`NULL_REFERENCE`](convention::NULL_REFERENCE), keeping every
+    /// other reference in its position.
+    Null,
+}
+
+/// An HxA file, holding an array of nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    /// The format version, see `HXA_VERSION_FORMAT`.
+    pub version: u8,
+    pub nodes: Vec<Node>,
+}
+
+impl Default for File {
+    fn default() -> Self {
+        Self {
+            version: hxa_sys::HXA_VERSION_FORMAT as u8,
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl File {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a node, or fail with [`Error::InvalidNode`].
+    pub fn node(&self, node: usize) -> Result<&Node> {
+        self.nodes.get(node).ok_or(Error::InvalidNode { node })
+    }
+
+    /// Get a node, or fail with [`Error::InvalidNode`].
+    pub fn node_mut(&mut self, node: usize) -> Result<&mut Node> {
+        self.nodes.get_mut(node).ok_or(Error::InvalidNode { node })
+    }
+
+    /// Get the geometry of a node, or fail if it is not a geometry node.
+    pub fn geometry(&self, node: usize) -> Result<&GeometryNode> {
+        let entry = self.node(node)?;
+        entry.geometry().ok_or(Error::NodeType {
+            node,
+            expected: HXANodeType::HXA_NT_GEOMETRY,
+            actual: entry.node_type(),
+        })
+    }
+
+    /// Get the geometry of a node, or fail if it is not a geometry node.
+    pub fn geometry_mut(&mut self, node: usize) -> Result<&mut GeometryNode> {
+        let entry = self.node_mut(node)?;
+        let actual = entry.node_type();
+        entry.geometry_mut().ok_or(Error::NodeType {
+            node,
+            expected: HXANodeType::HXA_NT_GEOMETRY,
+            actual,
+        })
+    }
+
+    /// Get the geometry of a node like [`geometry`](Self::geometry), also failing with
+    /// [`Error::QuantizedPositions`] if its positions are quantized.
+    pub(crate) fn unquantized_geometry(&self, node: usize) -> Result<&GeometryNode> {
+        let geometry = self.geometry(node)?;
+        self.nodes[node].expect_unquantized()?;
+        Ok(geometry)
+    }
+
+    /// Insert a node, moving the nodes after it back by one.
+    ///
+    /// References to the moved nodes are updated, references outside of the node array are not
+    /// changed. References in the inserted node are kept as they are, so they should already use
+    /// the new indices.
+    pub fn insert_node(&mut self, index: usize, node: Node) -> Result<()> {
+        let len = self.nodes.len();
+        if index > len {
+            return Err(Error::InvalidNode { node: index });
+        }
+        for entry in &mut self.nodes {
+            rewrite_references(&mut entry.meta, &mut |references| {
+                for reference in references {
+                    if (index..len).contains(&(*reference as usize)) {
+                        *reference += 1;
+                    }
+                }
+            });
+        }
+        self.nodes.insert(index, node);
+
+        Ok(())
+    }
+
+    /// Remove a node, moving the nodes after it forward by one and updating references to them.
+    ///
+    /// References to the removed node are handled according to `policy`. References outside of
+    /// the node array are not changed.
+    pub fn remove_node(&mut self, node: usize, policy: ReferencePolicy) -> Result<Node> {
+        self.node(node)?;
+        let mut keep = vec![true; self.nodes.len()];
+        keep[node] = false;
+        let mut removed = self.select_nodes(&keep, policy)?;
+
+        Ok(removed.remove(0))
+    }
+
+    /// Keep only the nodes for which `keep` returns true, updating references to the kept nodes.
+    pub fn retain_nodes(
+        &mut self,
+        mut keep: impl FnMut(&Node) -> bool,
+        policy: ReferencePolicy,
+    ) -> Result<()> {
+        let keep: Vec<bool> = self.nodes.iter().map(&mut keep).collect();
+        self.select_nodes(&keep, policy)?;
+
+        Ok(())
+    }
+
+    /// Reorder the nodes, updating every reference.
+    ///
+    /// `order` lists the current index of every node in the new order, and has to contain every
+    /// index exactly once, or the call fails with [`Error::InvalidNodeOrder`].
+    pub fn reorder_nodes(&mut self, order: &[usize]) -> Result<()> {
+        let mut old_to_new = vec![None; self.nodes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            match old_to_new.get_mut(old) {
+                Some(slot @ None) => *slot = Some(new as u32),
+                _ => return Err(Error::InvalidNodeOrder),
+            }
+        }
+        if order.len() != self.nodes.len() {
+            return Err(Error::InvalidNodeOrder);
+        }
+
+        let mut nodes: Vec<Option<Node>> = self.nodes.drain(..).map(Some).collect();
+        self.nodes = order
+            .iter()
+            .map(|&old| nodes[old].take().expect("order is a permutation"))
+            .collect();
+        for entry in &mut self.nodes {
+            rewrite_references(&mut entry.meta, &mut |references| {
+                for reference in references {
+                    if let Some(&Some(new)) = old_to_new.get(*reference as usize) {
+                        *reference = new;
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Remove the nodes that are not kept and update references, returning the removed nodes.
+    pub(crate) fn select_nodes(
+        &mut self,
+        keep: &[bool],
+        policy: ReferencePolicy,
+    ) -> Result<Vec<Node>> {
+        let mut old_to_new = Vec::with_capacity(keep.len());
+        let mut count = 0;
+        for &kept in keep {
+            old_to_new.push(kept.then_some(count));
+            count += kept as u32;
+        }
+        // References that were already dangling are left as they are.
+        let new_index = |reference: u32| match old_to_new.get(reference as usize) {
+            Some(&new) => new,
+            None => Some(reference),
+        };
+
+        if policy == ReferencePolicy::Fail {
+            for (node, entry) in self.nodes.iter().enumerate().filter(|&(i, _)| keep[i]) {
+                let mut broken = None;
+                visit_references(&entry.meta, &mut |name, references| {
+                    if let Some(&reference) = references.iter().find(|&&r| new_index(r).is_none()) {
+                        broken.get_or_insert((name.to_string(), reference));
+                    }
+                });
+                if let Some((meta, reference)) = broken {
+                    return Err(Error::RemovedReference {
+                        node,
+                        meta,
+                        reference,
+                    });
+                }
+            }
+        }
+
+        let mut kept = Vec::with_capacity(count as usize);
+        let mut removed = Vec::new();
+        for (node, entry) in self.nodes.drain(..).enumerate() {
+            if keep[node] {
+                kept.push(entry);
+            } else {
+                removed.push(entry);
+            }
+        }
+        for entry in &mut kept {
+            rewrite_references(&mut entry.meta, &mut |references| {
+                if policy == ReferencePolicy::Null {
+                    for reference in references {
+                        *reference = new_index(*reference).unwrap_or(convention::NULL_REFERENCE);
+                    }
+                    return;
+                }
+                references.retain_mut(|reference| match new_index(*reference) {
+                    Some(new) => {
+                        *reference = new;
+                        true
+                    }
+                    None => false,
+                });
+            });
+        }
+        self.nodes = kept;
+
+        Ok(removed)
+    }
+}
+
+/// Call `f` with the name and references of every node reference entry in a meta tree.
+pub(crate) fn visit_references(meta: &[Meta], f: &mut impl FnMut(&str, &[u32])) {
+    for entry in meta {
+        match &entry.value {
+            MetaValue::Node(references) => f(&entry.name, references),
+            MetaValue::Meta(children) => visit_references(children, f),
+            _ => {}
+        }
+    }
+}
+
+/// Call `f` with the references of every node reference entry in a meta tree.
+pub(crate) fn rewrite_references(meta: &mut [Meta], f: &mut impl FnMut(&mut Vec<u32>)) {
+    for entry in meta {
+        match &mut entry.value {
+            MetaValue::Node(references) => f(references),
+            MetaValue::Meta(children) => rewrite_references(children, f),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{convention, NodeContent};
+
+    /// Four named nodes, where node 0 references 2, and a nested entry of node 3 references 1
+    /// and 2.
+    fn file() -> File {
+        let mut file = File::new();
+        for name in ["a", "b", "c", "d"] {
+            let mut node = Node::new(NodeContent::MetaOnly);
+            node.set_name(name);
+            file.nodes.push(node);
+        }
+        file.nodes[0].set_meta(Meta::new(convention::CHILDREN, MetaValue::Node(vec![2])));
+        file.nodes[3].set_meta(Meta::new(
+            "material",
+            MetaValue::Meta(vec![Meta::new("textures", MetaValue::Node(vec![1, 2]))]),
+        ));
+        file
+    }
+
+    fn names(file: &File) -> Vec<&str> {
+        file.nodes.iter().map(|node| node.name().unwrap()).collect()
+    }
+
+    fn textures(file: &File, node: usize) -> &[u32] {
+        file.nodes[node]
+            .find_meta("material")
+            .unwrap()
+            .as_meta()
+            .unwrap()[0]
+            .as_node()
+            .unwrap()
+    }
+
+    #[test]
+    fn remove_node_updates_references() {
+        let mut file = file();
+        assert_eq!(
+            file.remove_node(1, ReferencePolicy::Fail),
+            Err(Error::RemovedReference {
+                node: 3,
+                meta: "textures".to_string(),
+                reference: 1
+            })
+        );
+        assert_eq!(names(&file), ["a", "b", "c", "d"]);
+
+        let removed = file.remove_node(1, ReferencePolicy::Remove).unwrap();
+        assert_eq!(removed.name(), Some("b"));
+        assert_eq!(names(&file), ["a", "c", "d"]);
+        assert_eq!(
+            file.nodes[0]
+                .find_meta(convention::CHILDREN)
+                .unwrap()
+                .as_node(),
+            Some(&[1][..])
+        );
+        assert_eq!(textures(&file, 2), &[1]);
+    }
+
+    #[test]
+    fn removed_references_can_be_nulled_in_place() {
+        let mut file = file();
+        file.remove_node(1, ReferencePolicy::Null).unwrap();
+        assert_eq!(names(&file), ["a", "c", "d"]);
+        assert_eq!(textures(&file, 2), &[convention::NULL_REFERENCE, 1]);
+
+        // Null references stay null, and do not break the scene hierarchy.
+        file.nodes[0].set_meta(Meta::new(
+            convention::CHILDREN,
+            MetaValue::Node(vec![convention::NULL_REFERENCE, 1]),
+        ));
+        file.remove_node(1, ReferencePolicy::Null).unwrap();
+        file.reorder_nodes(&[1, 0]).unwrap();
+        assert_eq!(textures(&file, 0), &[convention::NULL_REFERENCE; 2]);
+        assert_eq!(file.scene().unwrap().children(1), []);
+    }
+
+    #[test]
+    fn insert_and_reorder_nodes() {
+        let mut file = file();
+        file.insert_node(1, Node::new(NodeContent::MetaOnly))
+            .unwrap();
+        file.nodes[1].set_name("new");
+        assert_eq!(names(&file), ["a", "new", "b", "c", "d"]);
+        assert_eq!(textures(&file, 4), &[2, 3]);
+
+        file.reorder_nodes(&[4, 3, 2, 1, 0]).unwrap();
+        assert_eq!(names(&file), ["d", "c", "b", "new", "a"]);
+        assert_eq!(textures(&file, 0), &[2, 1]);
+        assert_eq!(
+            file.nodes[4]
+                .find_meta(convention::CHILDREN)
+                .unwrap()
+                .as_node(),
+            Some(&[1][..])
+        );
+        assert_eq!(
+            file.reorder_nodes(&[0, 0, 1, 2, 3]),
+            Err(Error::InvalidNodeOrder)
+        );
+        assert_eq!(file.reorder_nodes(&[0, 1]), Err(Error::InvalidNodeOrder));
+    }
+
+    #[test]
+    fn insert_node_leaves_dangling_references_alone() {
+        let mut file = file();
+        let missing = vec![4, 9, convention::NULL_REFERENCE];
+        file.nodes[0].set_meta(Meta::new("missing", MetaValue::Node(missing.clone())));
+        file.insert_node(0, Node::new(NodeContent::MetaOnly))
+            .unwrap();
+        let references = file.nodes[1].find_meta("missing").unwrap().as_node();
+        assert_eq!(references, Some(&missing[..]));
+    }
+
+    #[test]
+    fn retain_nodes_keeps_valid_references() {
+        let mut file = file();
+        file.retain_nodes(|node| node.name() != Some("a"), ReferencePolicy::Fail)
+            .unwrap();
+        assert_eq!(names(&file), ["b", "c", "d"]);
+        assert_eq!(textures(&file, 2), &[0, 1]);
+
+        file.retain_nodes(|node| node.name() == Some("d"), ReferencePolicy::Remove)
+            .unwrap();
+        assert_eq!(textures(&file, 0), &[] as &[u32]);
+    }
+
+    #[test]
+    fn dangling_references_are_left_alone() {
+        let mut file = file();
+        file.nodes[2].set_meta(Meta::new("target", MetaValue::Node(vec![9])));
+        file.remove_node(3, ReferencePolicy::Fail).unwrap();
+        assert_eq!(
+            file.nodes[2].find_meta("target").unwrap().as_node(),
+            Some(&[9][..])
+        );
+
+        file.remove_node(0, ReferencePolicy::Remove).unwrap();
+        assert_eq!(
+            file.nodes[1].find_meta("target").unwrap().as_node(),
+            Some(&[9][..])
+        );
+    }
+}
@@ -0,0 +1,604 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use hxa_sys::HXALayerDataType;
+
+use crate::{convention, Error, Layer, LayerData, LayerStack, Result};
+
+/// A polygon mesh stored as vertex, corner, edge and face layer stacks.
+///
+/// The first vertex layer is always the 3 component `vertex` layer holding positions, and the
+/// first corner layer is always the 1 component int32 `reference` layer. The reference layer
+/// stores the vertex used by each corner, with the last corner of every polygon stored as
+/// `-vertex - 1`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeometryNode {
+    /// Stack of vertex arrays. The first layer is always the vertex positions.
+    pub vertex_stack: LayerStack,
+    /// Stack of corner arrays. The first layer is always the reference layer.
+    pub corner_stack: LayerStack,
+    /// Stack of edge arrays, with one element per corner.
+    pub edge_stack: LayerStack,
+    /// Stack of per polygon data.
+    pub face_stack: LayerStack,
+}
+
+impl GeometryNode {
+    /// Create a node from a vertex layer and an encoded reference layer.
+    pub fn new(vertex: Layer, reference: Vec<i32>) -> Self {
+        let mut vertex_stack = LayerStack::new();
+        vertex_stack.push(vertex);
+        let mut corner_stack = LayerStack::new();
+        corner_stack.push(Layer::new(
+            convention::BASE_CORNER_LAYER_NAME,
+            convention::BASE_CORNER_LAYER_COMPONENTS,
+            LayerData::Int32(reference),
+        ));
+
+        Self {
+            vertex_stack,
+            corner_stack,
+            edge_stack: LayerStack::new(),
+            face_stack: LayerStack::new(),
+        }
+    }
+
+    /// Create a node with float positions from a list of polygons.
+    pub fn from_polygons<P: AsRef<[u32]>>(positions: &[[f32; 3]], polygons: &[P]) -> Self {
+        let vertex = Layer::new(
+            convention::BASE_VERTEX_LAYER_NAME,
+            convention::BASE_VERTEX_LAYER_COMPONENTS,
+            LayerData::Float(positions.iter().flatten().copied().collect()),
+        );
+
+        Self::new(vertex, encode_reference(polygons))
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_stack.layers.first().map_or(0, Layer::len)
+    }
+
+    pub fn corner_count(&self) -> usize {
+        self.corner_stack.layers.first().map_or(0, Layer::len)
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.reference()
+            .map_or(0, |reference| reference.iter().filter(|&&r| r < 0).count())
+    }
+
+    /// The base vertex layer holding positions.
+    pub fn vertex_layer(&self) -> Result<&Layer> {
+        self.vertex_stack
+            .layers
+            .first()
+            .ok_or_else(|| Error::MissingLayer {
+                name: convention::BASE_VERTEX_LAYER_NAME.to_string(),
+            })
+    }
+
+    /// The encoded reference layer.
+    pub fn reference(&self) -> Result<&[i32]> {
+        let layer = self
+            .corner_stack
+            .layers
+            .first()
+            .ok_or_else(|| Error::MissingLayer {
+                name: convention::BASE_CORNER_LAYER_NAME.to_string(),
+            })?;
+        match &layer.data {
+            LayerData::Int32(reference) => Ok(reference),
+            data => Err(Error::LayerType {
+                name: layer.name.clone(),
+                expected: HXALayerDataType::HXA_LDT_INT32,
+                actual: data.data_type(),
+            }),
+        }
+    }
+
+    pub(crate) fn reference_mut(&mut self) -> Result<&mut Vec<i32>> {
+        self.reference()?;
+        match &mut self.corner_stack.layers[0].data {
+            LayerData::Int32(reference) => Ok(reference),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The vertex positions, read as doubles regardless of how they are stored.
+    pub fn positions(&self) -> Result<Vec<[f64; 3]>> {
+        self.vertex_layer()?.to_vec3()
+    }
+
+    /// Overwrite the vertex positions, keeping the stored type of the vertex layer.
+    pub fn set_positions(&mut self, positions: &[[f64; 3]]) -> Result<()> {
+        let vertex_count = self.vertex_count();
+        let layer = self
+            .vertex_stack
+            .layers
+            .first_mut()
+            .ok_or_else(|| Error::MissingLayer {
+                name: convention::BASE_VERTEX_LAYER_NAME.to_string(),
+            })?;
+        layer.expect_components(3)?;
+        if positions.len() != vertex_count {
+            return Err(Error::LayerLength {
+                name: layer.name.clone(),
+                expected: vertex_count,
+                actual: positions.len(),
+            });
+        }
+        for (index, position) in positions.iter().enumerate() {
+            for (component, &value) in position.iter().enumerate() {
+                layer.set(index, component, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The range of corners used by each face.
+    pub fn faces(&self) -> Result<Vec<Range<usize>>> {
+        let reference = self.reference()?;
+        let mut faces = Vec::new();
+        let mut start = 0;
+        for (corner, &r) in reference.iter().enumerate() {
+            if r < 0 {
+                faces.push(start..corner + 1);
+                start = corner + 1;
+            }
+        }
+        if start != reference.len() {
+            return Err(Error::UnterminatedPolygon);
+        }
+
+        Ok(faces)
+    }
+
+    /// The face that owns each corner.
+    pub fn corner_faces(&self) -> Result<Vec<usize>> {
+        let mut corner_faces = vec![0; self.corner_count()];
+        for (face, range) in self.faces()?.into_iter().enumerate() {
+            corner_faces[range].fill(face);
+        }
+
+        Ok(corner_faces)
+    }
+
+    /// The vertex used by each corner, with the polygon terminators decoded.
+    pub fn corner_vertices(&self) -> Result<Vec<usize>> {
+        let vertex_count = self.vertex_count();
+        self.reference()?
+            .iter()
+            .enumerate()
+            .map(|(corner, &r)| {
+                let vertex = decode_reference(r);
+                if vertex < vertex_count {
+                    Ok(vertex)
+                } else {
+                    Err(Error::InvalidReference {
+                        corner,
+                        vertex: vertex as i64,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// The vertices of each polygon.
+    pub fn polygons(&self) -> Result<Vec<Vec<usize>>> {
+        let corner_vertices = self.corner_vertices()?;
+        Ok(self
+            .faces()?
+            .into_iter()
+            .map(|range| corner_vertices[range].to_vec())
+            .collect())
+    }
+
+    /// Check that the node follows the hard conventions and that every stack is consistent.
+    pub fn validate(&self) -> Result<()> {
+        let vertex = self.vertex_layer()?;
+        if vertex.name != convention::BASE_VERTEX_LAYER_NAME {
+            return Err(Error::MissingLayer {
+                name: convention::BASE_VERTEX_LAYER_NAME.to_string(),
+            });
+        }
+        vertex.expect_components(convention::BASE_VERTEX_LAYER_COMPONENTS)?;
+        self.reference()?;
+        let reference = &self.corner_stack.layers[0];
+        if reference.name != convention::BASE_CORNER_LAYER_NAME {
+            return Err(Error::MissingLayer {
+                name: convention::BASE_CORNER_LAYER_NAME.to_string(),
+            });
+        }
+        reference.expect_components(convention::BASE_CORNER_LAYER_COMPONENTS)?;
+        self.faces()?;
+        self.corner_vertices()?;
+
+        let vertex_count = self.vertex_count();
+        let corner_count = self.corner_count();
+        let face_count = self.face_count();
+        for (stack, expected) in [
+            (&self.vertex_stack, vertex_count),
+            (&self.corner_stack, corner_count),
+            (&self.edge_stack, corner_count),
+            (&self.face_stack, face_count),
+        ] {
+            for layer in stack {
+                if layer.data.len() != expected * layer.components as usize {
+                    return Err(Error::LayerLength {
+                        name: layer.name.clone(),
+                        expected,
+                        actual: layer.len(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove vertices that are not used by any corner, returning how many were removed.
+    pub fn remove_unused_vertices(&mut self) -> Result<usize> {
+        let mut used = vec![false; self.vertex_count()];
+        for vertex in self.corner_vertices()? {
+            used[vertex] = true;
+        }
+        let kept: Vec<usize> = (0..used.len()).filter(|&v| used[v]).collect();
+        let removed = used.len() - kept.len();
+        if removed > 0 {
+            self.reorder_vertices(&kept)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Rebuild the vertex stack from the vertices at `new_to_old`, updating the reference layer.
+    ///
+    /// Every vertex used by a corner has to appear exactly once in `new_to_old`.
+    pub(crate) fn reorder_vertices(&mut self, new_to_old: &[usize]) -> Result<()> {
+        let mut old_to_new = vec![u32::MAX; self.vertex_count()];
+        for (new, &old) in new_to_old.iter().enumerate() {
+            old_to_new[old] = new as u32;
+        }
+        self.corner_vertices()?;
+
+        self.vertex_stack = self.vertex_stack.gather(new_to_old);
+        for r in self.reference_mut()? {
+            let new = old_to_new[decode_reference(*r)];
+            debug_assert_ne!(new, u32::MAX, "a used vertex was removed");
+            *r = if *r < 0 {
+                -(new as i32) - 1
+            } else {
+                new as i32
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Fill the `neighbour` edge layer with the corner starting the same edge in the opposite
+    /// direction, or -1 for boundary edges.
+    ///
+    /// The edge of a corner runs from its vertex to the vertex of the next corner in the polygon.
+    pub fn generate_neighbours(&mut self) -> Result<()> {
+        let corner_vertices = self.corner_vertices()?;
+        let faces = self.faces()?;
+
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for face in &faces {
+            for corner in face.clone() {
+                let next = next_corner(face, corner);
+                edges
+                    .entry((corner_vertices[corner], corner_vertices[next]))
+                    .or_insert(corner);
+            }
+        }
+
+        let mut neighbour = vec![-1; corner_vertices.len()];
+        for face in &faces {
+            for corner in face.clone() {
+                let next = next_corner(face, corner);
+                if let Some(&opposite) =
+                    edges.get(&(corner_vertices[next], corner_vertices[corner]))
+                {
+                    neighbour[corner] = opposite as i32;
+                }
+            }
+        }
+
+        self.edge_stack.insert(Layer::new(
+            convention::EDGE_NEIGHBOUR_LAYER_NAME,
+            1,
+            LayerData::Int32(neighbour),
+        ));
+
+        Ok(())
+    }
+
+    /// The crease sharpness of the edge starting at each corner, read from the `creases` layer of
+    /// the edge stack, or of the corner stack if the edge stack has none. Zero means smooth.
+    pub fn edge_creases(&self) -> Vec<f64> {
+        let layer = self
+            .edge_stack
+            .find(convention::LAYER_CREASES)
+            .or_else(|| self.corner_stack.find(convention::LAYER_CREASES));
+        match layer {
+            Some(layer) if layer.len() == self.corner_count() => (0..layer.len())
+                .map(|corner| layer.get(corner, 0))
+                .collect(),
+            _ => vec![0.0; self.corner_count()],
+        }
+    }
+
+    /// Build a node with the same vertices from a new list of faces.
+    ///
+    /// Each new face is given as the face of this node it copies its face layers from, and the
+    /// corners of this node it copies its corner and edge layers from. The `neighbour` layer is
+    /// regenerated if present.
+    pub(crate) fn rebuild_faces(&self, faces: &[(usize, Vec<usize>)]) -> Result<GeometryNode> {
+        let corner_vertices = self.corner_vertices()?;
+
+        let mut reference = Vec::new();
+        let mut corners = Vec::new();
+        for (_, face_corners) in faces {
+            if let Some((&last, rest)) = face_corners.split_last() {
+                reference.extend(rest.iter().map(|&c| corner_vertices[c] as i32));
+                reference.push(-(corner_vertices[last] as i32) - 1);
+                corners.extend_from_slice(face_corners);
+            }
+        }
+        let source_faces: Vec<usize> = faces
+            .iter()
+            .filter(|(_, face_corners)| !face_corners.is_empty())
+            .map(|&(face, _)| face)
+            .collect();
+
+        let mut corner_stack = LayerStack::new();
+        corner_stack.push(Layer::new(
+            self.corner_stack.layers[0].name.clone(),
+            convention::BASE_CORNER_LAYER_COMPONENTS,
+            LayerData::Int32(reference),
+        ));
+        for layer in self.corner_stack.iter().skip(1) {
+            corner_stack.push(layer.gather(&corners));
+        }
+
+        let mut node = GeometryNode {
+            vertex_stack: self.vertex_stack.clone(),
+            corner_stack,
+            edge_stack: self.edge_stack.gather(&corners),
+            face_stack: self.face_stack.gather(&source_faces),
+        };
+        if node
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            node.generate_neighbours()?;
+        }
+
+        Ok(node)
+    }
+}
+
+/// The corner after `corner` in a face, wrapping around to the first corner.
+pub(crate) fn next_corner(face: &Range<usize>, corner: usize) -> usize {
+    if corner + 1 == face.end {
+        face.start
+    } else {
+        corner + 1
+    }
+}
+
+/// Encode polygons as a reference layer, storing the last index of each polygon as `-index - 1`.
+pub fn encode_reference<P: AsRef<[u32]>>(polygons: &[P]) -> Vec<i32> {
+    let mut reference = Vec::new();
+    for polygon in polygons {
+        let polygon = polygon.as_ref();
+        if let Some((&last, rest)) = polygon.split_last() {
+            reference.extend(rest.iter().map(|&v| v as i32));
+            reference.push(-(last as i32) - 1);
+        }
+    }
+
+    reference
+}
+
+/// Decode a value of the reference layer into a vertex index.
+pub fn decode_reference(reference: i32) -> usize {
+    if reference < 0 {
+        (-(reference as i64) - 1) as usize
+    } else {
+        reference as usize
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn quad_and_triangle() -> GeometryNode {
+        GeometryNode::from_polygons(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [2.0, 0.0, 0.0],
+            ],
+            &[vec![0, 1, 2, 3], vec![1, 4, 2]],
+        )
+    }
+
+    /// A flat grid of `size` by `size` quads in the XY plane, each split into two triangles.
+    pub(crate) fn triangle_grid(size: u32) -> GeometryNode {
+        let mut positions = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                positions.push([x as f32, y as f32, 0.0]);
+            }
+        }
+        let mut triangles = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let v = y * (size + 1) + x;
+                triangles.push(vec![v, v + 1, v + size + 2]);
+                triangles.push(vec![v, v + size + 2, v + size + 1]);
+            }
+        }
+
+        GeometryNode::from_polygons(&positions, &triangles)
+    }
+
+    /// A closed unit cube made of 6 outward facing quads.
+    pub(crate) fn cube() -> GeometryNode {
+        GeometryNode::from_polygons(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 0.0, 1.0],
+                [1.0, 1.0, 1.0],
+                [0.0, 1.0, 1.0],
+            ],
+            &[
+                vec![0, 3, 2, 1],
+                vec![4, 5, 6, 7],
+                vec![0, 1, 5, 4],
+                vec![1, 2, 6, 5],
+                vec![2, 3, 7, 6],
+                vec![3, 0, 4, 7],
+            ],
+        )
+    }
+
+    #[test]
+    fn reference_matches_format_example() {
+        let node = quad_and_triangle();
+
+        assert_eq!(node.reference().unwrap(), &[0, 1, 2, -4, 1, 4, -3]);
+        assert_eq!(node.vertex_count(), 5);
+        assert_eq!(node.corner_count(), 7);
+        assert_eq!(node.face_count(), 2);
+        assert_eq!(node.faces().unwrap(), vec![0..4, 4..7]);
+        assert_eq!(
+            node.polygons().unwrap(),
+            vec![vec![0, 1, 2, 3], vec![1, 4, 2]]
+        );
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_bad_data() {
+        let mut node = quad_and_triangle();
+        node.reference_mut().unwrap()[6] = 0;
+        assert_eq!(node.validate(), Err(Error::UnterminatedPolygon));
+
+        let mut node = quad_and_triangle();
+        node.reference_mut().unwrap()[1] = 9;
+        assert_eq!(
+            node.validate(),
+            Err(Error::InvalidReference {
+                corner: 1,
+                vertex: 9
+            })
+        );
+
+        let mut node = quad_and_triangle();
+        node.face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(vec![0])));
+        assert!(matches!(
+            node.validate(),
+            Err(Error::LayerLength {
+                expected: 2,
+                actual: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn set_positions_keeps_type() {
+        let mut node = quad_and_triangle();
+        let mut positions = node.positions().unwrap();
+        positions[4] = [3.0, 0.5, 0.25];
+        node.set_positions(&positions).unwrap();
+
+        assert_eq!(
+            node.vertex_layer().unwrap().data_type(),
+            HXALayerDataType::HXA_LDT_FLOAT
+        );
+        assert_eq!(node.positions().unwrap()[4], [3.0, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn remove_unused_vertices_compacts() {
+        let mut node = GeometryNode::from_polygons(
+            &[
+                [9.0; 3],
+                [0.0; 3],
+                [9.0; 3],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ],
+            &[vec![1, 3, 4]],
+        );
+
+        assert_eq!(node.remove_unused_vertices().unwrap(), 2);
+        assert_eq!(node.reference().unwrap(), &[0, 1, -3]);
+        assert_eq!(
+            node.positions().unwrap(),
+            vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        );
+        assert_eq!(node.remove_unused_vertices().unwrap(), 0);
+    }
+
+    #[test]
+    fn generate_neighbours_pairs_shared_edge() {
+        // Corners: [0, 1, 2, 3] [1, 4, 2], the edge 1 -> 2 is shared with 2 -> 1.
+        let mut node = quad_and_triangle();
+        node.generate_neighbours().unwrap();
+
+        assert_eq!(
+            node.edge_stack.get("neighbour").unwrap().data,
+            LayerData::Int32(vec![-1, 6, -1, -1, -1, -1, 1])
+        );
+    }
+
+    #[test]
+    fn rebuild_faces_copies_layers() {
+        let mut node = quad_and_triangle();
+        node.face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(vec![7, 8])));
+        node.corner_stack.push(Layer::new(
+            "uv",
+            1,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        ));
+        node.generate_neighbours().unwrap();
+
+        let rebuilt = node
+            .rebuild_faces(&[(1, vec![4, 5, 6]), (0, vec![0, 1, 2]), (0, vec![0, 2, 3])])
+            .unwrap();
+
+        assert_eq!(
+            rebuilt.reference().unwrap(),
+            &[1, 4, -3, 0, 1, -3, 0, 2, -4]
+        );
+        assert_eq!(
+            rebuilt.corner_stack.get("uv").unwrap().data,
+            LayerData::Float(vec![4.0, 5.0, 6.0, 0.0, 1.0, 2.0, 0.0, 2.0, 3.0])
+        );
+        assert_eq!(
+            rebuilt.face_stack.get("material").unwrap().data,
+            LayerData::Int32(vec![8, 7, 7])
+        );
+        assert_eq!(
+            rebuilt.edge_stack.get("neighbour").unwrap().data,
+            LayerData::Int32(vec![-1, -1, 4, -1, 2, 6, 5, -1, -1])
+        );
+        rebuilt.validate().unwrap();
+    }
+}
@@ -0,0 +1,635 @@
+//! Export of geometry nodes as vertex and index buffers ready for upload to a GPU.
+//!
+//! A [`GpuLayout`] lists the layers to export and the format to store each of them in, and can be
+//! parsed from a string like `"vertex:f32x3, normal:snorm16x4, uv:f16x2"`.
+//!
+//! Every attribute starts at a 4 byte aligned offset and the vertex stride is a multiple of 4,
+//! as required by most graphics APIs. All values are stored in little endian byte order.
+
+use std::fmt;
+use std::str::FromStr;
+
+use hxa_sys::HXALayerDataType;
+
+use crate::{Error, GeometryNode, Layer, Result};
+
+/// The type of each component of a vertex attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScalarFormat {
+    F32,
+    F16,
+    /// Signed 16 bit integer mapping [-1, 1] to [-32767, 32767].
+    Snorm16,
+    /// Unsigned 16 bit integer mapping [0, 1] to [0, 65535].
+    Unorm16,
+    /// Signed 8 bit integer mapping [-1, 1] to [-127, 127].
+    Snorm8,
+    /// Unsigned 8 bit integer mapping [0, 1] to [0, 255].
+    Unorm8,
+    U32,
+    I32,
+    U16,
+    I16,
+    U8,
+    I8,
+}
+
+impl ScalarFormat {
+    const NAMES: [(&'static str, ScalarFormat); 12] = [
+        ("f32", ScalarFormat::F32),
+        ("f16", ScalarFormat::F16),
+        ("snorm16", ScalarFormat::Snorm16),
+        ("unorm16", ScalarFormat::Unorm16),
+        ("snorm8", ScalarFormat::Snorm8),
+        ("unorm8", ScalarFormat::Unorm8),
+        ("u32", ScalarFormat::U32),
+        ("i32", ScalarFormat::I32),
+        ("u16", ScalarFormat::U16),
+        ("i16", ScalarFormat::I16),
+        ("u8", ScalarFormat::U8),
+        ("i8", ScalarFormat::I8),
+    ];
+
+    /// The size of a single component in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ScalarFormat::F32 | ScalarFormat::U32 | ScalarFormat::I32 => 4,
+            ScalarFormat::F16
+            | ScalarFormat::Snorm16
+            | ScalarFormat::Unorm16
+            | ScalarFormat::U16
+            | ScalarFormat::I16 => 2,
+            ScalarFormat::Snorm8 | ScalarFormat::Unorm8 | ScalarFormat::U8 | ScalarFormat::I8 => 1,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(_, format)| *format == self)
+            .map(|(name, _)| *name)
+            .expect("every format is named")
+    }
+
+    /// Check if the format maps [0, 1] or [-1, 1] to the range of an integer.
+    pub fn is_normalized(self) -> bool {
+        matches!(
+            self,
+            ScalarFormat::Snorm16
+                | ScalarFormat::Unorm16
+                | ScalarFormat::Snorm8
+                | ScalarFormat::Unorm8
+        )
+    }
+
+    fn encode(self, value: f64, out: &mut Vec<u8>) {
+        match self {
+            ScalarFormat::F32 => out.extend_from_slice(&(value as f32).to_le_bytes()),
+            ScalarFormat::F16 => {
+                out.extend_from_slice(&f32_to_f16_bits(value as f32).to_le_bytes())
+            }
+            ScalarFormat::Snorm16 => out.extend_from_slice(
+                &((value.clamp(-1.0, 1.0) * i16::MAX as f64).round() as i16).to_le_bytes(),
+            ),
+            ScalarFormat::Unorm16 => out.extend_from_slice(
+                &((value.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16).to_le_bytes(),
+            ),
+            ScalarFormat::Snorm8 => out.extend_from_slice(
+                &((value.clamp(-1.0, 1.0) * i8::MAX as f64).round() as i8).to_le_bytes(),
+            ),
+            ScalarFormat::Unorm8 => {
+                out.push((value.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8)
+            }
+            ScalarFormat::U32 => out.extend_from_slice(&(value.round() as u32).to_le_bytes()),
+            ScalarFormat::I32 => out.extend_from_slice(&(value.round() as i32).to_le_bytes()),
+            ScalarFormat::U16 => out.extend_from_slice(&(value.round() as u16).to_le_bytes()),
+            ScalarFormat::I16 => out.extend_from_slice(&(value.round() as i16).to_le_bytes()),
+            ScalarFormat::U8 => out.push(value.round() as u8),
+            ScalarFormat::I8 => out.extend_from_slice(&(value.round() as i8).to_le_bytes()),
+        }
+    }
+}
+
+/// The format of a vertex attribute, like `f32x3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexFormat {
+    pub scalar: ScalarFormat,
+    /// From 1 to 4.
+    pub components: u8,
+}
+
+impl VertexFormat {
+    pub fn new(scalar: ScalarFormat, components: u8) -> Self {
+        Self { scalar, components }
+    }
+
+    /// The size of an attribute in bytes, without padding.
+    pub fn size(&self) -> usize {
+        self.scalar.size() * self.components as usize
+    }
+}
+
+impl FromStr for VertexFormat {
+    type Err = Error;
+
+    /// Parse a format like `f32x3` or `unorm8x4`. A missing component count means 1.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidLayout {
+            entry: s.to_string(),
+        };
+        let (scalar, components) = match s.trim().split_once('x') {
+            Some((scalar, components)) => (scalar, components.parse().map_err(|_| invalid())?),
+            None => (s.trim(), 1),
+        };
+        let scalar = ScalarFormat::NAMES
+            .iter()
+            .find(|(name, _)| *name == scalar)
+            .map(|(_, format)| *format)
+            .ok_or_else(invalid)?;
+        if !(1..=4).contains(&components) {
+            return Err(invalid());
+        }
+
+        Ok(Self::new(scalar, components))
+    }
+}
+
+impl fmt::Display for VertexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.scalar.name(), self.components)
+    }
+}
+
+/// A layer to export and the format to store it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub name: String,
+    pub format: VertexFormat,
+}
+
+impl VertexAttribute {
+    pub fn new(name: impl Into<String>, format: VertexFormat) -> Self {
+        Self {
+            name: name.into(),
+            format,
+        }
+    }
+}
+
+/// How the attributes of the vertices are arranged in the vertex buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VertexStorage {
+    /// All attributes of a vertex are stored next to each other.
+    #[default]
+    Interleaved,
+    /// Each attribute is stored in its own stream, one after the other.
+    Planar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+}
+
+/// The attributes and storage of an exported mesh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuLayout {
+    pub attributes: Vec<VertexAttribute>,
+    pub storage: VertexStorage,
+    /// The index format, or `None` to use 16 bit indices whenever the vertices fit.
+    pub index_format: Option<IndexFormat>,
+}
+
+impl GpuLayout {
+    pub fn new(attributes: Vec<VertexAttribute>) -> Self {
+        Self {
+            attributes,
+            storage: VertexStorage::default(),
+            index_format: None,
+        }
+    }
+}
+
+impl FromStr for GpuLayout {
+    type Err = Error;
+
+    /// Parse a comma separated list of `name:format` entries, stored interleaved.
+    fn from_str(s: &str) -> Result<Self> {
+        let attributes = s
+            .split(',')
+            .map(|entry| {
+                let (name, format) = entry.split_once(':').ok_or_else(|| Error::InvalidLayout {
+                    entry: entry.trim().to_string(),
+                })?;
+                Ok(VertexAttribute::new(name.trim(), format.parse()?))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self::new(attributes))
+    }
+}
+
+/// Where an attribute is stored in the vertex buffer of a [`GpuMesh`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuAttribute {
+    pub name: String,
+    pub format: VertexFormat,
+    /// Byte offset of the attribute of the first vertex.
+    pub offset: usize,
+    /// Bytes between the attributes of two consecutive vertices.
+    pub stride: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexBuffer {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl IndexBuffer {
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            IndexBuffer::U16(_) => IndexFormat::U16,
+            IndexBuffer::U32(_) => IndexFormat::U32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexBuffer::U16(indices) => indices.len(),
+            IndexBuffer::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        match self {
+            IndexBuffer::U16(indices) => indices[index] as u32,
+            IndexBuffer::U32(indices) => indices[index],
+        }
+    }
+
+    /// The indices in little endian byte order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            IndexBuffer::U16(indices) => indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+            IndexBuffer::U32(indices) => indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+        }
+    }
+}
+
+/// A triangle list with every attribute stored per vertex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuMesh {
+    pub vertex_buffer: Vec<u8>,
+    pub vertex_count: usize,
+    pub attributes: Vec<GpuAttribute>,
+    /// Three indices per triangle, in the winding of the source polygons.
+    pub index_buffer: IndexBuffer,
+}
+
+impl GeometryNode {
+    /// Export the node as a triangle list with vertex and index buffers.
+    ///
+    /// Each attribute of the layout is looked up by name in the vertex, corner and face stacks,
+    /// in that order. The node is triangulated and vertices are split wherever a corner or face
+    /// attribute has a seam. Layers with fewer components than the format are padded with zeros
+    /// and a fourth component of 1, so RGB colors become opaque, and layers with more components
+    /// fail with [`Error::LayerComponents`]. Uint8 and int32 layers exported in a normalized
+    /// format are divided by the largest value of their type, so a uint8 color of 255 stays 1.
+    pub fn to_gpu_mesh(&self, layout: &GpuLayout) -> Result<GpuMesh> {
+        let mut node = self.clone();
+        node.triangulate()?;
+        node.edge_stack.layers.clear();
+
+        let corner_faces = node.corner_faces()?;
+        let mut corner_layers = Vec::new();
+        for attribute in &layout.attributes {
+            let layer = if let Some(layer) = node.vertex_stack.find(&attribute.name) {
+                layer
+            } else if let Some(layer) = node
+                .corner_stack
+                .iter()
+                .skip(1)
+                .find(|layer| layer.name == attribute.name)
+            {
+                corner_layers.push(layer.clone());
+                layer
+            } else if let Some(layer) = node.face_stack.find(&attribute.name) {
+                corner_layers.push(layer.gather(&corner_faces));
+                layer
+            } else {
+                return Err(Error::MissingLayer {
+                    name: attribute.name.clone(),
+                });
+            };
+            if layer.components > attribute.format.components {
+                return Err(Error::LayerComponents {
+                    name: layer.name.clone(),
+                    expected: attribute.format.components,
+                    actual: layer.components,
+                });
+            }
+        }
+        node.corner_stack.layers.truncate(1);
+        node.corner_stack.layers.extend(corner_layers);
+        node.face_stack.layers.clear();
+        node.split_vertices()?;
+        node.remove_unused_vertices()?;
+
+        let vertex_count = node.vertex_count();
+        let layers: Vec<&Layer> = layout
+            .attributes
+            .iter()
+            .map(|attribute| node.vertex_stack.get(&attribute.name))
+            .collect::<Result<_>>()?;
+        let sizes: Vec<usize> = layout
+            .attributes
+            .iter()
+            .map(|attribute| align4(attribute.format.size()))
+            .collect();
+
+        let mut attributes = Vec::with_capacity(layers.len());
+        let mut vertex_buffer = Vec::with_capacity(sizes.iter().sum::<usize>() * vertex_count);
+        match layout.storage {
+            VertexStorage::Interleaved => {
+                let stride = sizes.iter().sum();
+                let mut offset = 0;
+                for (attribute, size) in layout.attributes.iter().zip(&sizes) {
+                    attributes.push(GpuAttribute {
+                        name: attribute.name.clone(),
+                        format: attribute.format,
+                        offset,
+                        stride,
+                    });
+                    offset += size;
+                }
+                for vertex in 0..vertex_count {
+                    for (attribute, layer) in layout.attributes.iter().zip(&layers) {
+                        encode_element(layer, vertex, attribute.format, &mut vertex_buffer);
+                    }
+                }
+            }
+            VertexStorage::Planar => {
+                for ((attribute, layer), &size) in layout.attributes.iter().zip(&layers).zip(&sizes)
+                {
+                    attributes.push(GpuAttribute {
+                        name: attribute.name.clone(),
+                        format: attribute.format,
+                        offset: vertex_buffer.len(),
+                        stride: size,
+                    });
+                    for vertex in 0..vertex_count {
+                        encode_element(layer, vertex, attribute.format, &mut vertex_buffer);
+                    }
+                }
+            }
+        }
+
+        let indices = node.corner_vertices()?;
+        let index_format = match layout.index_format {
+            Some(format) => format,
+            None if vertex_count <= u16::MAX as usize + 1 => IndexFormat::U16,
+            None => IndexFormat::U32,
+        };
+        let index_buffer = match index_format {
+            IndexFormat::U16 if vertex_count > u16::MAX as usize + 1 => {
+                return Err(Error::IndexOverflow { vertex_count })
+            }
+            IndexFormat::U16 => IndexBuffer::U16(indices.iter().map(|&i| i as u16).collect()),
+            IndexFormat::U32 => IndexBuffer::U32(indices.iter().map(|&i| i as u32).collect()),
+        };
+
+        Ok(GpuMesh {
+            vertex_buffer,
+            vertex_count,
+            attributes,
+            index_buffer,
+        })
+    }
+}
+
+fn align4(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+fn encode_element(layer: &Layer, element: usize, format: VertexFormat, out: &mut Vec<u8>) {
+    let scale = match (format.scalar.is_normalized(), layer.data_type()) {
+        (true, HXALayerDataType::HXA_LDT_UINT8) => 1.0 / u8::MAX as f64,
+        (true, HXALayerDataType::HXA_LDT_INT32) => 1.0 / i32::MAX as f64,
+        _ => 1.0,
+    };
+    let start = out.len();
+    for component in 0..format.components as usize {
+        let value = match component {
+            component if component < layer.components as usize => {
+                layer.get(element, component) * scale
+            }
+            3 => 1.0,
+            _ => 0.0,
+        };
+        format.scalar.encode(value, out);
+    }
+    out.resize(start + align4(format.size()), 0);
+}
+
+/// Convert a float to the bits of a half float, rounding to the nearest even value.
+pub(crate) fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (half, remainder, halfway) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (
+            mantissa >> shift,
+            mantissa & ((1 << shift) - 1),
+            1 << (shift - 1),
+        )
+    } else {
+        (
+            ((exponent as u32) << 10) | (mantissa >> 13),
+            mantissa & 0x1fff,
+            0x1000,
+        )
+    };
+    let round_up = remainder > halfway || (remainder == halfway && half & 1 == 1);
+
+    sign | (half + round_up as u32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+    use crate::LayerData;
+
+    fn f32_at(buffer: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn parse_layout() {
+        let layout: GpuLayout = "vertex:f32x3, normal:snorm16x4, uv:f16x2".parse().unwrap();
+
+        assert_eq!(
+            layout.attributes,
+            vec![
+                VertexAttribute::new("vertex", VertexFormat::new(ScalarFormat::F32, 3)),
+                VertexAttribute::new("normal", VertexFormat::new(ScalarFormat::Snorm16, 4)),
+                VertexAttribute::new("uv", VertexFormat::new(ScalarFormat::F16, 2)),
+            ]
+        );
+        assert_eq!(layout.attributes[1].format.to_string(), "snorm16x4");
+        assert!(matches!(
+            "vertex".parse::<GpuLayout>(),
+            Err(Error::InvalidLayout { .. })
+        ));
+        assert!(matches!(
+            "uv:f64x2".parse::<GpuLayout>(),
+            Err(Error::InvalidLayout { .. })
+        ));
+        assert!(matches!(
+            "uv:f32x5".parse::<GpuLayout>(),
+            Err(Error::InvalidLayout { .. })
+        ));
+    }
+
+    #[test]
+    fn half_float_conversion() {
+        assert_eq!(f32_to_f16_bits(0.0), 0x0000);
+        assert_eq!(f32_to_f16_bits(-0.0), 0x8000);
+        assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(-2.0), 0xc000);
+        assert_eq!(f32_to_f16_bits(0.5), 0x3800);
+        assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16_bits(1.0e6), 0x7c00);
+        assert_eq!(f32_to_f16_bits(f32::NAN) & 0x7e00, 0x7e00);
+        // Smallest subnormal half float.
+        assert_eq!(f32_to_f16_bits(5.960_464_5e-8), 0x0001);
+        // 1 + 2^-11 is halfway between two halfs and rounds to even.
+        assert_eq!(f32_to_f16_bits(1.000_488_3), 0x3c00);
+    }
+
+    #[test]
+    fn export_interleaved_with_seams() {
+        let mut node = quad_and_triangle();
+        // Vertex 1 has a seam between the quad and the triangle.
+        node.corner_stack.push(Layer::new(
+            "uv",
+            2,
+            LayerData::Float(vec![
+                0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.5, 0.0, 1.0, 0.0, 1.0, 1.0,
+            ]),
+        ));
+        node.face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(vec![3, 4])));
+        let layout: GpuLayout = "vertex:f32x3, uv:unorm16x2, material:u8".parse().unwrap();
+        let mesh = node.to_gpu_mesh(&layout).unwrap();
+
+        // Vertex 1 and 2 are split between the quad and the triangle because of the material,
+        // and vertex 1 also because of the uv.
+        assert_eq!(mesh.vertex_count, 7);
+        assert_eq!(mesh.index_buffer.format(), IndexFormat::U16);
+        assert_eq!(mesh.index_buffer.len(), 9);
+        assert_eq!(mesh.attributes[0].offset, 0);
+        assert_eq!(mesh.attributes[1].offset, 12);
+        assert_eq!(mesh.attributes[2].offset, 16);
+        assert_eq!(mesh.attributes[0].stride, 20);
+        assert_eq!(mesh.vertex_buffer.len(), 7 * 20);
+
+        let positions = node.positions().unwrap();
+        for triangle in 0..3 {
+            for corner in 0..3 {
+                let index = mesh.index_buffer.get(triangle * 3 + corner) as usize;
+                let base = index * 20;
+                let position = [
+                    f32_at(&mesh.vertex_buffer, base) as f64,
+                    f32_at(&mesh.vertex_buffer, base + 4) as f64,
+                    f32_at(&mesh.vertex_buffer, base + 8) as f64,
+                ];
+                assert!(positions.contains(&position));
+                let material = mesh.vertex_buffer[base + 16];
+                let expected = if triangle == 2 { 4 } else { 3 };
+                assert_eq!(material, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn export_planar() {
+        let node = quad_and_triangle();
+        let layout = GpuLayout {
+            storage: VertexStorage::Planar,
+            index_format: Some(IndexFormat::U32),
+            ..GpuLayout::new(vec![VertexAttribute::new(
+                "vertex",
+                VertexFormat::new(ScalarFormat::F16, 3),
+            )])
+        };
+        let mesh = node.to_gpu_mesh(&layout).unwrap();
+
+        assert_eq!(mesh.vertex_count, 5);
+        assert_eq!(mesh.attributes[0].stride, 8);
+        assert_eq!(mesh.vertex_buffer.len(), 40);
+        assert_eq!(mesh.index_buffer.format(), IndexFormat::U32);
+        assert_eq!(mesh.index_buffer.to_bytes().len(), 36);
+        // Vertex 4 is at (2, 0, 0).
+        assert_eq!(&mesh.vertex_buffer[32..34], &0x4000u16.to_le_bytes());
+    }
+
+    #[test]
+    fn export_rejects_missing_and_wide_layers() {
+        let node = quad_and_triangle();
+
+        assert_eq!(
+            node.to_gpu_mesh(&"normal:f32x3".parse().unwrap()),
+            Err(Error::MissingLayer {
+                name: "normal".to_string()
+            })
+        );
+        assert!(matches!(
+            node.to_gpu_mesh(&"vertex:f32x2".parse().unwrap()),
+            Err(Error::LayerComponents { .. })
+        ));
+    }
+
+    #[test]
+    fn integer_layers_are_normalized() {
+        let mut node = quad_and_triangle();
+        node.vertex_stack.push(Layer::new(
+            "color",
+            3,
+            LayerData::Uint8([10, 20, 255].repeat(5)),
+        ));
+        node.vertex_stack
+            .push(Layer::new("weight", 1, LayerData::Int32(vec![i32::MAX; 5])));
+        let layout = "color:unorm8x4, weight:unorm16, vertex:snorm8x4"
+            .parse()
+            .unwrap();
+        let mesh = node.to_gpu_mesh(&layout).unwrap();
+
+        assert_eq!(mesh.attributes[0].stride, 12);
+        // The missing alpha and w are 1.
+        assert_eq!(&mesh.vertex_buffer[..4], &[10, 20, 255, 255]);
+        assert_eq!(&mesh.vertex_buffer[4..6], &u16::MAX.to_le_bytes());
+        assert_eq!(mesh.vertex_buffer[11], 127);
+    }
+}
@@ -0,0 +1,326 @@
+//! Half-edge adjacency over the faces of a geometry node.
+//!
+//! Half-edges are numbered like corners: half-edge `c` runs from the vertex of corner `c` to the
+//! vertex of the next corner of its face, like the edge layers. Any result can be used directly
+//! as an index into the corner and edge stacks, so attributes stay where they are.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::geometry::next_corner;
+use crate::{convention, Error, GeometryNode, Result};
+
+/// A read-only half-edge view of the faces of a geometry node.
+///
+/// The view is a snapshot, it has to be rebuilt when the faces change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HalfEdgeMesh {
+    faces: Vec<Range<usize>>,
+    corner_vertices: Vec<usize>,
+    corner_faces: Vec<usize>,
+    opposites: Vec<Option<usize>>,
+    /// The half-edges leaving each vertex, in corner order.
+    outgoing: Vec<Vec<usize>>,
+}
+
+impl HalfEdgeMesh {
+    /// Build the view of a node.
+    ///
+    /// Opposite half-edges come from the `neighbour` edge layer if the node has one, with values
+    /// outside of the corners read as boundaries. Otherwise they are matched like
+    /// [`generate_neighbours`](GeometryNode::generate_neighbours) does.
+    pub fn new(node: &GeometryNode) -> Result<Self> {
+        let faces = node.faces()?;
+        let corner_vertices = node.corner_vertices()?;
+        let corner_faces = node.corner_faces()?;
+        let corner_count = corner_vertices.len();
+
+        let opposites = match node.edge_stack.find(convention::EDGE_NEIGHBOUR_LAYER_NAME) {
+            Some(layer) => {
+                if layer.len() != corner_count {
+                    return Err(Error::LayerLength {
+                        name: layer.name.clone(),
+                        expected: corner_count,
+                        actual: layer.len(),
+                    });
+                }
+                (0..corner_count)
+                    .map(|corner| {
+                        let opposite = layer.get(corner, 0);
+                        (opposite >= 0.0 && opposite < corner_count as f64)
+                            .then_some(opposite as usize)
+                    })
+                    .collect()
+            }
+            None => {
+                let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+                for face in &faces {
+                    for corner in face.clone() {
+                        let next = corner_vertices[next_corner(face, corner)];
+                        edges
+                            .entry((corner_vertices[corner], next))
+                            .or_insert(corner);
+                    }
+                }
+                (0..corner_count)
+                    .map(|corner| {
+                        let next =
+                            corner_vertices[next_corner(&faces[corner_faces[corner]], corner)];
+                        edges.get(&(next, corner_vertices[corner])).copied()
+                    })
+                    .collect()
+            }
+        };
+
+        let mut outgoing = vec![Vec::new(); node.vertex_count()];
+        for (corner, &vertex) in corner_vertices.iter().enumerate() {
+            outgoing[vertex].push(corner);
+        }
+
+        Ok(Self {
+            faces,
+            corner_vertices,
+            corner_faces,
+            opposites,
+            outgoing,
+        })
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn half_edge_count(&self) -> usize {
+        self.corner_vertices.len()
+    }
+
+    /// The vertex a half-edge starts at.
+    pub fn origin(&self, half_edge: usize) -> usize {
+        self.corner_vertices[half_edge]
+    }
+
+    /// The vertex a half-edge ends at.
+    pub fn target(&self, half_edge: usize) -> usize {
+        self.corner_vertices[self.next(half_edge)]
+    }
+
+    /// The face a half-edge belongs to.
+    pub fn face(&self, half_edge: usize) -> usize {
+        self.corner_faces[half_edge]
+    }
+
+    /// The half-edges of a face, which are its corners.
+    pub fn face_half_edges(&self, face: usize) -> Range<usize> {
+        self.faces[face].clone()
+    }
+
+    /// The next half-edge around the face.
+    pub fn next(&self, half_edge: usize) -> usize {
+        next_corner(&self.faces[self.corner_faces[half_edge]], half_edge)
+    }
+
+    /// The previous half-edge around the face.
+    pub fn prev(&self, half_edge: usize) -> usize {
+        let face = &self.faces[self.corner_faces[half_edge]];
+        if half_edge == face.start {
+            face.end - 1
+        } else {
+            half_edge - 1
+        }
+    }
+
+    /// The half-edge running the other way along the same edge, or `None` on a boundary.
+    pub fn opposite(&self, half_edge: usize) -> Option<usize> {
+        self.opposites[half_edge]
+    }
+
+    pub fn is_boundary_edge(&self, half_edge: usize) -> bool {
+        self.opposites[half_edge].is_none()
+    }
+
+    /// Check if a vertex is on a boundary. Vertices without faces are not.
+    pub fn is_boundary_vertex(&self, vertex: usize) -> bool {
+        self.outgoing[vertex]
+            .iter()
+            .any(|&h| self.is_boundary_edge(h) || self.is_boundary_edge(self.prev(h)))
+    }
+
+    /// The half-edges leaving a vertex, ordered around it.
+    ///
+    /// Each fan of faces around the vertex is walked from a boundary if it has one. Vertices
+    /// where several fans meet list one fan after the other.
+    pub fn outgoing(&self, vertex: usize) -> Vec<usize> {
+        let mut ordered: Vec<usize> = Vec::with_capacity(self.outgoing[vertex].len());
+        for &first in &self.outgoing[vertex] {
+            if ordered.contains(&first) {
+                continue;
+            }
+            // Rotate backwards to the start of the fan, or all the way around.
+            let mut start = first;
+            for _ in 0..self.outgoing[vertex].len() {
+                match self.opposites[start].map(|h| self.next(h)) {
+                    Some(previous) if previous != first && self.origin(previous) == vertex => {
+                        start = previous
+                    }
+                    _ => break,
+                }
+            }
+            let mut half_edge = start;
+            loop {
+                ordered.push(half_edge);
+                match self.opposites[self.prev(half_edge)] {
+                    Some(next) if self.origin(next) == vertex && !ordered.contains(&next) => {
+                        half_edge = next
+                    }
+                    _ => break,
+                }
+            }
+        }
+        ordered
+    }
+
+    /// The vertices sharing an edge with a vertex, ordered around it.
+    pub fn one_ring(&self, vertex: usize) -> Vec<usize> {
+        let mut ring = Vec::new();
+        for half_edge in self.outgoing(vertex) {
+            let incoming = self.prev(half_edge);
+            for neighbour in [self.target(half_edge), self.origin(incoming)] {
+                if !ring.contains(&neighbour) {
+                    ring.push(neighbour);
+                }
+            }
+        }
+        ring
+    }
+
+    /// The faces using a vertex, ordered around it.
+    pub fn vertex_faces(&self, vertex: usize) -> Vec<usize> {
+        let mut faces = Vec::new();
+        for half_edge in self.outgoing(vertex) {
+            let face = self.face(half_edge);
+            if !faces.contains(&face) {
+                faces.push(face);
+            }
+        }
+        faces
+    }
+
+    /// The boundary half-edges, chained into loops following the winding of their faces.
+    ///
+    /// A loop can pass through a vertex more than once where fans meet.
+    pub fn boundary_loops(&self) -> Vec<Vec<usize>> {
+        let mut visited = vec![false; self.half_edge_count()];
+        let mut loops = Vec::new();
+        for first in 0..self.half_edge_count() {
+            if visited[first] || !self.is_boundary_edge(first) {
+                continue;
+            }
+            let mut boundary = Vec::new();
+            let mut half_edge = first;
+            while !visited[half_edge] {
+                visited[half_edge] = true;
+                boundary.push(half_edge);
+                half_edge = self.next_boundary(half_edge);
+            }
+            loops.push(boundary);
+        }
+        loops
+    }
+
+    /// The boundary half-edge leaving the target of a boundary half-edge.
+    fn next_boundary(&self, half_edge: usize) -> usize {
+        let mut next = self.next(half_edge);
+        let mut steps = 0;
+        while let Some(opposite) = self.opposites[next] {
+            next = self.next(opposite);
+            steps += 1;
+            // Inconsistent neighbours can spin forever around a vertex.
+            if steps > self.outgoing[self.origin(next)].len() {
+                break;
+            }
+        }
+        next
+    }
+}
+
+impl GeometryNode {
+    /// Build a half-edge view of the faces. See [`HalfEdgeMesh::new`].
+    pub fn half_edges(&self) -> Result<HalfEdgeMesh> {
+        HalfEdgeMesh::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle};
+    use crate::LayerData;
+
+    #[test]
+    fn adjacency_around_vertices() {
+        let mesh = quad_and_triangle().half_edges().unwrap();
+        assert_eq!(mesh.half_edge_count(), 7);
+        // Corner 1 runs from vertex 1 to 2, opposite the triangle corner 6 from 2 to 1.
+        assert_eq!((mesh.origin(1), mesh.target(1)), (1, 2));
+        assert_eq!(mesh.opposite(1), Some(6));
+        assert_eq!(mesh.opposite(6), Some(1));
+        assert_eq!(mesh.next(6), 4);
+        assert_eq!(mesh.prev(4), 6);
+        assert_eq!(mesh.face(6), 1);
+
+        assert_eq!(mesh.outgoing(1), [4, 1]);
+        assert_eq!(mesh.one_ring(1), [4, 2, 0]);
+        assert_eq!(mesh.vertex_faces(2), [0, 1]);
+        assert_eq!(mesh.one_ring(3), [0, 2]);
+        assert!(mesh.is_boundary_vertex(1));
+        assert!(!mesh.is_boundary_edge(1));
+
+        assert_eq!(mesh.boundary_loops(), [vec![0, 4, 5, 2, 3]]);
+    }
+
+    #[test]
+    fn closed_meshes_have_full_rings() {
+        let mut node = cube();
+        let mesh = node.half_edges().unwrap();
+        assert!(mesh.boundary_loops().is_empty());
+        for vertex in 0..mesh.vertex_count() {
+            assert!(!mesh.is_boundary_vertex(vertex));
+            assert_eq!(mesh.one_ring(vertex).len(), 3);
+            assert_eq!(mesh.vertex_faces(vertex).len(), 3);
+            for half_edge in mesh.outgoing(vertex) {
+                let opposite = mesh.opposite(half_edge).unwrap();
+                assert_eq!(mesh.origin(opposite), mesh.target(half_edge));
+            }
+        }
+
+        node.generate_neighbours().unwrap();
+        assert_eq!(node.half_edges().unwrap(), mesh);
+    }
+
+    #[test]
+    fn neighbour_layer_is_used() {
+        let mut node = quad_and_triangle();
+        node.generate_neighbours().unwrap();
+        let neighbours = node
+            .edge_stack
+            .get_mut(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .unwrap();
+        // Cut the shared edge.
+        neighbours.set(1, 0, -1.0);
+        neighbours.set(6, 0, -1.0);
+        let mesh = node.half_edges().unwrap();
+        assert_eq!(mesh.opposite(1), None);
+        assert_eq!(mesh.boundary_loops().len(), 2);
+        assert_eq!(mesh.vertex_faces(2), [0, 1]);
+
+        node.edge_stack
+            .get_mut(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .unwrap()
+            .data = LayerData::Int32(vec![-1; 3]);
+        assert!(node.half_edges().is_err());
+    }
+}
@@ -0,0 +1,503 @@
+use hxa_sys::HXALayerDataType;
+
+use crate::{Error, Result};
+
+/// The values stored in a layer, in one of the types supported by the format.
+///
+/// Values are stored flat, so a layer with 3 components stores `x0, y0, z0, x1, y1, z1, ...`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerData {
+    Uint8(Vec<u8>),
+    Int32(Vec<i32>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl LayerData {
+    /// Create empty data of the given type.
+    pub fn new(data_type: HXALayerDataType) -> Self {
+        Self::with_capacity(data_type, 0)
+    }
+
+    /// Create empty data of the given type with room for `capacity` values.
+    pub fn with_capacity(data_type: HXALayerDataType, capacity: usize) -> Self {
+        match data_type {
+            HXALayerDataType::HXA_LDT_UINT8 => LayerData::Uint8(Vec::with_capacity(capacity)),
+            HXALayerDataType::HXA_LDT_INT32 => LayerData::Int32(Vec::with_capacity(capacity)),
+            HXALayerDataType::HXA_LDT_FLOAT => LayerData::Float(Vec::with_capacity(capacity)),
+            HXALayerDataType::HXA_LDT_DOUBLE | HXALayerDataType::HXA_LDT_COUNT => {
+                LayerData::Double(Vec::with_capacity(capacity))
+            }
+        }
+    }
+
+    /// Create zero filled data of the given type.
+    pub fn zeroed(data_type: HXALayerDataType, len: usize) -> Self {
+        match data_type {
+            HXALayerDataType::HXA_LDT_UINT8 => LayerData::Uint8(vec![0; len]),
+            HXALayerDataType::HXA_LDT_INT32 => LayerData::Int32(vec![0; len]),
+            HXALayerDataType::HXA_LDT_FLOAT => LayerData::Float(vec![0.0; len]),
+            HXALayerDataType::HXA_LDT_DOUBLE | HXALayerDataType::HXA_LDT_COUNT => {
+                LayerData::Double(vec![0.0; len])
+            }
+        }
+    }
+
+    pub fn data_type(&self) -> HXALayerDataType {
+        match self {
+            LayerData::Uint8(_) => HXALayerDataType::HXA_LDT_UINT8,
+            LayerData::Int32(_) => HXALayerDataType::HXA_LDT_INT32,
+            LayerData::Float(_) => HXALayerDataType::HXA_LDT_FLOAT,
+            LayerData::Double(_) => HXALayerDataType::HXA_LDT_DOUBLE,
+        }
+    }
+
+    /// The number of values, counting every component separately.
+    pub fn len(&self) -> usize {
+        match self {
+            LayerData::Uint8(data) => data.len(),
+            LayerData::Int32(data) => data.len(),
+            LayerData::Float(data) => data.len(),
+            LayerData::Double(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read a value as a double, regardless of the stored type.
+    pub fn get_f64(&self, index: usize) -> f64 {
+        match self {
+            LayerData::Uint8(data) => data[index] as f64,
+            LayerData::Int32(data) => data[index] as f64,
+            LayerData::Float(data) => data[index] as f64,
+            LayerData::Double(data) => data[index],
+        }
+    }
+
+    /// Write a value from a double. Integer types round to the nearest value and saturate.
+    pub fn set_f64(&mut self, index: usize, value: f64) {
+        match self {
+            LayerData::Uint8(data) => data[index] = value.round() as u8,
+            LayerData::Int32(data) => data[index] = value.round() as i32,
+            LayerData::Float(data) => data[index] = value as f32,
+            LayerData::Double(data) => data[index] = value,
+        }
+    }
+
+    /// Append a value from a double. Integer types round to the nearest value and saturate.
+    pub fn push_f64(&mut self, value: f64) {
+        match self {
+            LayerData::Uint8(data) => data.push(value.round() as u8),
+            LayerData::Int32(data) => data.push(value.round() as i32),
+            LayerData::Float(data) => data.push(value as f32),
+            LayerData::Double(data) => data.push(value),
+        }
+    }
+
+    /// Read every value as a double.
+    pub fn to_f64_vec(&self) -> Vec<f64> {
+        match self {
+            LayerData::Uint8(data) => data.iter().map(|&v| v as f64).collect(),
+            LayerData::Int32(data) => data.iter().map(|&v| v as f64).collect(),
+            LayerData::Float(data) => data.iter().map(|&v| v as f64).collect(),
+            LayerData::Double(data) => data.clone(),
+        }
+    }
+
+    /// Copy the elements of `components` values at `indices` into new data of the same type.
+    pub fn gather(&self, components: usize, indices: &[usize]) -> Self {
+        fn gather<T: Copy>(data: &[T], components: usize, indices: &[usize]) -> Vec<T> {
+            let mut out = Vec::with_capacity(indices.len() * components);
+            for &index in indices {
+                out.extend_from_slice(&data[index * components..(index + 1) * components]);
+            }
+            out
+        }
+
+        match self {
+            LayerData::Uint8(data) => LayerData::Uint8(gather(data, components, indices)),
+            LayerData::Int32(data) => LayerData::Int32(gather(data, components, indices)),
+            LayerData::Float(data) => LayerData::Float(gather(data, components, indices)),
+            LayerData::Double(data) => LayerData::Double(gather(data, components, indices)),
+        }
+    }
+
+    /// Append the element of `components` values at `index` of `other` to this data.
+    ///
+    /// `other` is converted if it is stored with a different type.
+    pub fn extend_from_element(&mut self, other: &LayerData, components: usize, index: usize) {
+        let range = index * components..(index + 1) * components;
+        match (self, other) {
+            (LayerData::Uint8(data), LayerData::Uint8(other)) => {
+                data.extend_from_slice(&other[range])
+            }
+            (LayerData::Int32(data), LayerData::Int32(other)) => {
+                data.extend_from_slice(&other[range])
+            }
+            (LayerData::Float(data), LayerData::Float(other)) => {
+                data.extend_from_slice(&other[range])
+            }
+            (LayerData::Double(data), LayerData::Double(other)) => {
+                data.extend_from_slice(&other[range])
+            }
+            (data, other) => {
+                for i in range {
+                    data.push_f64(other.get_f64(i));
+                }
+            }
+        }
+    }
+
+    /// Check if two elements hold exactly the same values.
+    pub fn element_eq(&self, components: usize, a: usize, b: usize) -> bool {
+        fn eq<T: PartialEq>(data: &[T], components: usize, a: usize, b: usize) -> bool {
+            data[a * components..(a + 1) * components] == data[b * components..(b + 1) * components]
+        }
+
+        match self {
+            LayerData::Uint8(data) => eq(data, components, a, b),
+            LayerData::Int32(data) => eq(data, components, a, b),
+            LayerData::Float(data) => eq(data, components, a, b),
+            LayerData::Double(data) => eq(data, components, a, b),
+        }
+    }
+
+    /// Append the bit patterns of an element to `key`, for hashing elements by their exact value.
+    pub(crate) fn push_element_bits(&self, components: usize, index: usize, key: &mut Vec<u64>) {
+        let range = index * components..(index + 1) * components;
+        match self {
+            LayerData::Uint8(data) => key.extend(data[range].iter().map(|&v| v as u64)),
+            LayerData::Int32(data) => key.extend(data[range].iter().map(|&v| v as u32 as u64)),
+            LayerData::Float(data) => key.extend(data[range].iter().map(|&v| v.to_bits() as u64)),
+            LayerData::Double(data) => key.extend(data[range].iter().map(|&v| v.to_bits())),
+        }
+    }
+}
+
+/// Layers are arrays of data used to store geometry and pixel data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    /// Name of the layer. See [`convention`](crate::convention) for predefined names.
+    pub name: String,
+    /// 2 for uv, 3 for xyz or rgb, 4 for rgba. From 1 - 255 is legal.
+    pub components: u8,
+    pub data: LayerData,
+}
+
+impl Layer {
+    pub fn new(name: impl Into<String>, components: u8, data: LayerData) -> Self {
+        Self {
+            name: name.into(),
+            components,
+            data,
+        }
+    }
+
+    pub fn data_type(&self) -> HXALayerDataType {
+        self.data.data_type()
+    }
+
+    /// The number of elements in the layer.
+    pub fn len(&self) -> usize {
+        match self.components {
+            0 => 0,
+            components => self.data.len() / components as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read a single component of an element as a double.
+    pub fn get(&self, element: usize, component: usize) -> f64 {
+        self.data
+            .get_f64(element * self.components as usize + component)
+    }
+
+    /// Write a single component of an element from a double.
+    pub fn set(&mut self, element: usize, component: usize, value: f64) {
+        let components = self.components as usize;
+        self.data.set_f64(element * components + component, value);
+    }
+
+    /// Copy the elements at `indices` into a new layer with the same name and type.
+    pub fn gather(&self, indices: &[usize]) -> Self {
+        Self {
+            name: self.name.clone(),
+            components: self.components,
+            data: self.data.gather(self.components as usize, indices),
+        }
+    }
+
+    /// Copy the elements at `indices` into a new layer, filling the elements without an index
+    /// with zeros.
+    pub fn gather_optional(&self, indices: &[Option<usize>]) -> Self {
+        let components = self.components as usize;
+        let mut data = LayerData::with_capacity(self.data_type(), indices.len() * components);
+        for index in indices {
+            match index {
+                Some(element) => data.extend_from_element(&self.data, components, *element),
+                None => {
+                    for _ in 0..components {
+                        data.push_f64(0.0);
+                    }
+                }
+            }
+        }
+
+        Self {
+            name: self.name.clone(),
+            components: self.components,
+            data,
+        }
+    }
+
+    /// Build a new layer where each element is a weighted sum of elements of this layer, given as
+    /// `(element, weight)` pairs.
+    ///
+    /// Integer values can not be blended, so integer layers copy the element with the largest
+    /// weight instead.
+    pub fn interpolate(&self, stencils: &[Vec<(usize, f64)>]) -> Self {
+        let components = self.components as usize;
+        let mut data = LayerData::with_capacity(self.data_type(), stencils.len() * components);
+        for stencil in stencils {
+            match self.data {
+                LayerData::Float(_) | LayerData::Double(_) => {
+                    for component in 0..components {
+                        let value = stencil
+                            .iter()
+                            .map(|&(element, weight)| self.get(element, component) * weight)
+                            .sum();
+                        data.push_f64(value);
+                    }
+                }
+                LayerData::Uint8(_) | LayerData::Int32(_) => {
+                    let mut weights: Vec<(usize, f64)> = Vec::with_capacity(stencil.len());
+                    for &(element, weight) in stencil {
+                        match weights.iter_mut().find(|(e, _)| *e == element) {
+                            Some((_, total)) => *total += weight,
+                            None => weights.push((element, weight)),
+                        }
+                    }
+                    match weights.iter().max_by(|a, b| a.1.total_cmp(&b.1)) {
+                        Some(&(element, _)) => {
+                            data.extend_from_element(&self.data, components, element)
+                        }
+                        None => (0..components).for_each(|_| data.push_f64(0.0)),
+                    }
+                }
+            }
+        }
+
+        Self {
+            name: self.name.clone(),
+            components: self.components,
+            data,
+        }
+    }
+
+    /// Read the layer as 3 component double vectors.
+    pub fn to_vec3(&self) -> Result<Vec<[f64; 3]>> {
+        self.expect_components(3)?;
+        let values = self.data.to_f64_vec();
+        Ok(values.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect())
+    }
+
+    /// Read the layer as 2 component double vectors.
+    pub fn to_vec2(&self) -> Result<Vec<[f64; 2]>> {
+        self.expect_components(2)?;
+        let values = self.data.to_f64_vec();
+        Ok(values.chunks_exact(2).map(|v| [v[0], v[1]]).collect())
+    }
+
+    pub(crate) fn expect_components(&self, components: u8) -> Result<()> {
+        if self.components == components {
+            Ok(())
+        } else {
+            Err(Error::LayerComponents {
+                name: self.name.clone(),
+                expected: components,
+                actual: self.components,
+            })
+        }
+    }
+}
+
+/// Layer stacks are arrays of layers where all the layers have the same number of entries
+/// (polygons, edges, vertices or pixels).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayerStack {
+    pub layers: Vec<Layer>,
+}
+
+impl LayerStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Layer> {
+        self.layers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Layer> {
+        self.layers.iter_mut()
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    /// Find a layer, or fail with [`Error::MissingLayer`].
+    pub fn get(&self, name: &str) -> Result<&Layer> {
+        self.find(name).ok_or_else(|| Error::MissingLayer {
+            name: name.to_string(),
+        })
+    }
+
+    /// Find a layer, or fail with [`Error::MissingLayer`].
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut Layer> {
+        self.find_mut(name).ok_or_else(|| Error::MissingLayer {
+            name: name.to_string(),
+        })
+    }
+
+    pub fn push(&mut self, layer: Layer) {
+        self.layers.push(layer);
+    }
+
+    /// Add a layer, replacing any layer with the same name.
+    pub fn insert(&mut self, layer: Layer) {
+        match self.position(&layer.name) {
+            Some(index) => self.layers[index] = layer,
+            None => self.layers.push(layer),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Layer> {
+        self.position(name).map(|index| self.layers.remove(index))
+    }
+
+    /// Copy the elements at `indices` of every layer into a new stack.
+    pub fn gather(&self, indices: &[usize]) -> Self {
+        Self {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| layer.gather(indices))
+                .collect(),
+        }
+    }
+
+    /// Copy the elements at `indices` of every layer into a new stack, filling the elements
+    /// without an index with zeros.
+    pub fn gather_optional(&self, indices: &[Option<usize>]) -> Self {
+        Self {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| layer.gather_optional(indices))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a LayerStack {
+    type Item = &'a Layer;
+    type IntoIter = std::slice::Iter<'a, Layer>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.layers.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gather_keeps_type_and_components() {
+        let layer = Layer::new(
+            "uv",
+            2,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]),
+        );
+        let gathered = layer.gather(&[2, 0, 2]);
+
+        assert_eq!(gathered.name, "uv");
+        assert_eq!(gathered.components, 2);
+        assert_eq!(
+            gathered.data,
+            LayerData::Float(vec![4.0, 5.0, 0.0, 1.0, 4.0, 5.0])
+        );
+    }
+
+    #[test]
+    fn extend_from_element_converts() {
+        let mut data = LayerData::Float(Vec::new());
+        data.extend_from_element(&LayerData::Uint8(vec![1, 2, 3, 4]), 2, 1);
+
+        assert_eq!(data, LayerData::Float(vec![3.0, 4.0]));
+    }
+
+    #[test]
+    fn set_rounds_integers() {
+        let mut layer = Layer::new("material", 1, LayerData::Int32(vec![0, 0]));
+        layer.set(1, 0, 2.6);
+
+        assert_eq!(layer.data, LayerData::Int32(vec![0, 3]));
+        assert_eq!(layer.get(1, 0), 3.0);
+    }
+
+    #[test]
+    fn interpolate_blends_floats_and_picks_integers() {
+        let stencils = vec![
+            vec![(0, 0.25), (1, 0.75)],
+            vec![(1, 0.5), (0, 0.25), (0, 0.125)],
+        ];
+        let uv = Layer::new("uv", 2, LayerData::Float(vec![0.0, 4.0, 8.0, 0.0]));
+        assert_eq!(
+            uv.interpolate(&stencils).data,
+            LayerData::Float(vec![6.0, 1.0, 4.0, 1.5])
+        );
+
+        let material = Layer::new("material", 1, LayerData::Int32(vec![1, 2]));
+        assert_eq!(
+            material.interpolate(&stencils).data,
+            LayerData::Int32(vec![2, 2])
+        );
+    }
+
+    #[test]
+    fn stack_insert_replaces_by_name() {
+        let mut stack = LayerStack::new();
+        stack.insert(Layer::new("a", 1, LayerData::Uint8(vec![1])));
+        stack.insert(Layer::new("b", 1, LayerData::Uint8(vec![2])));
+        stack.insert(Layer::new("a", 1, LayerData::Uint8(vec![3])));
+
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.get("a").unwrap().data, LayerData::Uint8(vec![3]));
+        assert!(matches!(
+            stack.get("c"),
+            Err(Error::MissingLayer { name }) if name == "c"
+        ));
+    }
+}
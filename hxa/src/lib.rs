@@ -0,0 +1,79 @@
+/*!
+Safe Rust representation of the HxA 3D asset format.
+
+The types in this crate mirror the structures in `hxa-sys`, but own their data. A [`File`] holds
+an array of [`Node`]s. Every node has [`Meta`] data, and geometry and image nodes store their
+values in named [`Layer`]s collected in [`LayerStack`]s.
+
+```
+use hxa::{File, GeometryNode};
+
+let mut file = File::new();
+let geometry = GeometryNode::from_polygons(
+    &[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0], [2.0, 0.0, 0.0]],
+    &[vec![0, 1, 2, 3], vec![1, 4, 2]],
+);
+assert_eq!(geometry.reference().unwrap(), &[0, 1, 2, -4, 1, 4, -3]);
+file.nodes.push(geometry.into());
+```
+*/
+
+mod bake;
+mod blendshape;
+mod bounds;
+#[cfg(feature = "bvh")]
+pub mod bvh;
+mod color;
+pub mod convention;
+mod convert;
+mod corner;
+mod error;
+mod file;
+mod geometry;
+pub mod gpu;
+mod halfedge;
+mod layer;
+mod lightmap;
+mod math;
+mod merge;
+mod meta;
+mod node;
+pub mod optimize;
+mod repair;
+mod scene;
+mod selection;
+mod sequence;
+mod simplify;
+mod skin;
+mod split;
+mod subdivide;
+mod transfer;
+mod transform;
+mod triangulate;
+mod uv;
+
+pub use bake::BakeOptions;
+pub use blendshape::{BlendShape, BlendShapeKind};
+pub use bounds::{Aabb, BoundingSphere, Obb};
+pub use color::{ColorFormat, ColorSpace};
+pub use convert::{ConvertOptions, Normalization, Rounding};
+pub use error::{Error, Result};
+pub use file::{File, ReferencePolicy};
+pub use geometry::{decode_reference, encode_reference, GeometryNode};
+pub use halfedge::HalfEdgeMesh;
+pub use hxa_sys::{HXAImageType, HXALayerDataType, HXAMetaDataType, HXANodeType};
+pub use layer::{Layer, LayerData, LayerStack};
+pub use lightmap::LightmapOptions;
+pub use merge::NameClash;
+pub use meta::{find_meta, set_meta, Meta, MetaValue};
+pub use node::{ImageNode, Node, NodeContent};
+pub use repair::{RepairOptions, RepairReport};
+pub use scene::Scene;
+pub use selection::{Selection, SelectionDomain};
+pub use sequence::VertexSequence;
+pub use simplify::SimplifyOptions;
+pub use skin::Skin;
+pub use split::SplitBy;
+pub use subdivide::SubdivisionScheme;
+pub use transform::Mat4;
+pub use uv::{uv_layer_name, TexelDensity};
@@ -0,0 +1,423 @@
+//! Automatic lightmap UV generation.
+//!
+//! Faces are grouped into charts of roughly the same direction, every chart is flattened with
+//! least squares conformal maps (LSCM) and the charts are packed into the unit square. Unlike
+//! texture UVs, lightmap UVs never overlap, so every texel belongs to one point of the surface.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::math::{add, cross, dot, length, normalize, polygon_normal, scale, sub, Vec3};
+use crate::triangulate::triangulate_polygon;
+use crate::uv::pack_islands_padded;
+use crate::{GeometryNode, Result};
+
+/// Settings of a lightmap unwrap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightmapOptions {
+    /// Largest angle in radians between a face and the average direction of its chart.
+    pub max_chart_angle: f64,
+    /// Largest angle in radians between two neighbouring faces of the same chart.
+    pub max_edge_angle: f64,
+    /// Width and height of the lightmap in texels.
+    pub resolution: u32,
+    /// Texels of space kept around every chart, so bilinear filtering does not bleed.
+    pub padding: u32,
+}
+
+impl LightmapOptions {
+    /// Unwrap for a lightmap of `resolution` by `resolution` texels.
+    pub fn new(resolution: u32) -> Self {
+        Self {
+            max_chart_angle: 60f64.to_radians(),
+            max_edge_angle: 45f64.to_radians(),
+            resolution,
+            padding: 2,
+        }
+    }
+}
+
+impl GeometryNode {
+    /// Generate non-overlapping lightmap UVs in the `uv1` corner layer.
+    ///
+    /// Charts keep the relative size of the surface, so texel density is about the same
+    /// everywhere. Returns the chart of every face.
+    pub fn generate_lightmap_uvs(&mut self, options: &LightmapOptions) -> Result<Vec<usize>> {
+        let positions = self.positions()?;
+        let corner_vertices = self.corner_vertices()?;
+        let faces = self.faces()?;
+        let charts = self.lightmap_charts(options)?;
+        let chart_count = charts.iter().max().map_or(0, |&max| max + 1);
+
+        let mut chart_faces = vec![Vec::new(); chart_count];
+        for (face, &chart) in charts.iter().enumerate() {
+            chart_faces[chart].push(face);
+        }
+        let mut uvs = vec![[0.0; 2]; corner_vertices.len()];
+        for faces_of_chart in &chart_faces {
+            let mut local: HashMap<usize, usize> = HashMap::new();
+            let mut triangles = Vec::new();
+            for &face in faces_of_chart {
+                let range = faces[face].clone();
+                let vertices: Vec<usize> = range
+                    .clone()
+                    .map(|corner| {
+                        let next = local.len();
+                        *local.entry(corner_vertices[corner]).or_insert(next)
+                    })
+                    .collect();
+                let points: Vec<Vec3> = range
+                    .map(|corner| positions[corner_vertices[corner]])
+                    .collect();
+                for [a, b, c] in triangulate_polygon(&points) {
+                    triangles.push([vertices[a], vertices[b], vertices[c]]);
+                }
+            }
+            let mut chart_positions = vec![[0.0; 3]; local.len()];
+            for (&vertex, &index) in &local {
+                chart_positions[index] = positions[vertex];
+            }
+
+            let chart_uvs = conformal_map(&chart_positions, &triangles);
+            for &face in faces_of_chart {
+                for corner in faces[face].clone() {
+                    uvs[corner] = chart_uvs[local[&corner_vertices[corner]]];
+                }
+            }
+        }
+
+        let padding = options.padding as f64 / options.resolution.max(1) as f64;
+        pack_islands_padded(&faces, &charts, &mut uvs, padding);
+        self.set_corner_uvs(1, &uvs)?;
+
+        Ok(charts)
+    }
+
+    /// Group faces into charts by growing them over shared edges from the largest faces.
+    fn lightmap_charts(&self, options: &LightmapOptions) -> Result<Vec<usize>> {
+        let positions = self.positions()?;
+        let polygons = self.polygons()?;
+        let normals: Vec<Vec3> = polygons
+            .iter()
+            .map(|polygon| {
+                let points: Vec<Vec3> = polygon.iter().map(|&v| positions[v]).collect();
+                polygon_normal(&points)
+            })
+            .collect();
+
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (face, polygon) in polygons.iter().enumerate() {
+            for (i, &a) in polygon.iter().enumerate() {
+                let b = polygon[(i + 1) % polygon.len()];
+                edge_faces
+                    .entry((a.min(b), a.max(b)))
+                    .or_default()
+                    .push(face);
+            }
+        }
+        let edge_faces = &edge_faces;
+        let neighbours = |face: usize| {
+            let polygon = &polygons[face];
+            (0..polygon.len()).filter_map(move |i| {
+                let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                match edge_faces[&(a.min(b), a.max(b))][..] {
+                    [x, y] => Some(if x == face { y } else { x }),
+                    _ => None,
+                }
+            })
+        };
+
+        let (chart_cos, edge_cos) = (options.max_chart_angle.cos(), options.max_edge_angle.cos());
+        let mut seeds: Vec<usize> = (0..polygons.len()).collect();
+        seeds.sort_by(|&a, &b| length(normals[b]).total_cmp(&length(normals[a])));
+        let mut charts = vec![usize::MAX; polygons.len()];
+        let mut chart_count = 0;
+        for seed in seeds {
+            if charts[seed] != usize::MAX {
+                continue;
+            }
+            charts[seed] = chart_count;
+            let mut direction = normals[seed];
+            let mut queue = VecDeque::from([seed]);
+            while let Some(face) = queue.pop_front() {
+                for neighbour in neighbours(face) {
+                    let normal = normalize(normals[neighbour]);
+                    if charts[neighbour] != usize::MAX
+                        || dot(normal, normalize(normals[face])) < edge_cos
+                        || dot(normal, normalize(direction)) < chart_cos
+                    {
+                        continue;
+                    }
+                    charts[neighbour] = chart_count;
+                    direction = add(direction, normals[neighbour]);
+                    queue.push_back(neighbour);
+                }
+            }
+            chart_count += 1;
+        }
+
+        Ok(charts)
+    }
+}
+
+/// Flatten a chart with least squares conformal maps, keeping the scale of the surface.
+///
+/// The two vertices furthest apart are pinned and the other UVs are solved with conjugate
+/// gradients, starting from a projection onto the plane of the chart.
+fn conformal_map(positions: &[Vec3], triangles: &[[usize; 3]]) -> Vec<[f64; 2]> {
+    let Some(&origin) = positions.first() else {
+        return Vec::new();
+    };
+    let furthest = |from: Vec3| {
+        (0..positions.len())
+            .max_by(|&a, &b| {
+                length(sub(positions[a], from)).total_cmp(&length(sub(positions[b], from)))
+            })
+            .unwrap_or(0)
+    };
+    let first = furthest(origin);
+    let second = furthest(positions[first]);
+
+    // Project onto the plane of the chart, with the pinned vertices on the u axis.
+    let normal = normalize(triangles.iter().fold([0.0; 3], |normal, &[a, b, c]| {
+        add(
+            normal,
+            cross(
+                sub(positions[b], positions[a]),
+                sub(positions[c], positions[a]),
+            ),
+        )
+    }));
+    let axis = sub(positions[second], positions[first]);
+    let u_axis = normalize(sub(axis, scale(normal, dot(axis, normal))));
+    let v_axis = cross(normal, u_axis);
+    let mut uvs: Vec<[f64; 2]> = positions
+        .iter()
+        .map(|&p| {
+            let p = sub(p, positions[first]);
+            [dot(p, u_axis), dot(p, v_axis)]
+        })
+        .collect();
+    if first == second || length(u_axis) == 0.0 {
+        return uvs;
+    }
+    uvs[second] = [length(axis), 0.0];
+
+    // Every free vertex has two unknowns, pinned vertices move to the right hand side.
+    let mut unknowns = vec![usize::MAX; positions.len()];
+    let mut count = 0;
+    for (vertex, unknown) in unknowns.iter_mut().enumerate() {
+        if vertex != first && vertex != second {
+            *unknown = count;
+            count += 2;
+        }
+    }
+    let mut rows: Vec<(Vec<(usize, f64)>, f64)> = Vec::with_capacity(triangles.len() * 2);
+    for &[a, b, c] in triangles {
+        let (e1, e2) = (
+            sub(positions[b], positions[a]),
+            sub(positions[c], positions[a]),
+        );
+        let area = length(cross(e1, e2)) / 2.0;
+        if area <= 0.0 {
+            continue;
+        }
+        let x = normalize(e1);
+        let y = normalize(cross(cross(e1, e2), x));
+        let w = [[0.0, 0.0], [length(e1), 0.0], [dot(e2, x), dot(e2, y)]];
+        let weight = 1.0 / (2.0 * area).sqrt();
+
+        // The real and imaginary parts of the sum of (w[j + 2] - w[j + 1]) * uv[j].
+        let (mut real, mut imaginary) = ((Vec::new(), 0.0), (Vec::new(), 0.0));
+        for (j, vertex) in [a, b, c].into_iter().enumerate() {
+            let next = w[(j + 1) % 3];
+            let last = w[(j + 2) % 3];
+            let (wr, wi) = ((last[0] - next[0]) * weight, (last[1] - next[1]) * weight);
+            let [u, v] = uvs[vertex];
+            if unknowns[vertex] == usize::MAX {
+                real.1 -= wr * u - wi * v;
+                imaginary.1 -= wi * u + wr * v;
+            } else {
+                let unknown = unknowns[vertex];
+                real.0.extend([(unknown, wr), (unknown + 1, -wi)]);
+                imaginary.0.extend([(unknown, wi), (unknown + 1, wr)]);
+            }
+        }
+        rows.push(real);
+        rows.push(imaginary);
+    }
+
+    let mut solution = vec![0.0; count];
+    for (vertex, &unknown) in unknowns.iter().enumerate() {
+        if unknown != usize::MAX {
+            solution[unknown..unknown + 2].copy_from_slice(&uvs[vertex]);
+        }
+    }
+    least_squares(&rows, &mut solution, 10 * count + 100);
+    for (vertex, &unknown) in unknowns.iter().enumerate() {
+        if unknown != usize::MAX {
+            uvs[vertex] = [solution[unknown], solution[unknown + 1]];
+        }
+    }
+
+    uvs
+}
+
+/// Minimize the squared residual of sparse rows with conjugate gradients on the normal
+/// equations, starting from the given solution.
+fn least_squares(rows: &[(Vec<(usize, f64)>, f64)], solution: &mut [f64], iterations: usize) {
+    let unknowns = solution.len();
+    let multiply = |x: &[f64]| -> Vec<f64> {
+        rows.iter()
+            .map(|(row, _)| row.iter().map(|&(i, value)| value * x[i]).sum())
+            .collect()
+    };
+    let multiply_transposed = |r: &[f64]| -> Vec<f64> {
+        let mut result = vec![0.0; unknowns];
+        for ((row, _), &r) in rows.iter().zip(r) {
+            for &(i, value) in row {
+                result[i] += value * r;
+            }
+        }
+        result
+    };
+    let squared = |x: &[f64]| x.iter().map(|v| v * v).sum::<f64>();
+
+    let mut residual: Vec<f64> = rows
+        .iter()
+        .zip(multiply(solution))
+        .map(|((_, rhs), value)| rhs - value)
+        .collect();
+    let mut gradient = multiply_transposed(&residual);
+    let mut direction = gradient.clone();
+    let mut gamma = squared(&gradient);
+    let tolerance = gamma * 1e-20;
+    for _ in 0..iterations {
+        if gamma <= tolerance || gamma == 0.0 {
+            break;
+        }
+        let step = multiply(&direction);
+        let alpha = gamma / squared(&step);
+        for (x, d) in solution.iter_mut().zip(&direction) {
+            *x += alpha * d;
+        }
+        for (r, s) in residual.iter_mut().zip(&step) {
+            *r -= alpha * s;
+        }
+        gradient = multiply_transposed(&residual);
+        let next_gamma = squared(&gradient);
+        let beta = next_gamma / gamma;
+        for (d, g) in direction.iter_mut().zip(&gradient) {
+            *d = g + beta * *d;
+        }
+        gamma = next_gamma;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, triangle_grid};
+
+    /// The ratio between UV and surface distance for every edge of the node.
+    fn edge_scales(node: &GeometryNode) -> Vec<f64> {
+        let positions = node.positions().unwrap();
+        let uvs = node.corner_uvs(1).unwrap();
+        let corner_vertices = node.corner_vertices().unwrap();
+        let mut scales = Vec::new();
+        for range in node.faces().unwrap() {
+            for corner in range.clone() {
+                let next = crate::geometry::next_corner(&range, corner);
+                let surface = length(sub(
+                    positions[corner_vertices[next]],
+                    positions[corner_vertices[corner]],
+                ));
+                let [du, dv] = [uvs[next][0] - uvs[corner][0], uvs[next][1] - uvs[corner][1]];
+                scales.push((du * du + dv * dv).sqrt() / surface);
+            }
+        }
+        scales
+    }
+
+    #[test]
+    fn cube_gets_one_chart_per_side() {
+        let mut node = cube();
+        let charts = node
+            .generate_lightmap_uvs(&LightmapOptions::new(256))
+            .unwrap();
+        assert_eq!(charts.iter().max(), Some(&5));
+        assert_eq!(node.uv_islands(1).unwrap(), [0, 1, 2, 3, 4, 5]);
+
+        let [min, max] = node.uv_bounds(1).unwrap().unwrap();
+        assert!(min.iter().all(|&v| v >= 0.0) && max.iter().all(|&v| v <= 1.0));
+        let scales = edge_scales(&node);
+        assert!(scales.iter().all(|&s| (s - scales[0]).abs() < 1e-6));
+    }
+
+    #[test]
+    fn charts_keep_their_padding_in_texels() {
+        let mut node = cube();
+        let options = LightmapOptions::new(256);
+        let charts = node.generate_lightmap_uvs(&options).unwrap();
+        let uvs = node.corner_uvs(1).unwrap();
+        let mut bounds = [[[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]]; 6];
+        for (range, &chart) in node.faces().unwrap().into_iter().zip(&charts) {
+            for uv in &uvs[range] {
+                for axis in 0..2 {
+                    bounds[chart][0][axis] = bounds[chart][0][axis].min(uv[axis]);
+                    bounds[chart][1][axis] = bounds[chart][1][axis].max(uv[axis]);
+                }
+            }
+        }
+
+        let texels = options.resolution as f64;
+        let mut smallest = f64::INFINITY;
+        for (a, [min_a, max_a]) in bounds.iter().enumerate() {
+            for [min_b, max_b] in &bounds[a + 1..] {
+                let gap = (0..2)
+                    .map(|axis| (min_b[axis] - max_a[axis]).max(min_a[axis] - max_b[axis]))
+                    .fold(f64::NEG_INFINITY, f64::max);
+                smallest = smallest.min(gap * texels);
+            }
+            for axis in 0..2 {
+                assert!(min_a[axis] * texels > options.padding as f64 - 1e-3);
+                assert!((1.0 - max_a[axis]) * texels > options.padding as f64 - 1e-3);
+            }
+        }
+        assert!(smallest > options.padding as f64 - 1e-3, "{}", smallest);
+        assert!(smallest < options.padding as f64 + 1.0, "{}", smallest);
+    }
+
+    #[test]
+    fn folded_grid_is_flattened_without_distortion() {
+        let mut node = triangle_grid(4);
+        // Fold the grid by 30 degrees along x = 2.
+        let folded: Vec<[f64; 3]> = node
+            .positions()
+            .unwrap()
+            .into_iter()
+            .map(|[x, y, z]| match x > 2.0 {
+                true => {
+                    let (sin, cos) = 30f64.to_radians().sin_cos();
+                    [2.0 + (x - 2.0) * cos, y, z + (x - 2.0) * sin]
+                }
+                false => [x, y, z],
+            })
+            .collect();
+        node.set_positions(&folded).unwrap();
+
+        let charts = node
+            .generate_lightmap_uvs(&LightmapOptions::new(64))
+            .unwrap();
+        assert!(charts.iter().all(|&chart| chart == 0));
+        let scales = edge_scales(&node);
+        assert!(scales.iter().all(|&s| (s - scales[0]).abs() < 1e-4));
+
+        // A sharp fold splits the grid.
+        let options = LightmapOptions {
+            max_edge_angle: 20f64.to_radians(),
+            ..LightmapOptions::new(64)
+        };
+        let charts = node.generate_lightmap_uvs(&options).unwrap();
+        assert_eq!(charts.iter().max(), Some(&1));
+    }
+}
@@ -0,0 +1,404 @@
+//! Merging files and combining geometry nodes.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::file::{rewrite_references, visit_references};
+use crate::{
+    convention, Error, File, GeometryNode, HXALayerDataType, Layer, LayerStack, Mat4, Meta,
+    MetaValue, ReferencePolicy, Result,
+};
+
+/// What to do when a merged node has the same `name` as a node already in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NameClash {
+    /// Add a `_1`, `_2`, ... suffix to the name of the merged node.
+    Rename,
+    /// Leave the merged node out, and point references to it at the node with the same name.
+    Skip,
+    /// Fail with [`Error::NameClash`] and leave the file unchanged.
+    Fail,
+}
+
+impl File {
+    /// Append the nodes of another file, updating the node references in them.
+    ///
+    /// Returns the index in this file of every node of `other`. Fails with
+    /// [`Error::DanglingReference`] if `other` references a node it does not have, other than
+    /// [`NULL_REFERENCE`](convention::NULL_REFERENCE).
+    pub fn merge(&mut self, mut other: File, name_clash: NameClash) -> Result<Vec<usize>> {
+        for (node, entry) in other.nodes.iter().enumerate() {
+            let mut dangling = None;
+            visit_references(&entry.meta, &mut |name, references| {
+                if let Some(&reference) = references
+                    .iter()
+                    .filter(|&&reference| reference != convention::NULL_REFERENCE)
+                    .find(|&&reference| reference as usize >= other.nodes.len())
+                {
+                    dangling.get_or_insert((name.to_string(), reference));
+                }
+            });
+            if let Some((meta, reference)) = dangling {
+                return Err(Error::DanglingReference {
+                    node,
+                    meta,
+                    reference,
+                });
+            }
+        }
+
+        let mut names: HashMap<String, usize> = HashMap::new();
+        for (node, entry) in self.nodes.iter().enumerate() {
+            if let Some(name) = entry.name() {
+                names.entry(name.to_string()).or_insert(node);
+            }
+        }
+        let mut targets = Vec::with_capacity(other.nodes.len());
+        let mut renames = Vec::new();
+        let mut next = self.nodes.len();
+        for (node, entry) in other.nodes.iter().enumerate() {
+            let name = entry.name().map(str::to_string);
+            let existing = name.as_ref().and_then(|name| names.get(name).copied());
+            match (existing, name_clash) {
+                (Some(existing), NameClash::Skip) => {
+                    targets.push(existing);
+                    continue;
+                }
+                (Some(_), NameClash::Fail) => {
+                    return Err(Error::NameClash {
+                        name: name.unwrap_or_default(),
+                    })
+                }
+                (Some(_), NameClash::Rename) => {
+                    let name = name.unwrap_or_default();
+                    let renamed = (1..)
+                        .map(|suffix| format!("{}_{}", name, suffix))
+                        .find(|renamed| !names.contains_key(renamed))
+                        .expect("a free suffix exists");
+                    names.insert(renamed.clone(), next);
+                    renames.push((node, renamed));
+                }
+                (None, _) => {
+                    if let Some(name) = name {
+                        names.insert(name, next);
+                    }
+                }
+            }
+            targets.push(next);
+            next += 1;
+        }
+
+        for (node, name) in renames {
+            other.nodes[node].set_name(name);
+        }
+        for (node, mut entry) in other.nodes.into_iter().enumerate() {
+            if targets[node] != self.nodes.len() {
+                continue;
+            }
+            rewrite_references(&mut entry.meta, &mut |references| {
+                for reference in references {
+                    *reference = targets[*reference as usize] as u32;
+                }
+            });
+            self.nodes.push(entry);
+        }
+
+        Ok(targets)
+    }
+
+    /// Combine geometry nodes with the same layers into the first node with those layers.
+    ///
+    /// Combined geometry is moved into the space of the node it is added to using their world
+    /// transforms, see [`Scene::world_transform`](crate::Scene::world_transform). Nodes with
+    /// children are never added to another node, as their children would lose their space. The
+    /// meta of combined nodes is dropped, they are removed from the `children` meta of their
+    /// parents, and other references to them point at the node they were added to. Returns the
+    /// new index of every node. Fails with [`Error::QuantizedPositions`] if a geometry node has
+    /// quantized positions.
+    pub fn combine_geometry(&mut self) -> Result<Vec<usize>> {
+        let scene = self.scene()?;
+        let mut heads: Vec<usize> = Vec::new();
+        let mut targets: Vec<usize> = (0..self.nodes.len()).collect();
+        for (node, target) in targets.iter_mut().enumerate() {
+            let Some(geometry) = self.nodes[node].geometry() else {
+                continue;
+            };
+            self.nodes[node].expect_unquantized()?;
+            let head = heads.iter().copied().find(|&head| {
+                let head_geometry = self.nodes[head].geometry().expect("heads are geometry");
+                head_geometry.check_schema(geometry).is_ok()
+                    && scene.world_transform(head).inverse().is_some()
+            });
+            let head = head.filter(|_| scene.children(node).is_empty());
+            let Some(head) = head else {
+                heads.push(node);
+                continue;
+            };
+
+            let relative = scene
+                .world_transform(head)
+                .inverse()
+                .expect("heads can be inverted")
+                * scene.world_transform(node);
+            let mut geometry = geometry.clone();
+            if relative != Mat4::IDENTITY {
+                geometry.apply_transform(&relative)?;
+            }
+            self.geometry_mut(head)?.append(&geometry)?;
+            *target = head;
+        }
+
+        let merged: HashSet<usize> = (0..targets.len())
+            .filter(|&node| targets[node] != node)
+            .collect();
+        for entry in &mut self.nodes {
+            if let Some(Meta {
+                value: MetaValue::Node(children),
+                ..
+            }) = entry
+                .meta
+                .iter_mut()
+                .find(|meta| meta.name == convention::CHILDREN)
+            {
+                children.retain(|&child| !merged.contains(&(child as usize)));
+            }
+            rewrite_references(&mut entry.meta, &mut |references| {
+                for reference in references {
+                    if let Some(&target) = targets.get(*reference as usize) {
+                        *reference = target as u32;
+                    }
+                }
+            });
+        }
+        let keep: Vec<bool> = (0..targets.len())
+            .map(|node| !merged.contains(&node))
+            .collect();
+        self.select_nodes(&keep, ReferencePolicy::Remove)?;
+
+        let mut new_index = Vec::with_capacity(keep.len());
+        let mut count = 0;
+        for &kept in &keep {
+            new_index.push(count);
+            count += kept as usize;
+        }
+        Ok(targets.iter().map(|&target| new_index[target]).collect())
+    }
+}
+
+impl GeometryNode {
+    /// Append the vertices, corners and faces of another node with the same layers.
+    ///
+    /// Fails with [`Error::SchemaMismatch`] if the stacks do not hold the same layers, in the
+    /// same order and with the same types and components.
+    pub fn append(&mut self, other: &GeometryNode) -> Result<()> {
+        self.check_schema(other)?;
+        let offset = self.vertex_count() as i32;
+        let reference: Vec<i32> = other
+            .reference()?
+            .iter()
+            .map(|&r| if r < 0 { r - offset } else { r + offset })
+            .collect();
+
+        append_stack(&mut self.vertex_stack, &other.vertex_stack);
+        self.reference_mut()?.extend(reference);
+        for (layer, appended) in self.corner_stack.layers[1..]
+            .iter_mut()
+            .zip(&other.corner_stack.layers[1..])
+        {
+            append_data(layer, appended);
+        }
+        append_stack(&mut self.edge_stack, &other.edge_stack);
+        append_stack(&mut self.face_stack, &other.face_stack);
+        if self
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            self.generate_neighbours()?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that both nodes have the same layers in every stack.
+    fn check_schema(&self, other: &GeometryNode) -> Result<()> {
+        let stacks = [
+            (&self.vertex_stack, &other.vertex_stack),
+            (&self.corner_stack, &other.corner_stack),
+            (&self.edge_stack, &other.edge_stack),
+            (&self.face_stack, &other.face_stack),
+        ];
+        for (stack, other) in stacks {
+            let schema = |stack: &LayerStack| -> Vec<(String, HXALayerDataType, u8)> {
+                stack
+                    .iter()
+                    .map(|layer| (layer.name.clone(), layer.data_type(), layer.components))
+                    .collect()
+            };
+            let (a, b) = (schema(stack), schema(other));
+            if a != b {
+                let mismatch = a
+                    .iter()
+                    .zip(&b)
+                    .find(|(a, b)| a != b)
+                    .map(|(a, _)| a)
+                    .or_else(|| a.get(b.len()).or_else(|| b.get(a.len())))
+                    .expect("schemas differ");
+                return Err(Error::SchemaMismatch {
+                    name: mismatch.0.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn append_stack(stack: &mut LayerStack, other: &LayerStack) {
+    for (layer, appended) in stack.iter_mut().zip(other) {
+        append_data(layer, appended);
+    }
+}
+
+fn append_data(layer: &mut Layer, other: &Layer) {
+    for index in 0..other.data.len() {
+        layer.data.extend_from_element(&other.data, 1, index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle};
+    use crate::{Layer, LayerData, Meta, MetaValue, Node, NodeContent};
+
+    fn named(name: &str) -> Node {
+        let mut node = Node::new(NodeContent::MetaOnly);
+        node.set_name(name);
+        node
+    }
+
+    fn names(file: &File) -> Vec<&str> {
+        file.nodes.iter().map(|node| node.name().unwrap()).collect()
+    }
+
+    /// A file with a mesh referencing a texture.
+    fn pack(mesh: &str, texture: &str) -> File {
+        let mut file = File::new();
+        file.nodes.push(named(mesh));
+        file.nodes.push(named(texture));
+        file.nodes[0].set_meta(Meta::new("texture", MetaValue::Node(vec![1])));
+        file
+    }
+
+    #[test]
+    fn merge_remaps_references() {
+        let mut file = pack("rock", "stone");
+        let targets = file.merge(pack("tree", "bark"), NameClash::Fail).unwrap();
+        assert_eq!(targets, vec![2, 3]);
+        assert_eq!(names(&file), ["rock", "stone", "tree", "bark"]);
+        assert_eq!(
+            file.nodes[2].find_meta("texture").unwrap().as_node(),
+            Some(&[3][..])
+        );
+
+        assert_eq!(
+            file.merge(pack("bush", "bark"), NameClash::Fail),
+            Err(Error::NameClash {
+                name: "bark".to_string()
+            })
+        );
+        assert_eq!(file.nodes.len(), 4);
+    }
+
+    #[test]
+    fn merge_name_clashes() {
+        let mut file = pack("rock", "stone");
+        let targets = file
+            .merge(pack("rock", "stone"), NameClash::Rename)
+            .unwrap();
+        assert_eq!(targets, vec![2, 3]);
+        assert_eq!(names(&file), ["rock", "stone", "rock_1", "stone_1"]);
+
+        // The skipped texture is shared with the existing one.
+        let targets = file
+            .merge(pack("pebble", "stone"), NameClash::Skip)
+            .unwrap();
+        assert_eq!(targets, vec![4, 1]);
+        assert_eq!(
+            file.nodes[4].find_meta("texture").unwrap().as_node(),
+            Some(&[1][..])
+        );
+    }
+
+    #[test]
+    fn combine_geometry_with_transforms() {
+        let mut file = File::new();
+        file.nodes.push(cube().into());
+        file.nodes.push(named("texture"));
+        let mut moved: Node = cube().into();
+        moved.set_transform(&Mat4::from_translation([2.0, 0.0, 0.0]));
+        file.nodes.push(moved);
+        let mut other = quad_and_triangle();
+        other
+            .face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(vec![0, 1])));
+        file.nodes.push(other.into());
+        file.nodes[1].set_meta(Meta::new("users", MetaValue::Node(vec![0, 2, 3])));
+
+        let targets = file.combine_geometry().unwrap();
+        assert_eq!(targets, vec![0, 1, 0, 2]);
+        assert_eq!(file.nodes.len(), 3);
+        let combined = file.nodes[0].geometry().unwrap();
+        assert_eq!(combined.vertex_count(), 16);
+        assert_eq!(combined.face_count(), 12);
+        assert_eq!(combined.aabb().unwrap().unwrap().max, [3.0, 1.0, 1.0]);
+        assert!(combined.is_closed().unwrap());
+        combined.validate().unwrap();
+        assert_eq!(
+            file.nodes[1].find_meta("users").unwrap().as_node(),
+            Some(&[0, 0, 2][..])
+        );
+    }
+
+    #[test]
+    fn combine_geometry_in_world_space() {
+        // 0 is a group moved along X holding the cube 1, 2 is a cube at the origin, and 3 is a
+        // cube with a child, which stays a node of its own.
+        let mut file = File::new();
+        let mut group = named("group");
+        group.set_transform(&Mat4::from_translation([2.0, 0.0, 0.0]));
+        file.nodes.push(group);
+        file.nodes.push(cube().into());
+        file.nodes.push(cube().into());
+        file.nodes.push(cube().into());
+        file.nodes.push(named("child"));
+        file.set_parent(1, Some(0)).unwrap();
+        file.set_parent(4, Some(3)).unwrap();
+
+        let targets = file.combine_geometry().unwrap();
+        assert_eq!(targets, vec![0, 1, 1, 2, 3]);
+        let combined = file.nodes[1].geometry().unwrap();
+        assert_eq!(combined.vertex_count(), 16);
+        assert_eq!(combined.aabb().unwrap().unwrap().min, [-2.0, 0.0, 0.0]);
+        assert_eq!(file.nodes[2].geometry().unwrap().vertex_count(), 8);
+
+        let scene = file.scene().unwrap();
+        assert_eq!(scene.children(0), &[1]);
+        assert_eq!(scene.children(2), &[3]);
+    }
+
+    #[test]
+    fn append_requires_matching_layers() {
+        let mut node = cube();
+        let mut other = cube();
+        other
+            .face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(vec![0; 6])));
+        assert_eq!(
+            node.append(&other),
+            Err(Error::SchemaMismatch {
+                name: "material".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use hxa_sys::HXAMetaDataType;
+
+/// A named meta data value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Meta {
+    pub name: String,
+    pub value: MetaValue,
+}
+
+/// The values stored in a meta entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaValue {
+    Int64(Vec<u64>),
+    Double(Vec<f64>),
+    /// References to other nodes, as indices into [`File::nodes`](crate::File::nodes).
+    Node(Vec<u32>),
+    Text(String),
+    Binary(Vec<u8>),
+    Meta(Vec<Meta>),
+}
+
+impl MetaValue {
+    pub fn data_type(&self) -> HXAMetaDataType {
+        match self {
+            MetaValue::Int64(_) => HXAMetaDataType::HXA_MDT_INT64,
+            MetaValue::Double(_) => HXAMetaDataType::HXA_MDT_DOUBLE,
+            MetaValue::Node(_) => HXAMetaDataType::HXA_MDT_NODE,
+            MetaValue::Text(_) => HXAMetaDataType::HXA_MDT_TEXT,
+            MetaValue::Binary(_) => HXAMetaDataType::HXA_MDT_BINARY,
+            MetaValue::Meta(_) => HXAMetaDataType::HXA_MDT_META,
+        }
+    }
+
+    /// The number of values stored, or the length of the text.
+    pub fn array_length(&self) -> usize {
+        match self {
+            MetaValue::Int64(values) => values.len(),
+            MetaValue::Double(values) => values.len(),
+            MetaValue::Node(values) => values.len(),
+            MetaValue::Text(text) => text.len(),
+            MetaValue::Binary(values) => values.len(),
+            MetaValue::Meta(values) => values.len(),
+        }
+    }
+}
+
+impl Meta {
+    pub fn new(name: impl Into<String>, value: MetaValue) -> Self {
+        Self {
+            name: name.into(),
+            value,
+        }
+    }
+
+    pub fn as_int64(&self) -> Option<&[u64]> {
+        match &self.value {
+            MetaValue::Int64(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_double(&self) -> Option<&[f64]> {
+        match &self.value {
+            MetaValue::Double(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_node(&self) -> Option<&[u32]> {
+        match &self.value {
+            MetaValue::Node(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match &self.value {
+            MetaValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_binary(&self) -> Option<&[u8]> {
+        match &self.value {
+            MetaValue::Binary(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_meta(&self) -> Option<&[Meta]> {
+        match &self.value {
+            MetaValue::Meta(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Find the first meta entry with the given name.
+pub fn find_meta<'a>(meta: &'a [Meta], name: &str) -> Option<&'a Meta> {
+    meta.iter().find(|meta| meta.name == name)
+}
+
+/// Replace the first meta entry with the same name, or append it.
+pub fn set_meta(meta: &mut Vec<Meta>, entry: Meta) {
+    match meta.iter_mut().find(|meta| meta.name == entry.name) {
+        Some(existing) => *existing = entry,
+        None => meta.push(entry),
+    }
+}
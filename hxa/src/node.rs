@@ -0,0 +1,125 @@
+use hxa_sys::{HXAImageType, HXANodeType};
+
+use crate::{convention, meta, GeometryNode, LayerStack, Meta, MetaValue};
+
+/// A node in a file. All nodes have meta data, geometry nodes have geometry and image nodes have
+/// pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub meta: Vec<Meta>,
+    pub content: NodeContent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeContent {
+    MetaOnly,
+    Geometry(GeometryNode),
+    Image(ImageNode),
+}
+
+/// A 1D, 2D, 3D or cube image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageNode {
+    pub image_type: HXAImageType,
+    /// Resolution in the X, Y and Z dimension. Unused dimensions are 1.
+    pub resolution: [u32; 3],
+    /// The number of values in the stack is equal to the number of pixels.
+    pub image_stack: LayerStack,
+}
+
+impl ImageNode {
+    pub fn new(image_type: HXAImageType, resolution: [u32; 3]) -> Self {
+        Self {
+            image_type,
+            resolution,
+            image_stack: LayerStack::new(),
+        }
+    }
+
+    /// The number of pixels in every layer of the image stack.
+    pub fn pixel_count(&self) -> usize {
+        let [x, y, z] = self.resolution.map(|r| r as usize);
+        match self.image_type {
+            HXAImageType::HXA_IT_CUBE_IMAGE => x * y * 6,
+            HXAImageType::HXA_IT_1D_IMAGE => x,
+            HXAImageType::HXA_IT_2D_IMAGE => x * y,
+            HXAImageType::HXA_IT_3D_IMAGE => x * y * z,
+        }
+    }
+}
+
+impl Node {
+    pub fn new(content: NodeContent) -> Self {
+        Self {
+            meta: Vec::new(),
+            content,
+        }
+    }
+
+    pub fn node_type(&self) -> HXANodeType {
+        match self.content {
+            NodeContent::MetaOnly => HXANodeType::HXA_NT_META_ONLY,
+            NodeContent::Geometry(_) => HXANodeType::HXA_NT_GEOMETRY,
+            NodeContent::Image(_) => HXANodeType::HXA_NT_IMAGE,
+        }
+    }
+
+    pub fn geometry(&self) -> Option<&GeometryNode> {
+        match &self.content {
+            NodeContent::Geometry(geometry) => Some(geometry),
+            _ => None,
+        }
+    }
+
+    pub fn geometry_mut(&mut self) -> Option<&mut GeometryNode> {
+        match &mut self.content {
+            NodeContent::Geometry(geometry) => Some(geometry),
+            _ => None,
+        }
+    }
+
+    pub fn image(&self) -> Option<&ImageNode> {
+        match &self.content {
+            NodeContent::Image(image) => Some(image),
+            _ => None,
+        }
+    }
+
+    pub fn image_mut(&mut self) -> Option<&mut ImageNode> {
+        match &mut self.content {
+            NodeContent::Image(image) => Some(image),
+            _ => None,
+        }
+    }
+
+    /// Find the first meta entry with the given name.
+    pub fn find_meta(&self, name: &str) -> Option<&Meta> {
+        meta::find_meta(&self.meta, name)
+    }
+
+    /// Replace the first meta entry with the same name, or append it.
+    pub fn set_meta(&mut self, entry: Meta) {
+        meta::set_meta(&mut self.meta, entry);
+    }
+
+    /// The `name` meta text of the node.
+    pub fn name(&self) -> Option<&str> {
+        self.find_meta(convention::NAME).and_then(Meta::as_text)
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.set_meta(Meta::new(convention::NAME, MetaValue::Text(name.into())));
+    }
+}
+
+impl From<GeometryNode> for Node {
+    fn from(geometry: GeometryNode) -> Self {
+        Node::new(NodeContent::Geometry(geometry))
+    }
+}
+
+impl From<ImageNode> for Node {
+    fn from(image: ImageNode) -> Self {
+        Node::new(NodeContent::Image(image))
+    }
+}
@@ -0,0 +1,401 @@
+//! Reordering of triangle meshes for faster rendering on GPUs.
+//!
+//! All passes only change the order of faces or vertices. Corner, edge and face layers are
+//! reordered with their faces and vertex layers with their vertices, so the node describes the
+//! same mesh afterwards. The passes are meant to be run in the order vertex cache, overdraw, then
+//! vertex fetch.
+
+use std::cmp::Ordering;
+
+use crate::math::{add, dot, scale, sub, triangle_normal, Vec3};
+use crate::{GeometryNode, Result};
+
+/// Vertex cache size of most GPUs, a good default for the passes below.
+pub const DEFAULT_CACHE_SIZE: usize = 16;
+
+/// Largest cache size supported by [`GeometryNode::optimize_vertex_cache`].
+const MAX_CACHE_SIZE: usize = 64;
+
+impl GeometryNode {
+    /// The average number of vertices transformed per triangle, with a FIFO post transform cache
+    /// of `cache_size` vertices. 3 is the worst case and values around 0.6 are close to optimal.
+    pub fn acmr(&self, cache_size: usize) -> Result<f64> {
+        let triangles = self.triangles()?;
+        if triangles.is_empty() {
+            return Ok(0.0);
+        }
+        let misses: usize = cache_misses(&triangles, self.vertex_count(), cache_size)
+            .iter()
+            .sum();
+
+        Ok(misses as f64 / triangles.len() as f64)
+    }
+
+    /// Reorder the triangles to reuse transformed vertices from the post transform cache.
+    ///
+    /// Uses Tom Forsyth's linear speed vertex cache optimisation, which works well for any cache
+    /// size up to `cache_size`. Fails with [`Error::NotTriangulated`](crate::Error) if the node
+    /// has faces that are not triangles.
+    pub fn optimize_vertex_cache(&mut self, cache_size: usize) -> Result<()> {
+        let triangles = self.triangles()?;
+        let order = forsyth_order(&triangles, self.vertex_count(), cache_size);
+        self.reorder_triangles(&order)
+    }
+
+    /// Reorder clusters of triangles so that triangles facing away from the center of the mesh,
+    /// which are likely to occlude the rest, are drawn first.
+    ///
+    /// The node should already be optimized for the vertex cache. Clusters are split where the
+    /// cache is flushed, and also where the cluster ACMR stays below `threshold` times the ACMR of
+    /// the mesh. If the reordered mesh has an ACMR above `threshold` times the original one, the
+    /// triangles are left in their order, so a threshold of 1.05 gives up at most 5% of vertex
+    /// cache efficiency for less overdraw.
+    pub fn optimize_overdraw(&mut self, cache_size: usize, threshold: f64) -> Result<()> {
+        let triangles = self.triangles()?;
+        if triangles.is_empty() {
+            return Ok(());
+        }
+        let positions = self.positions()?;
+        let misses = cache_misses(&triangles, self.vertex_count(), cache_size);
+        let mesh_acmr = misses.iter().sum::<usize>() as f64 / triangles.len() as f64;
+
+        let mut clusters = Vec::new();
+        let mut start = 0;
+        let mut cluster_misses = 0;
+        for (triangle, &miss) in misses.iter().enumerate() {
+            cluster_misses += miss;
+            let end = triangle + 1;
+            let hard_boundary = end == misses.len() || misses[end] == 3;
+            let soft_boundary =
+                cluster_misses as f64 / (end - start) as f64 <= threshold * mesh_acmr;
+            if hard_boundary || soft_boundary {
+                clusters.push(start..end);
+                start = end;
+                cluster_misses = 0;
+            }
+        }
+
+        let mesh_centroid = centroid(&positions, &triangles, 0..triangles.len());
+        let mut sorted: Vec<(f64, std::ops::Range<usize>)> = clusters
+            .into_iter()
+            .map(|cluster| {
+                let mut normal = [0.0; 3];
+                for &[a, b, c] in &triangles[cluster.clone()] {
+                    normal = add(
+                        normal,
+                        triangle_normal(positions[a], positions[b], positions[c]),
+                    );
+                }
+                let offset = sub(
+                    centroid(&positions, &triangles, cluster.clone()),
+                    mesh_centroid,
+                );
+                (dot(offset, normal), cluster)
+            })
+            .collect();
+        sorted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+        let order: Vec<usize> = sorted
+            .into_iter()
+            .flat_map(|(_, cluster)| cluster)
+            .collect();
+        let reordered: Vec<[usize; 3]> = order.iter().map(|&t| triangles[t]).collect();
+        let reordered_misses = cache_misses(&reordered, self.vertex_count(), cache_size);
+        let reordered_acmr = reordered_misses.iter().sum::<usize>() as f64 / triangles.len() as f64;
+        if reordered_acmr > threshold * mesh_acmr {
+            return Ok(());
+        }
+        self.reorder_triangles(&order)
+    }
+
+    /// Renumber the vertices in the order they are first used by the corners, so the vertex
+    /// buffer is read close to sequentially. Unused vertices are moved to the end.
+    pub fn optimize_vertex_fetch(&mut self) -> Result<()> {
+        let mut seen = vec![false; self.vertex_count()];
+        let mut order = Vec::with_capacity(seen.len());
+        for vertex in self.corner_vertices()? {
+            if !seen[vertex] {
+                seen[vertex] = true;
+                order.push(vertex);
+            }
+        }
+        order.extend((0..seen.len()).filter(|&v| !seen[v]));
+
+        self.reorder_vertices(&order)
+    }
+
+    fn reorder_triangles(&mut self, order: &[usize]) -> Result<()> {
+        let faces: Vec<(usize, Vec<usize>)> = order
+            .iter()
+            .map(|&face| (face, vec![face * 3, face * 3 + 1, face * 3 + 2]))
+            .collect();
+        *self = self.rebuild_faces(&faces)?;
+
+        Ok(())
+    }
+}
+
+/// The number of vertices missing from a FIFO cache for each triangle, in order.
+fn cache_misses(triangles: &[[usize; 3]], vertex_count: usize, cache_size: usize) -> Vec<usize> {
+    // The time each vertex entered the cache, a vertex is cached if it entered less than
+    // `cache_size` misses ago.
+    let mut entered = vec![usize::MAX; vertex_count];
+    let mut time = 0;
+    triangles
+        .iter()
+        .map(|triangle| {
+            let mut misses = 0;
+            for &vertex in triangle {
+                if entered[vertex] == usize::MAX || time - entered[vertex] >= cache_size {
+                    entered[vertex] = time;
+                    time += 1;
+                    misses += 1;
+                }
+            }
+            misses
+        })
+        .collect()
+}
+
+fn centroid(positions: &[Vec3], triangles: &[[usize; 3]], range: std::ops::Range<usize>) -> Vec3 {
+    let mut sum = [0.0; 3];
+    let count = range.len();
+    for &[a, b, c] in &triangles[range] {
+        sum = add(sum, add(positions[a], add(positions[b], positions[c])));
+    }
+    scale(sum, 1.0 / (count * 3).max(1) as f64)
+}
+
+const VALENCE_BOOST_SCALE: f64 = 2.0;
+const VALENCE_BOOST_POWER: f64 = 0.5;
+const CACHE_DECAY_POWER: f64 = 1.5;
+const LAST_TRIANGLE_SCORE: f64 = 0.75;
+
+fn vertex_score(cache_position: Option<usize>, cache_size: usize, remaining: usize) -> f64 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (cache_size - 3) as f64;
+            (1.0 - (position - 3) as f64 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f64).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Order triangles with Tom Forsyth's vertex cache optimisation.
+fn forsyth_order(triangles: &[[usize; 3]], vertex_count: usize, cache_size: usize) -> Vec<usize> {
+    let cache_size = cache_size.clamp(4, MAX_CACHE_SIZE);
+
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (triangle, vertices) in triangles.iter().enumerate() {
+        for &vertex in vertices {
+            vertex_triangles[vertex].push(triangle);
+        }
+    }
+    let mut remaining: Vec<usize> = vertex_triangles.iter().map(Vec::len).collect();
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f64> = (0..vertex_count)
+        .map(|v| vertex_score(None, cache_size, remaining[v]))
+        .collect();
+    let mut triangle_scores: Vec<f64> = triangles
+        .iter()
+        .map(|t| t.iter().map(|&v| vertex_scores[v]).sum())
+        .collect();
+    let mut emitted = vec![false; triangles.len()];
+
+    let mut order = Vec::with_capacity(triangles.len());
+    let mut cache: Vec<usize> = Vec::with_capacity(cache_size + 3);
+    let mut best = best_triangle(&triangle_scores, &emitted, 0..triangles.len());
+    let mut scan_start = 0;
+    while let Some(triangle) = best {
+        emitted[triangle] = true;
+        order.push(triangle);
+
+        let mut new_cache = Vec::with_capacity(cache_size + 3);
+        for &vertex in &triangles[triangle] {
+            remaining[vertex] -= 1;
+            new_cache.push(vertex);
+        }
+        new_cache.extend(
+            cache
+                .iter()
+                .copied()
+                .filter(|v| !triangles[triangle].contains(v)),
+        );
+        for &evicted in new_cache.iter().skip(cache_size) {
+            cache_position[evicted] = None;
+        }
+        let touched = new_cache.clone();
+        new_cache.truncate(cache_size);
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            cache_position[vertex] = Some(position);
+        }
+        cache = new_cache;
+
+        for &vertex in &touched {
+            let score = vertex_score(cache_position[vertex], cache_size, remaining[vertex]);
+            let delta = score - vertex_scores[vertex];
+            vertex_scores[vertex] = score;
+            for &t in &vertex_triangles[vertex] {
+                triangle_scores[t] += delta;
+            }
+        }
+
+        best = None;
+        let mut best_score = f64::NEG_INFINITY;
+        for &vertex in &cache {
+            for &t in &vertex_triangles[vertex] {
+                if !emitted[t] && triangle_scores[t] > best_score {
+                    best_score = triangle_scores[t];
+                    best = Some(t);
+                }
+            }
+        }
+        if best.is_none() {
+            while scan_start < triangles.len() && emitted[scan_start] {
+                scan_start += 1;
+            }
+            best = best_triangle(&triangle_scores, &emitted, scan_start..triangles.len());
+        }
+    }
+
+    order
+}
+
+fn best_triangle(scores: &[f64], emitted: &[bool], range: std::ops::Range<usize>) -> Option<usize> {
+    range
+        .filter(|&t| !emitted[t])
+        .max_by(|&a, &b| scores[a].partial_cmp(&scores[b]).unwrap_or(Ordering::Equal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, triangle_grid};
+    use crate::{Error, Layer, LayerData};
+
+    fn shuffled_grid() -> GeometryNode {
+        let mut node = triangle_grid(16);
+        let face_count = node.face_count();
+        node.face_stack.push(Layer::new(
+            "material",
+            1,
+            LayerData::Int32((0..face_count as i32).collect()),
+        ));
+        // A deterministic shuffle of the triangles, far from cache friendly.
+        let order: Vec<usize> = (0..face_count).map(|i| (i * 97) % face_count).collect();
+        node.reorder_triangles(&order).unwrap();
+        node
+    }
+
+    /// Every face as its sorted vertex positions and material, to compare meshes regardless of
+    /// order.
+    fn face_set(node: &GeometryNode) -> Vec<(Vec<[i64; 3]>, i32)> {
+        let positions = node.positions().unwrap();
+        let material = node.face_stack.get("material").unwrap();
+        let mut faces: Vec<_> = node
+            .polygons()
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(face, polygon)| {
+                let mut points: Vec<[i64; 3]> = polygon
+                    .iter()
+                    .map(|&v| positions[v].map(|x| x as i64))
+                    .collect();
+                points.sort();
+                (points, material.get(face, 0) as i32)
+            })
+            .collect();
+        faces.sort();
+        faces
+    }
+
+    #[test]
+    fn vertex_cache_lowers_acmr() {
+        let mut node = shuffled_grid();
+        let before = node.acmr(DEFAULT_CACHE_SIZE).unwrap();
+        let faces = face_set(&node);
+        node.optimize_vertex_cache(DEFAULT_CACHE_SIZE).unwrap();
+        let after = node.acmr(DEFAULT_CACHE_SIZE).unwrap();
+
+        assert!(before > 2.0, "{}", before);
+        assert!(after < 1.0, "{}", after);
+        assert_eq!(face_set(&node), faces);
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn overdraw_keeps_faces_and_cache() {
+        let mut node = shuffled_grid();
+        node.optimize_vertex_cache(DEFAULT_CACHE_SIZE).unwrap();
+        let acmr = node.acmr(DEFAULT_CACHE_SIZE).unwrap();
+        let faces = face_set(&node);
+        let mut strict = node.clone();
+        node.optimize_overdraw(DEFAULT_CACHE_SIZE, 1.05).unwrap();
+
+        assert_eq!(face_set(&node), faces);
+        assert!(node.acmr(DEFAULT_CACHE_SIZE).unwrap() <= acmr * 1.05);
+        node.validate().unwrap();
+
+        // No order has half the ACMR of a cache optimized one, so nothing changes.
+        let triangles = strict.triangles().unwrap();
+        strict.optimize_overdraw(DEFAULT_CACHE_SIZE, 0.5).unwrap();
+        assert_eq!(strict.triangles().unwrap(), triangles);
+    }
+
+    #[test]
+    fn overdraw_draws_outer_faces_first() {
+        // Both triangles face +z. The one below the center faces the other and is likely
+        // occluded by it, so the one above is drawn first.
+        let mut node = GeometryNode::from_polygons(
+            &[
+                [0.0, 0.0, -1.0],
+                [1.0, 0.0, -1.0],
+                [0.0, 1.0, -1.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 0.0, 1.0],
+                [0.0, 1.0, 1.0],
+            ],
+            &[vec![0, 1, 2], vec![3, 4, 5]],
+        );
+        node.optimize_overdraw(DEFAULT_CACHE_SIZE, 1.05).unwrap();
+
+        assert_eq!(node.triangles().unwrap(), vec![[3, 4, 5], [0, 1, 2]]);
+    }
+
+    #[test]
+    fn vertex_fetch_orders_by_first_use() {
+        let mut node = GeometryNode::from_polygons(
+            &[[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [7.0; 3]],
+            &[vec![2, 0, 1]],
+        );
+        node.vertex_stack.push(Layer::new(
+            "weight",
+            1,
+            LayerData::Uint8(vec![10, 11, 12, 13]),
+        ));
+        node.optimize_vertex_fetch().unwrap();
+
+        assert_eq!(node.reference().unwrap(), &[0, 1, -3]);
+        assert_eq!(
+            node.vertex_stack.get("weight").unwrap().data,
+            LayerData::Uint8(vec![12, 10, 11, 13])
+        );
+        assert_eq!(node.positions().unwrap()[0], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn passes_require_triangles() {
+        let mut node = cube();
+
+        assert_eq!(
+            node.optimize_vertex_cache(DEFAULT_CACHE_SIZE),
+            Err(Error::NotTriangulated { face: 0 })
+        );
+    }
+}
@@ -0,0 +1,565 @@
+//! Cleaning up broken geometry.
+//!
+//! Scanned and converted meshes often carry faces without area, faces listed twice, faces wound
+//! against their neighbours, small holes, edges shared by more than two faces and vertices
+//! nothing uses. [`GeometryNode::repair`] fixes these in one pass, keeping every layer stack in
+//! sync with the faces that remain.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::geometry::encode_reference;
+use crate::math::{cross, dot, length, polygon_normal, Vec3};
+use crate::{convention, GeometryNode, Layer, LayerData, Result};
+
+/// Settings of a repair.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RepairOptions {
+    /// Faces with at most this area are removed.
+    pub min_area: f64,
+    /// Flip faces so neighbours agree on their winding, and closed parts face outwards.
+    pub fix_winding: bool,
+    /// Fill holes bounded by at most this many edges with a new face. `None` leaves holes open.
+    pub max_hole_edges: Option<usize>,
+    /// Give every sheet of faces meeting at an edge shared by more than two faces its own copies
+    /// of the vertices along the edge.
+    pub split_non_manifold: bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            min_area: 0.0,
+            fix_winding: true,
+            max_hole_edges: None,
+            split_non_manifold: true,
+        }
+    }
+}
+
+/// What a repair changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Faces removed for having less than 3 corners, a repeated vertex or no area.
+    pub degenerate_faces: usize,
+    /// Faces removed for using the same vertices as an earlier face.
+    pub duplicate_faces: usize,
+    pub flipped_faces: usize,
+    pub filled_holes: usize,
+    /// Vertices added to split edges shared by more than two faces.
+    pub split_vertices: usize,
+    pub removed_vertices: usize,
+    /// Edges still shared by more than two faces after the repair, because splitting was turned
+    /// off or the faces around them are connected elsewhere.
+    pub non_manifold_edges: usize,
+}
+
+impl RepairReport {
+    /// Check if the repair left the node as it was.
+    pub fn is_unchanged(&self) -> bool {
+        self.degenerate_faces == 0
+            && self.duplicate_faces == 0
+            && self.flipped_faces == 0
+            && self.filled_holes == 0
+            && self.split_vertices == 0
+            && self.removed_vertices == 0
+    }
+}
+
+/// A face of the repaired node, with the elements it copies its layer values from.
+struct Face {
+    source: Option<usize>,
+    vertices: Vec<usize>,
+    corners: Vec<Option<usize>>,
+    /// The edge starting at each corner.
+    edges: Vec<Option<usize>>,
+}
+
+impl Face {
+    /// Reverse the winding, keeping every edge value on the same pair of vertices.
+    fn flip(&mut self) {
+        self.vertices.reverse();
+        self.corners.reverse();
+        self.edges.reverse();
+        self.edges.rotate_left(1);
+    }
+}
+
+impl GeometryNode {
+    /// Remove degenerate and duplicate faces, make the winding consistent, fill small holes, split
+    /// non-manifold edges and remove unused vertices.
+    ///
+    /// Removed faces take their corner, edge and face values with them. Flipped faces keep the
+    /// values of their corners and of their edges, so vectors like normals are not negated.
+    /// Filling faces copy the corner values of the faces around the hole at the same vertices,
+    /// the face values of the face along their first edge, and get zeros in the edge layers. The
+    /// `neighbour` layer is regenerated if present.
+    ///
+    /// Winding spreads from the first face of every connected part across edges shared by two
+    /// faces. Closed parts with a negative volume are then turned inside out.
+    ///
+    /// Faces connected across edges shared by exactly two faces form a sheet. Where sheets meet
+    /// at an edge shared by more than two faces, the sheet of the first face using a vertex on
+    /// such an edge keeps it, and every other sheet gets a copy with the same vertex values.
+    pub fn repair(&mut self, options: &RepairOptions) -> Result<RepairReport> {
+        let positions = self.positions()?;
+        let corner_vertices = self.corner_vertices()?;
+        let mut report = RepairReport::default();
+
+        let mut faces = Vec::new();
+        let mut seen = HashSet::new();
+        for (index, range) in self.faces()?.into_iter().enumerate() {
+            let vertices: Vec<usize> = range.clone().map(|c| corner_vertices[c]).collect();
+            let unique: HashSet<usize> = vertices.iter().copied().collect();
+            let points: Vec<Vec3> = vertices.iter().map(|&v| positions[v]).collect();
+            if vertices.len() < 3
+                || unique.len() < vertices.len()
+                || length(polygon_normal(&points)) / 2.0 <= options.min_area
+            {
+                report.degenerate_faces += 1;
+            } else if !seen.insert(canonical(&vertices)) {
+                report.duplicate_faces += 1;
+            } else {
+                faces.push(Face {
+                    source: Some(index),
+                    vertices,
+                    corners: range.clone().map(Some).collect(),
+                    edges: range.map(Some).collect(),
+                });
+            }
+        }
+
+        if options.fix_winding {
+            report.flipped_faces = fix_winding(&mut faces, &positions);
+        }
+        if let Some(max_edges) = options.max_hole_edges {
+            report.filled_holes = fill_holes(&mut faces, max_edges);
+        }
+        let mut copies = Vec::new();
+        if options.split_non_manifold {
+            copies = split_non_manifold(&mut faces, positions.len());
+            report.split_vertices = copies.len();
+        }
+        report.non_manifold_edges = edge_faces(&faces)
+            .values()
+            .filter(|faces| faces.len() > 2)
+            .count();
+
+        if !report.is_unchanged() {
+            if !copies.is_empty() {
+                let sources: Vec<usize> = (0..positions.len()).chain(copies).collect();
+                self.vertex_stack = self.vertex_stack.gather(&sources);
+            }
+            self.rebuild(&faces)?;
+        }
+        report.removed_vertices = self.remove_unused_vertices()?;
+
+        Ok(report)
+    }
+
+    /// Replace the faces of the node, gathering the corner, edge and face layers.
+    fn rebuild(&mut self, faces: &[Face]) -> Result<()> {
+        let polygons: Vec<Vec<u32>> = faces
+            .iter()
+            .map(|face| face.vertices.iter().map(|&v| v as u32).collect())
+            .collect();
+        let corners: Vec<Option<usize>> = faces.iter().flat_map(|f| f.corners.clone()).collect();
+        let edges: Vec<Option<usize>> = faces.iter().flat_map(|f| f.edges.clone()).collect();
+        let sources: Vec<Option<usize>> = faces.iter().map(|face| face.source).collect();
+
+        let mut corner_stack = self.corner_stack.gather_optional(&corners);
+        corner_stack.layers[0] = Layer::new(
+            self.corner_stack.layers[0].name.clone(),
+            convention::BASE_CORNER_LAYER_COMPONENTS,
+            LayerData::Int32(encode_reference(&polygons)),
+        );
+        self.corner_stack = corner_stack;
+        self.edge_stack = self.edge_stack.gather_optional(&edges);
+        self.face_stack = self.face_stack.gather_optional(&sources);
+        if self
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            self.generate_neighbours()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The vertices of a polygon starting at the smallest one, in the direction that makes the
+/// sequence smallest, so the same polygon gives the same key with any rotation or winding.
+fn canonical(vertices: &[usize]) -> Vec<usize> {
+    let start = (0..vertices.len())
+        .min_by_key(|&i| vertices[i])
+        .unwrap_or(0);
+    let mut forward = vertices.to_vec();
+    forward.rotate_left(start);
+    let mut backward = forward.clone();
+    backward[1..].reverse();
+    forward.min(backward)
+}
+
+/// The faces using each edge, keyed by its vertices in increasing order, along with whether the
+/// face runs along the edge in that order.
+fn edge_faces(faces: &[Face]) -> HashMap<(usize, usize), Vec<(usize, bool)>> {
+    let mut edges: HashMap<(usize, usize), Vec<(usize, bool)>> = HashMap::new();
+    for (index, face) in faces.iter().enumerate() {
+        for (i, &a) in face.vertices.iter().enumerate() {
+            let b = face.vertices[(i + 1) % face.vertices.len()];
+            edges
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((index, a < b));
+        }
+    }
+    edges
+}
+
+/// Flip faces to agree with their neighbours and closed parts to face outwards, returning how
+/// many faces were flipped.
+fn fix_winding(faces: &mut [Face], positions: &[Vec3]) -> usize {
+    let edges = edge_faces(faces);
+    let mut neighbours = vec![Vec::new(); faces.len()];
+    for users in edges.values() {
+        if let &[(a, a_forward), (b, b_forward)] = users.as_slice() {
+            // Neighbours agree when they run along their shared edge in opposite directions.
+            neighbours[a].push((b, a_forward == b_forward));
+            neighbours[b].push((a, a_forward == b_forward));
+        }
+    }
+
+    let mut flip: Vec<Option<bool>> = vec![None; faces.len()];
+    for first in 0..faces.len() {
+        if flip[first].is_some() {
+            continue;
+        }
+        flip[first] = Some(false);
+        let mut part = vec![first];
+        let mut queue = VecDeque::from([first]);
+        while let Some(face) = queue.pop_front() {
+            let flipped = flip[face] == Some(true);
+            for &(neighbour, disagrees) in &neighbours[face] {
+                if flip[neighbour].is_none() {
+                    flip[neighbour] = Some(flipped != disagrees);
+                    part.push(neighbour);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        // Every edge of a closed part is shared by two of its faces.
+        let closed = part
+            .iter()
+            .all(|&face| neighbours[face].len() == faces[face].vertices.len());
+        if closed {
+            let volume: f64 = part
+                .iter()
+                .map(|&face| {
+                    let points: Vec<Vec3> =
+                        faces[face].vertices.iter().map(|&v| positions[v]).collect();
+                    let volume: f64 = points[1..]
+                        .windows(2)
+                        .map(|pair| dot(points[0], cross(pair[0], pair[1])))
+                        .sum();
+                    if flip[face] == Some(true) {
+                        -volume
+                    } else {
+                        volume
+                    }
+                })
+                .sum();
+            if volume < 0.0 {
+                for &face in &part {
+                    flip[face] = flip[face].map(|flipped| !flipped);
+                }
+            }
+        }
+    }
+
+    let mut count = 0;
+    for (face, flipped) in faces.iter_mut().zip(flip) {
+        if flipped == Some(true) {
+            face.flip();
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Close holes bounded by at most `max_edges` edges with a new face, returning how many were
+/// filled.
+///
+/// Boundary edges are edges used by a single face. The new face runs along them in the opposite
+/// direction, and holes touching another hole at a vertex are left open.
+fn fill_holes(faces: &mut Vec<Face>, max_edges: usize) -> usize {
+    // For every vertex on a hole, the next vertex along the hole and where the new face copies
+    // its values from: the corner at the vertex and the face along the edge.
+    let mut next: HashMap<usize, Vec<(usize, Option<usize>, usize)>> = HashMap::new();
+    let edges = edge_faces(faces);
+    for (index, face) in faces.iter().enumerate() {
+        for (i, &a) in face.vertices.iter().enumerate() {
+            let following = (i + 1) % face.vertices.len();
+            let b = face.vertices[following];
+            if edges[&(a.min(b), a.max(b))].len() == 1 {
+                next.entry(b)
+                    .or_default()
+                    .push((a, face.corners[following], index));
+            }
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut starts: Vec<usize> = next.keys().copied().collect();
+    starts.sort_unstable();
+    let mut filled = 0;
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+        let mut hole = Vec::new();
+        let mut vertex = start;
+        let closed = loop {
+            match next.get(&vertex).map(Vec::as_slice) {
+                Some(&[step]) if visited.insert(vertex) => {
+                    hole.push((vertex, step.1, step.2));
+                    vertex = step.0;
+                    if vertex == start {
+                        break true;
+                    }
+                }
+                _ => break false,
+            }
+        };
+        if closed && hole.len() <= max_edges {
+            faces.push(Face {
+                source: faces[hole[0].2].source,
+                vertices: hole.iter().map(|step| step.0).collect(),
+                corners: hole.iter().map(|step| step.1).collect(),
+                edges: vec![None; hole.len()],
+            });
+            filled += 1;
+        }
+    }
+    filled
+}
+
+/// Give every sheet of faces meeting at an edge shared by more than two faces its own copies of
+/// the vertices along the edge, returning the vertex each new vertex copies. New vertices are
+/// numbered from `vertex_count`.
+fn split_non_manifold(faces: &mut [Face], vertex_count: usize) -> Vec<usize> {
+    /// The lowest face of the sheet `face` belongs to.
+    fn root(sheet: &mut [usize], mut face: usize) -> usize {
+        while sheet[face] != face {
+            sheet[face] = sheet[sheet[face]];
+            face = sheet[face];
+        }
+        face
+    }
+
+    let mut sheet: Vec<usize> = (0..faces.len()).collect();
+    let mut seams = HashSet::new();
+    for ((a, b), users) in edge_faces(faces) {
+        if let &[(first, _), (second, _)] = users.as_slice() {
+            let first = root(&mut sheet, first);
+            let second = root(&mut sheet, second);
+            sheet[first.max(second)] = first.min(second);
+        } else if users.len() > 2 {
+            seams.insert(a);
+            seams.insert(b);
+        }
+    }
+
+    let mut owners = HashMap::new();
+    let mut copies = HashMap::new();
+    let mut sources = Vec::new();
+    for (index, face) in faces.iter_mut().enumerate() {
+        let part = root(&mut sheet, index);
+        for vertex in face.vertices.iter_mut() {
+            if !seams.contains(vertex) || *owners.entry(*vertex).or_insert(part) == part {
+                continue;
+            }
+            *vertex = *copies.entry((*vertex, part)).or_insert_with(|| {
+                sources.push(*vertex);
+                vertex_count + sources.len() - 1
+            });
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle};
+
+    #[test]
+    fn degenerate_and_duplicate_faces_are_removed() {
+        let mut node = GeometryNode::from_polygons(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [5.0, 5.0, 5.0],
+            ],
+            &[
+                vec![0, 1, 2, 3],
+                vec![1, 4, 4],
+                vec![2, 3, 0, 1],
+                vec![0, 1, 4],
+                vec![1, 4, 2],
+            ],
+        );
+        node.face_stack.push(Layer::new(
+            convention::LAYER_MATERIAL_ID,
+            1,
+            LayerData::Int32(vec![10, 11, 12, 13, 14]),
+        ));
+        node.corner_stack.push(Layer::new(
+            "index",
+            1,
+            LayerData::Int32((0..node.corner_count() as i32).collect()),
+        ));
+
+        let report = node.repair(&RepairOptions::default()).unwrap();
+        assert_eq!(
+            report,
+            RepairReport {
+                degenerate_faces: 2,
+                duplicate_faces: 1,
+                removed_vertices: 1,
+                ..RepairReport::default()
+            }
+        );
+        node.validate().unwrap();
+        assert_eq!(node.polygons().unwrap(), [vec![0, 1, 2, 3], vec![1, 4, 2]]);
+        assert_eq!(
+            node.face_stack.layers[0].data,
+            LayerData::Int32(vec![10, 14])
+        );
+        assert_eq!(
+            node.corner_stack.get("index").unwrap().data,
+            LayerData::Int32(vec![0, 1, 2, 3, 14, 15, 16])
+        );
+        assert!(node
+            .repair(&RepairOptions::default())
+            .unwrap()
+            .is_unchanged());
+    }
+
+    #[test]
+    fn winding_follows_neighbours_and_faces_outwards() {
+        let mut node = quad_and_triangle();
+        node.edge_stack.push(Layer::new(
+            "edge",
+            1,
+            LayerData::Int32(vec![0, 1, 2, 3, 4, 5, 6]),
+        ));
+        // Flip the triangle [1, 4, 2], whose edges start at corners 4, 5 and 6.
+        node.corner_stack.layers[0].data = LayerData::Int32(vec![0, 1, 2, -4, 2, 4, -2]);
+        node.edge_stack.layers[0].data = LayerData::Int32(vec![0, 1, 2, 3, 5, 4, 6]);
+        let report = node.repair(&RepairOptions::default()).unwrap();
+        assert_eq!(report.flipped_faces, 1);
+        assert_eq!(node.polygons().unwrap(), [vec![0, 1, 2, 3], vec![1, 4, 2]]);
+        // Every edge keeps its value: 1 -> 4 is 4, 4 -> 2 is 5 and 2 -> 1 is 6.
+        assert_eq!(
+            node.edge_stack.layers[0].data,
+            LayerData::Int32(vec![0, 1, 2, 3, 4, 5, 6])
+        );
+
+        let mut inside_out = cube();
+        let polygons: Vec<Vec<u32>> = inside_out
+            .polygons()
+            .unwrap()
+            .into_iter()
+            .map(|polygon| polygon.into_iter().rev().map(|v| v as u32).collect())
+            .collect();
+        inside_out.corner_stack.layers[0].data = LayerData::Int32(encode_reference(&polygons));
+        assert!(inside_out.volume().unwrap().unwrap() < 0.0);
+        let report = inside_out.repair(&RepairOptions::default()).unwrap();
+        assert_eq!(report.flipped_faces, 6);
+        assert!((inside_out.volume().unwrap().unwrap() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn small_holes_are_filled() {
+        let mut node = cube();
+        node.generate_neighbours().unwrap();
+        let faces: Vec<(usize, Vec<usize>)> = node
+            .faces()
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .skip(1)
+            .map(|(face, range)| (face, range.collect()))
+            .collect();
+        node = node.rebuild_faces(&faces).unwrap();
+        assert!(!node.is_closed().unwrap());
+
+        let options = RepairOptions {
+            max_hole_edges: Some(3),
+            ..RepairOptions::default()
+        };
+        assert_eq!(node.repair(&options).unwrap().filled_holes, 0);
+
+        let options = RepairOptions {
+            max_hole_edges: Some(4),
+            ..RepairOptions::default()
+        };
+        let report = node.repair(&options).unwrap();
+        assert_eq!(report.filled_holes, 1);
+        assert_eq!(report.flipped_faces, 0);
+        node.validate().unwrap();
+        assert!(node.is_closed().unwrap());
+        assert!((node.volume().unwrap().unwrap() - 1.0).abs() < 1e-12);
+        let neighbours = node
+            .edge_stack
+            .get(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .unwrap();
+        assert!((0..neighbours.len()).all(|corner| neighbours.get(corner, 0) >= 0.0));
+    }
+
+    #[test]
+    fn non_manifold_edges_are_split() {
+        // Three fins sharing the edge from vertex 0 to vertex 1.
+        let mut node = GeometryNode::from_polygons(
+            &[
+                [0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [-1.0, -1.0, 0.0],
+            ],
+            &[vec![0, 1, 2], vec![1, 0, 3], vec![0, 1, 4]],
+        );
+        node.vertex_stack
+            .push(Layer::new("index", 1, LayerData::Int32((0..5).collect())));
+
+        let options = RepairOptions {
+            split_non_manifold: false,
+            ..RepairOptions::default()
+        };
+        let report = node.clone().repair(&options).unwrap();
+        assert_eq!(report.non_manifold_edges, 1);
+        assert!(report.is_unchanged());
+
+        let report = node.repair(&RepairOptions::default()).unwrap();
+        assert_eq!(report.split_vertices, 4);
+        assert_eq!(report.non_manifold_edges, 0);
+        node.validate().unwrap();
+        assert_eq!(
+            node.polygons().unwrap(),
+            [vec![0, 1, 2], vec![5, 6, 3], vec![7, 8, 4]]
+        );
+        assert_eq!(
+            node.vertex_stack.get("index").unwrap().data,
+            LayerData::Int32(vec![0, 1, 2, 3, 4, 1, 0, 0, 1])
+        );
+        assert!(node
+            .repair(&RepairOptions::default())
+            .unwrap()
+            .is_unchanged());
+    }
+}
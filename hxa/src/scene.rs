@@ -0,0 +1,395 @@
+//! Scene hierarchies built from node references.
+//!
+//! A node lists its children in a `children` node meta, or names its parent in a `parent` node
+//! meta. A file can use either convention or both, as long as they agree. Nodes without a parent
+//! are roots, and the `transform` meta of a node is relative to its parent.
+
+use crate::{convention, Error, File, GeometryNode, Mat4, Meta, MetaValue, Node, Result};
+
+/// The hierarchy of the nodes of a file, with resolved world transforms.
+///
+/// The scene is a snapshot, it does not change when the file does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    roots: Vec<usize>,
+    world_transforms: Vec<Mat4>,
+}
+
+impl Scene {
+    /// Build the hierarchy of a file.
+    ///
+    /// [`NULL_REFERENCE`](convention::NULL_REFERENCE) references are skipped. Fails with
+    /// [`Error::DanglingReference`] if another reference points outside of the node array,
+    /// [`Error::ConflictingParent`] if a node has more than one parent and
+    /// [`Error::HierarchyCycle`] if a node is its own ancestor.
+    pub fn new(file: &File) -> Result<Self> {
+        let count = file.nodes.len();
+        let mut parents: Vec<Option<usize>> = vec![None; count];
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); count];
+        let mut link = |parent: usize, child: usize| {
+            match parents[child] {
+                Some(existing) if existing != parent => {
+                    return Err(Error::ConflictingParent { node: child })
+                }
+                _ => parents[child] = Some(parent),
+            }
+            if !children[parent].contains(&child) {
+                children[parent].push(child);
+            }
+            Ok(())
+        };
+
+        for node in 0..count {
+            for child in references(file, node, convention::CHILDREN)? {
+                link(node, child)?;
+            }
+        }
+        for node in 0..count {
+            match references(file, node, convention::PARENT)?[..] {
+                [] => {}
+                [parent] => link(parent, node)?,
+                _ => return Err(Error::ConflictingParent { node }),
+            }
+        }
+
+        // Walk up from every node, marking the nodes on the current path to find cycles.
+        const UNVISITED: u8 = 0;
+        const ON_PATH: u8 = 1;
+        const DONE: u8 = 2;
+        let mut state = vec![UNVISITED; count];
+        for start in 0..count {
+            let mut path = Vec::new();
+            let mut node = Some(start);
+            while let Some(current) = node {
+                match state[current] {
+                    DONE => break,
+                    ON_PATH => return Err(Error::HierarchyCycle { node: current }),
+                    _ => {}
+                }
+                state[current] = ON_PATH;
+                path.push(current);
+                node = parents[current];
+            }
+            for node in path {
+                state[node] = DONE;
+            }
+        }
+
+        let roots = (0..count).filter(|&node| parents[node].is_none()).collect();
+        let mut scene = Self {
+            parents,
+            children,
+            roots,
+            world_transforms: vec![Mat4::IDENTITY; count],
+        };
+        for node in scene.depth_first() {
+            let local = file.nodes[node].transform().unwrap_or_default();
+            scene.world_transforms[node] = match scene.parents[node] {
+                Some(parent) => scene.world_transforms[parent] * local,
+                None => local,
+            };
+        }
+
+        Ok(scene)
+    }
+
+    /// The number of nodes in the scene.
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    pub fn parent(&self, node: usize) -> Option<usize> {
+        self.parents[node]
+    }
+
+    pub fn children(&self, node: usize) -> &[usize] {
+        &self.children[node]
+    }
+
+    /// The nodes without a parent, in node order.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// The parent, grandparent and further ancestors of a node.
+    pub fn ancestors(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.parents[node], move |&node| self.parents[node])
+    }
+
+    /// The transform from the space of a node to the space of the scene.
+    pub fn world_transform(&self, node: usize) -> Mat4 {
+        self.world_transforms[node]
+    }
+
+    /// All nodes, ordered so every node comes after its parent.
+    pub fn depth_first(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.len());
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            order.push(node);
+            stack.extend(self.children[node].iter().rev());
+        }
+        order
+    }
+}
+
+impl File {
+    /// Build the scene hierarchy of the file. See [`Scene::new`].
+    pub fn scene(&self) -> Result<Scene> {
+        Scene::new(self)
+    }
+
+    /// Move a node under a new parent, or make it a root.
+    ///
+    /// The node is removed from the `children` meta of every other node, and its `parent` meta
+    /// and the `children` meta of the new parent are updated. Fails with
+    /// [`Error::HierarchyCycle`] if the new parent is the node or one of its descendants.
+    pub fn set_parent(&mut self, node: usize, parent: Option<usize>) -> Result<()> {
+        self.node(node)?;
+        if let Some(parent) = parent {
+            self.node(parent)?;
+            // Walk up from the new parent, giving up after as many steps as there are nodes in
+            // case the hierarchy already has a cycle elsewhere.
+            let mut ancestor = Some(parent);
+            for _ in 0..self.nodes.len() {
+                match ancestor {
+                    Some(current) if current == node => return Err(Error::HierarchyCycle { node }),
+                    Some(current) => ancestor = stored_parent(self, current),
+                    None => break,
+                }
+            }
+        }
+
+        let reference = node as u32;
+        for entry in &mut self.nodes {
+            for meta in &mut entry.meta {
+                if let (convention::CHILDREN, MetaValue::Node(children)) =
+                    (meta.name.as_str(), &mut meta.value)
+                {
+                    children.retain(|&child| child != reference);
+                }
+            }
+        }
+        let entry = &mut self.nodes[node];
+        entry.meta.retain(|meta| meta.name != convention::PARENT);
+        if let Some(parent) = parent {
+            entry.set_meta(Meta::new(
+                convention::PARENT,
+                MetaValue::Node(vec![parent as u32]),
+            ));
+            let parent = &mut self.nodes[parent];
+            match parent
+                .meta
+                .iter_mut()
+                .find(|meta| meta.name == convention::CHILDREN)
+            {
+                Some(Meta {
+                    value: MetaValue::Node(children),
+                    ..
+                }) => children.push(reference),
+                _ => parent.set_meta(Meta::new(
+                    convention::CHILDREN,
+                    MetaValue::Node(vec![reference]),
+                )),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A new node holding geometry made from part of a node, like a split part or a level of
+    /// detail.
+    ///
+    /// The meta of the node is copied without its `children` and `lod` references, and the new
+    /// node gets the same parent, so it is placed like the node. The `bounds`, `bounding_sphere`,
+    /// `surface_area` and `volume` meta only describe the node, so they are recomputed for the
+    /// new node if the node has any of them.
+    pub(crate) fn derived_node(&self, node: usize, geometry: GeometryNode) -> Result<Node> {
+        const HIERARCHY: [&str; 3] = [convention::CHILDREN, convention::PARENT, convention::LOD];
+        const DERIVED: [&str; 4] = [
+            convention::BOUNDS,
+            convention::BOUNDING_SPHERE,
+            convention::SURFACE_AREA,
+            convention::VOLUME,
+        ];
+        let source = self.node(node)?;
+        let mut derived = Node::from(geometry);
+        derived.meta = source
+            .meta
+            .iter()
+            .filter(|meta| !HIERARCHY.contains(&meta.name.as_str()))
+            .filter(|meta| !DERIVED.contains(&meta.name.as_str()))
+            .cloned()
+            .collect();
+        if let Some(parent) = stored_parent(self, node) {
+            derived.set_meta(Meta::new(
+                convention::PARENT,
+                MetaValue::Node(vec![parent as u32]),
+            ));
+        }
+        if source
+            .meta
+            .iter()
+            .any(|meta| DERIVED.contains(&meta.name.as_str()))
+        {
+            derived.update_bounds()?;
+        }
+
+        Ok(derived)
+    }
+}
+
+/// The parent of a node named by its `parent` meta, or else by the `children` meta of another
+/// node. References outside of the node array are ignored.
+pub(crate) fn stored_parent(file: &File, node: usize) -> Option<usize> {
+    let named = file.nodes[node]
+        .find_meta(convention::PARENT)
+        .and_then(Meta::as_node)
+        .and_then(|references| references.first())
+        .map(|&parent| parent as usize)
+        .filter(|&parent| parent < file.nodes.len());
+    named.or_else(|| {
+        file.nodes.iter().position(|entry| {
+            entry
+                .find_meta(convention::CHILDREN)
+                .and_then(Meta::as_node)
+                .is_some_and(|children| children.contains(&(node as u32)))
+        })
+    })
+}
+
+/// The nodes referenced by a node meta entry of a node, checked against the node array.
+fn references(file: &File, node: usize, name: &str) -> Result<Vec<usize>> {
+    let Some(references) = file.nodes[node].find_meta(name).and_then(Meta::as_node) else {
+        return Ok(Vec::new());
+    };
+    references
+        .iter()
+        .filter(|&&reference| reference != convention::NULL_REFERENCE)
+        .map(|&reference| match reference as usize {
+            index if index < file.nodes.len() => Ok(index),
+            _ => Err(Error::DanglingReference {
+                node,
+                meta: name.to_string(),
+                reference,
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Node, NodeContent};
+
+    fn file(count: usize) -> File {
+        let mut file = File::new();
+        file.nodes = vec![Node::new(NodeContent::MetaOnly); count];
+        file
+    }
+
+    fn set_node_meta(file: &mut File, node: usize, name: &str, references: &[u32]) {
+        file.nodes[node].set_meta(Meta::new(name, MetaValue::Node(references.to_vec())));
+    }
+
+    #[test]
+    fn hierarchy_and_world_transforms() {
+        // 0 -> 2 -> 1 with the children convention, 3 names 2 as its parent and 4 is a root.
+        let mut file = file(5);
+        set_node_meta(&mut file, 0, convention::CHILDREN, &[2]);
+        set_node_meta(&mut file, 2, convention::CHILDREN, &[1]);
+        set_node_meta(&mut file, 3, convention::PARENT, &[2]);
+        file.nodes[0].set_transform(&Mat4::from_translation([1.0, 0.0, 0.0]));
+        file.nodes[2].set_transform(&Mat4::from_scale([2.0, 2.0, 2.0]));
+        file.nodes[1].set_transform(&Mat4::from_translation([0.0, 1.0, 0.0]));
+        let scene = file.scene().unwrap();
+
+        assert_eq!(scene.roots(), &[0, 4]);
+        assert_eq!(scene.children(2), &[1, 3]);
+        assert_eq!(scene.parent(3), Some(2));
+        assert_eq!(scene.ancestors(1).collect::<Vec<_>>(), vec![2, 0]);
+        assert_eq!(scene.depth_first(), vec![0, 2, 1, 3, 4]);
+        assert_eq!(
+            scene.world_transform(1).transform_point([0.0; 3]),
+            [1.0, 2.0, 0.0]
+        );
+        assert_eq!(
+            scene.world_transform(3).transform_point([1.0, 0.0, 0.0]),
+            [3.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn invalid_hierarchies() {
+        let mut cycle = file(3);
+        set_node_meta(&mut cycle, 0, convention::CHILDREN, &[1]);
+        set_node_meta(&mut cycle, 1, convention::CHILDREN, &[2]);
+        set_node_meta(&mut cycle, 2, convention::CHILDREN, &[0]);
+        assert!(matches!(cycle.scene(), Err(Error::HierarchyCycle { .. })));
+
+        let mut dangling = file(2);
+        set_node_meta(&mut dangling, 1, convention::PARENT, &[7]);
+        assert_eq!(
+            dangling.scene(),
+            Err(Error::DanglingReference {
+                node: 1,
+                meta: convention::PARENT.to_string(),
+                reference: 7
+            })
+        );
+
+        let mut conflict = file(3);
+        set_node_meta(&mut conflict, 0, convention::CHILDREN, &[2]);
+        set_node_meta(&mut conflict, 2, convention::PARENT, &[1]);
+        assert_eq!(conflict.scene(), Err(Error::ConflictingParent { node: 2 }));
+    }
+
+    #[test]
+    fn set_parent_moves_nodes() {
+        let mut file = file(3);
+        file.set_parent(2, Some(0)).unwrap();
+        file.set_parent(1, Some(0)).unwrap();
+        file.set_parent(2, Some(1)).unwrap();
+        let scene = file.scene().unwrap();
+        assert_eq!(scene.children(0), &[1]);
+        assert_eq!(scene.children(1), &[2]);
+
+        file.set_parent(1, None).unwrap();
+        let scene = file.scene().unwrap();
+        assert_eq!(scene.roots(), &[0, 1]);
+        assert!(file.nodes[1].find_meta(convention::PARENT).is_none());
+        assert_eq!(
+            file.set_parent(0, Some(3)),
+            Err(Error::InvalidNode { node: 3 })
+        );
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut file = file(3);
+        file.set_parent(1, Some(0)).unwrap();
+        file.set_parent(2, Some(1)).unwrap();
+        let before = file.clone();
+
+        assert_eq!(
+            file.set_parent(0, Some(0)),
+            Err(Error::HierarchyCycle { node: 0 })
+        );
+        assert_eq!(
+            file.set_parent(0, Some(1)),
+            Err(Error::HierarchyCycle { node: 0 })
+        );
+        assert_eq!(
+            file.set_parent(0, Some(2)),
+            Err(Error::HierarchyCycle { node: 0 })
+        );
+        assert_eq!(file, before);
+        file.scene().unwrap();
+    }
+}
@@ -0,0 +1,479 @@
+//! Selections and weight maps stored in `select` layers.
+//!
+//! A selection holds a weight from 0 to 1 for every vertex, corner, edge or face, in the `select`
+//! layer of the matching stack. Elements with a weight above 0 are selected, and weights below
+//! 1 make soft selections or weight maps. Edges are stored per corner like every edge layer, and
+//! an edge counts as selected if either of its half-edges is.
+
+use crate::{
+    convention, Error, File, GeometryNode, HalfEdgeMesh, Layer, LayerData, LayerStack, Result,
+};
+
+/// The kind of element a selection is over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionDomain {
+    Vertex,
+    Corner,
+    /// The edge starting at each corner, stored in the edge stack.
+    Edge,
+    Face,
+}
+
+/// A weight for every element of one kind of a geometry node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub domain: SelectionDomain,
+    pub weights: Vec<f64>,
+}
+
+impl Selection {
+    /// A selection of `len` elements with nothing selected.
+    pub fn new(domain: SelectionDomain, len: usize) -> Self {
+        Self {
+            domain,
+            weights: vec![0.0; len],
+        }
+    }
+
+    /// A selection of `len` elements with the elements at `indices` fully selected.
+    pub fn from_indices(domain: SelectionDomain, len: usize, indices: &[usize]) -> Self {
+        let mut selection = Self::new(domain, len);
+        for &index in indices {
+            selection.weights[index] = 1.0;
+        }
+        selection
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn is_selected(&self, element: usize) -> bool {
+        self.weights[element] > 0.0
+    }
+
+    /// The selected elements, in increasing order.
+    pub fn indices(&self) -> Vec<usize> {
+        (0..self.len()).filter(|&e| self.is_selected(e)).collect()
+    }
+
+    /// The elements selected in either selection, keeping the larger weight.
+    pub fn union(&self, other: &Selection) -> Result<Selection> {
+        self.combine(other, f64::max)
+    }
+
+    /// The elements selected in both selections, keeping the smaller weight.
+    pub fn intersection(&self, other: &Selection) -> Result<Selection> {
+        self.combine(other, f64::min)
+    }
+
+    /// The elements selected in this selection but not in the other, scaling the weights down
+    /// by the weights of the other.
+    pub fn difference(&self, other: &Selection) -> Result<Selection> {
+        self.combine(other, |a, b| a * (1.0 - b.clamp(0.0, 1.0)))
+    }
+
+    /// Swap selected and unselected elements, turning every weight `w` into `1 - w`.
+    pub fn invert(&self) -> Selection {
+        Selection {
+            domain: self.domain,
+            weights: self.weights.iter().map(|w| 1.0 - w).collect(),
+        }
+    }
+
+    fn combine(&self, other: &Selection, f: impl Fn(f64, f64) -> f64) -> Result<Selection> {
+        if other.domain != self.domain {
+            return Err(Error::SelectionDomain {
+                expected: self.domain,
+                actual: other.domain,
+            });
+        }
+        if other.len() != self.len() {
+            return Err(Error::LayerLength {
+                name: convention::LAYER_SELECTION.to_string(),
+                expected: self.len(),
+                actual: other.len(),
+            });
+        }
+
+        Ok(Selection {
+            domain: self.domain,
+            weights: self
+                .weights
+                .iter()
+                .zip(&other.weights)
+                .map(|(&a, &b)| f(a, b))
+                .collect(),
+        })
+    }
+}
+
+impl GeometryNode {
+    /// Read the `select` layer of the stack of a domain.
+    pub fn selection(&self, domain: SelectionDomain) -> Result<Selection> {
+        let layer = self
+            .selection_stack(domain)
+            .get(convention::LAYER_SELECTION)?;
+        layer.expect_components(1)?;
+
+        Ok(Selection {
+            domain,
+            weights: (0..layer.len()).map(|e| layer.get(e, 0)).collect(),
+        })
+    }
+
+    /// Store a selection as a float `select` layer in the stack of its domain, replacing the
+    /// layer already there.
+    pub fn set_selection(&mut self, selection: &Selection) -> Result<()> {
+        let expected = self.domain_len(selection.domain);
+        if selection.len() != expected {
+            return Err(Error::LayerLength {
+                name: convention::LAYER_SELECTION.to_string(),
+                expected,
+                actual: selection.len(),
+            });
+        }
+        let data = selection.weights.iter().map(|&w| w as f32).collect();
+        let layer = Layer::new(convention::LAYER_SELECTION, 1, LayerData::Float(data));
+        match selection.domain {
+            SelectionDomain::Vertex => self.vertex_stack.insert(layer),
+            SelectionDomain::Corner => self.corner_stack.insert(layer),
+            SelectionDomain::Edge => self.edge_stack.insert(layer),
+            SelectionDomain::Face => self.face_stack.insert(layer),
+        }
+
+        Ok(())
+    }
+
+    /// Convert a selection to another domain.
+    ///
+    /// Elements inside selected elements are selected, like the vertices and edges of a selected
+    /// face, taking the largest weight. Elements made of selected elements are selected, like a
+    /// face whose vertices are all selected, taking the smallest weight. Corners belong to their
+    /// vertex, their face and the two edges of their face meeting at them.
+    pub fn convert_selection(
+        &self,
+        selection: &Selection,
+        domain: SelectionDomain,
+    ) -> Result<Selection> {
+        use SelectionDomain::*;
+
+        let mesh = self.half_edges()?;
+        let weights = self.check_selection(selection)?;
+        let corner_count = mesh.half_edge_count();
+        let edges = |w: &dyn Fn(usize) -> f64| -> Vec<f64> {
+            // An edge takes the weight of its more selected half-edge.
+            let halves: Vec<f64> = (0..corner_count).map(w).collect();
+            (0..corner_count)
+                .map(|h| match mesh.opposite(h) {
+                    Some(opposite) => halves[h].max(halves[opposite]),
+                    None => halves[h],
+                })
+                .collect()
+        };
+        let faces = |w: &dyn Fn(usize) -> f64| -> Vec<f64> {
+            (0..mesh.face_count())
+                .map(|face| {
+                    mesh.face_half_edges(face)
+                        .map(w)
+                        .fold(f64::INFINITY, f64::min)
+                })
+                .collect()
+        };
+
+        let weights = match (selection.domain, domain) {
+            (from, to) if from == to => weights.to_vec(),
+            (_, Vertex) => {
+                let mut vertices = vec![0.0f64; mesh.vertex_count()];
+                let elements = self.domain_vertices(&mesh, selection.domain);
+                for (element, vertex_list) in elements.into_iter().enumerate() {
+                    for vertex in vertex_list {
+                        vertices[vertex] = vertices[vertex].max(weights[element]);
+                    }
+                }
+                vertices
+            }
+            (Vertex, Corner) => (0..corner_count).map(|c| weights[mesh.origin(c)]).collect(),
+            (Vertex, Edge) => edges(&|h| weights[mesh.origin(h)].min(weights[mesh.target(h)])),
+            (Vertex, Face) => faces(&|c| weights[mesh.origin(c)]),
+            (Face, Corner) => (0..corner_count).map(|c| weights[mesh.face(c)]).collect(),
+            (Face, Edge) => edges(&|h| weights[mesh.face(h)]),
+            (Corner, Edge) => edges(&|h| weights[h].min(weights[mesh.next(h)])),
+            (Corner, Face) => faces(&|c| weights[c]),
+            (Edge, Corner) => {
+                let edges = edges(&|h| weights[h]);
+                (0..corner_count)
+                    .map(|c| edges[c].max(edges[mesh.prev(c)]))
+                    .collect()
+            }
+            (Edge, Face) => {
+                let edges = edges(&|h| weights[h]);
+                faces(&|h| edges[h])
+            }
+            (Corner | Edge | Face, _) => unreachable!("all other pairs are handled above"),
+        };
+
+        Ok(Selection { domain, weights })
+    }
+
+    /// Add the elements next to the selection, sharing a vertex with a selected element, or
+    /// an edge for vertex selections. Added elements take the largest neighbouring weight.
+    pub fn grow_selection(&self, selection: &Selection) -> Result<Selection> {
+        self.spread_selection(selection, f64::max)
+    }
+
+    /// Remove the elements on the border of the selection, the ones next to an unselected
+    /// element as in [`grow_selection`](Self::grow_selection). Remaining elements take the
+    /// smallest neighbouring weight.
+    pub fn shrink_selection(&self, selection: &Selection) -> Result<Selection> {
+        self.spread_selection(selection, f64::min)
+    }
+
+    /// Build a new node from the faces of a selection, converted to faces first.
+    ///
+    /// Layers are copied for the kept elements, and vertices no kept face uses are removed.
+    pub fn extract_selection(&self, selection: &Selection) -> Result<GeometryNode> {
+        let faces = self.convert_selection(selection, SelectionDomain::Face)?;
+        let kept: Vec<(usize, Vec<usize>)> = self
+            .faces()?
+            .into_iter()
+            .enumerate()
+            .filter(|&(face, _)| faces.is_selected(face))
+            .map(|(face, range)| (face, range.collect()))
+            .collect();
+        let mut node = self.rebuild_faces(&kept)?;
+        node.remove_unused_vertices()?;
+
+        Ok(node)
+    }
+
+    fn spread_selection(
+        &self,
+        selection: &Selection,
+        f: impl Fn(f64, f64) -> f64 + Copy,
+    ) -> Result<Selection> {
+        let mesh = self.half_edges()?;
+        let weights = self.check_selection(selection)?;
+
+        let mut vertices: Vec<Option<f64>> = vec![None; mesh.vertex_count()];
+        if selection.domain == SelectionDomain::Vertex {
+            for (vertex, spread) in vertices.iter_mut().enumerate() {
+                *spread = Some(
+                    mesh.one_ring(vertex)
+                        .into_iter()
+                        .fold(weights[vertex], |w, neighbour| f(w, weights[neighbour])),
+                );
+            }
+            return Ok(Selection {
+                domain: selection.domain,
+                weights: vertices.into_iter().flatten().collect(),
+            });
+        }
+
+        let elements = self.domain_vertices(&mesh, selection.domain);
+        for (element, vertex_list) in elements.iter().enumerate() {
+            for &vertex in vertex_list {
+                let weight = weights[element];
+                vertices[vertex] = Some(vertices[vertex].map_or(weight, |w| f(w, weight)));
+            }
+        }
+        let mut spread = weights.to_vec();
+        for (element, vertex_list) in elements.into_iter().enumerate() {
+            spread[element] = vertex_list
+                .into_iter()
+                .filter_map(|vertex| vertices[vertex])
+                .fold(spread[element], f);
+        }
+
+        Ok(Selection {
+            domain: selection.domain,
+            weights: spread,
+        })
+    }
+
+    /// The vertices of every element of a domain.
+    fn domain_vertices(&self, mesh: &HalfEdgeMesh, domain: SelectionDomain) -> Vec<Vec<usize>> {
+        let half_edges = 0..mesh.half_edge_count();
+        match domain {
+            SelectionDomain::Vertex => (0..mesh.vertex_count()).map(|v| vec![v]).collect(),
+            SelectionDomain::Corner => half_edges.map(|c| vec![mesh.origin(c)]).collect(),
+            SelectionDomain::Edge => half_edges
+                .map(|h| vec![mesh.origin(h), mesh.target(h)])
+                .collect(),
+            SelectionDomain::Face => (0..mesh.face_count())
+                .map(|face| mesh.face_half_edges(face).map(|c| mesh.origin(c)).collect())
+                .collect(),
+        }
+    }
+
+    /// The weights of a selection, checking it has one for every element of its domain.
+    fn check_selection<'a>(&self, selection: &'a Selection) -> Result<&'a [f64]> {
+        let expected = self.domain_len(selection.domain);
+        if selection.len() != expected {
+            return Err(Error::LayerLength {
+                name: convention::LAYER_SELECTION.to_string(),
+                expected,
+                actual: selection.len(),
+            });
+        }
+
+        Ok(&selection.weights)
+    }
+
+    fn selection_stack(&self, domain: SelectionDomain) -> &LayerStack {
+        match domain {
+            SelectionDomain::Vertex => &self.vertex_stack,
+            SelectionDomain::Corner => &self.corner_stack,
+            SelectionDomain::Edge => &self.edge_stack,
+            SelectionDomain::Face => &self.face_stack,
+        }
+    }
+
+    fn domain_len(&self, domain: SelectionDomain) -> usize {
+        match domain {
+            SelectionDomain::Vertex => self.vertex_count(),
+            SelectionDomain::Corner | SelectionDomain::Edge => self.corner_count(),
+            SelectionDomain::Face => self.face_count(),
+        }
+    }
+}
+
+impl File {
+    /// Extract the selection of a geometry node into a new node, returning its index.
+    ///
+    /// The new node copies the meta of the node, with a `_selection` suffix added to its name.
+    /// The `children` and `lod` references are not copied, the new node gets the parent of the
+    /// node and the bounds and surface meta are recomputed for it. The node itself is left
+    /// unchanged.
+    pub fn extract_selection(&mut self, node: usize, selection: &Selection) -> Result<usize> {
+        let geometry = self
+            .unquantized_geometry(node)?
+            .extract_selection(selection)?;
+        let mut extracted = self.derived_node(node, geometry)?;
+        if let Some(name) = self.nodes[node].name() {
+            extracted.set_name(format!("{}_selection", name));
+        }
+        self.nodes.push(extracted);
+
+        Ok(self.nodes.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{quad_and_triangle, triangle_grid};
+    use crate::{Node, NodeContent};
+    use SelectionDomain::*;
+
+    #[test]
+    fn set_operations() {
+        let a = Selection::from_indices(Vertex, 4, &[0, 1]);
+        let mut b = Selection::from_indices(Vertex, 4, &[1, 2]);
+        b.weights[2] = 0.5;
+        assert_eq!(a.union(&b).unwrap().weights, [1.0, 1.0, 0.5, 0.0]);
+        assert_eq!(a.intersection(&b).unwrap().indices(), [1]);
+        assert_eq!(a.difference(&b).unwrap().indices(), [0]);
+        assert_eq!(a.invert().indices(), [2, 3]);
+        assert_eq!(
+            a.union(&Selection::new(Face, 4)),
+            Err(Error::SelectionDomain {
+                expected: Vertex,
+                actual: Face
+            })
+        );
+        assert!(a.union(&Selection::new(Vertex, 3)).is_err());
+    }
+
+    #[test]
+    fn selections_round_trip_through_layers() {
+        let mut node = quad_and_triangle();
+        let mut selection = Selection::from_indices(Edge, 7, &[1, 5]);
+        selection.weights[5] = 0.25;
+        node.set_selection(&selection).unwrap();
+        assert_eq!(node.selection(Edge).unwrap(), selection);
+        assert!(node.selection(Face).is_err());
+        assert!(node
+            .set_selection(&Selection::from_indices(Face, 3, &[0]))
+            .is_err());
+    }
+
+    #[test]
+    fn conversions_between_domains() {
+        let node = quad_and_triangle();
+        let triangle = Selection::from_indices(Face, 2, &[1]);
+        let vertices = node.convert_selection(&triangle, Vertex).unwrap();
+        assert_eq!(vertices.indices(), [1, 2, 4]);
+        // Corner 1 runs along the edge shared with the triangle.
+        let edges = node.convert_selection(&triangle, Edge).unwrap();
+        assert_eq!(edges.indices(), [1, 4, 5, 6]);
+        assert_eq!(
+            node.convert_selection(&triangle, Corner).unwrap().indices(),
+            [4, 5, 6]
+        );
+
+        // The vertices of the triangle only make the triangle, and the edges between them.
+        assert_eq!(
+            node.convert_selection(&vertices, Face).unwrap().indices(),
+            [1]
+        );
+        assert_eq!(node.convert_selection(&vertices, Edge).unwrap(), edges);
+        assert_eq!(node.convert_selection(&edges, Face).unwrap(), triangle);
+        let corners = node.convert_selection(&edges, Corner).unwrap();
+        assert_eq!(corners.indices(), [1, 2, 4, 5, 6]);
+        assert_eq!(node.convert_selection(&corners, Face).unwrap(), triangle);
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let node = triangle_grid(4);
+        // The center vertex of the 5 by 5 grid.
+        let center = Selection::from_indices(Vertex, 25, &[12]);
+        let grown = node.grow_selection(&center).unwrap();
+        assert_eq!(grown.indices(), [6, 7, 11, 12, 13, 17, 18]);
+        assert_eq!(node.shrink_selection(&grown).unwrap(), center);
+
+        let faces = node.convert_selection(&grown, Face).unwrap();
+        assert_eq!(faces.indices().len(), 6);
+        let more = node.grow_selection(&faces).unwrap();
+        assert!(more.indices().len() > 6);
+        assert_eq!(
+            node.shrink_selection(&faces).unwrap().indices(),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn extract_into_new_node() {
+        let mut file = File::new();
+        let mut node = Node::from(quad_and_triangle());
+        node.set_name("mesh");
+        node.update_bounds().unwrap();
+        file.nodes.push(node);
+        file.nodes.push(Node::new(NodeContent::MetaOnly));
+        file.set_parent(1, Some(0)).unwrap();
+        let selection = Selection::from_indices(Vertex, 5, &[1, 2, 4]);
+        let index = file.extract_selection(0, &selection).unwrap();
+
+        let scene = file.scene().unwrap();
+        assert_eq!(scene.children(0), [1]);
+        assert_eq!(scene.parent(index), None);
+        let area = file.nodes[index]
+            .find_meta(convention::SURFACE_AREA)
+            .unwrap();
+        assert_eq!(
+            area.as_double(),
+            Some(&[file.geometry(index).unwrap().surface_area().unwrap()][..])
+        );
+
+        assert_eq!(file.nodes[index].name(), Some("mesh_selection"));
+        let extracted = file.geometry(index).unwrap();
+        extracted.validate().unwrap();
+        assert_eq!(extracted.vertex_count(), 3);
+        assert_eq!(extracted.polygons().unwrap(), [vec![0, 2, 1]]);
+    }
+}
@@ -0,0 +1,208 @@
+//! Animated vertex positions stored as a sequence of frames.
+//!
+//! Every frame is a 3 component vertex layer holding the position of every vertex. The first
+//! frame is named `sequence`, followed by `sequence1`, `sequence2` and so on.
+
+use crate::math::{add, scale, sub};
+use crate::{convention, Aabb, Error, GeometryNode, Layer, LayerData, Result};
+
+/// The frames of an animated geometry node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexSequence {
+    vertex_count: usize,
+    frames: Vec<Vec<[f64; 3]>>,
+}
+
+impl VertexSequence {
+    /// An empty sequence for a node with the given number of vertices.
+    pub fn new(vertex_count: usize) -> Self {
+        Self {
+            vertex_count,
+            frames: Vec::new(),
+        }
+    }
+
+    /// The name of the vertex layer holding a frame.
+    pub fn layer_name(frame: usize) -> String {
+        match frame {
+            0 => convention::LAYER_SEQUENCE0.to_string(),
+            frame => format!("{}{}", convention::LAYER_SEQUENCE0, frame),
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    /// The number of frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frame(&self, frame: usize) -> &[[f64; 3]] {
+        &self.frames[frame]
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &[[f64; 3]]> + '_ {
+        self.frames.iter().map(Vec::as_slice)
+    }
+
+    /// Add a frame at the end of the sequence, such as the next step of a simulation cache.
+    pub fn push_frame(&mut self, positions: Vec<[f64; 3]>) -> Result<()> {
+        if positions.len() != self.vertex_count {
+            return Err(Error::LayerLength {
+                name: Self::layer_name(self.frames.len()),
+                expected: self.vertex_count,
+                actual: positions.len(),
+            });
+        }
+        self.frames.push(positions);
+
+        Ok(())
+    }
+
+    /// The positions at a fractional frame, linearly interpolated between the surrounding
+    /// frames. Times outside of the sequence are clamped to the first or last frame.
+    ///
+    /// Returns `None` if the sequence is empty or the time is not a number.
+    pub fn sample(&self, time: f64) -> Option<Vec<[f64; 3]>> {
+        let last = self.frames.len().checked_sub(1)?;
+        if time.is_nan() {
+            return None;
+        }
+        let time = time.clamp(0.0, last as f64);
+        let frame = (time.floor() as usize).min(last);
+        let t = time - frame as f64;
+        if t == 0.0 {
+            return Some(self.frames[frame].clone());
+        }
+        let next = &self.frames[frame + 1];
+
+        Some(
+            self.frames[frame]
+                .iter()
+                .zip(next)
+                .map(|(&a, &b)| add(a, scale(sub(b, a), t)))
+                .collect(),
+        )
+    }
+
+    /// The bounding box of a frame, or `None` if there are no vertices.
+    pub fn frame_bounds(&self, frame: usize) -> Option<Aabb> {
+        Aabb::from_points(self.frames[frame].iter().copied())
+    }
+
+    /// The bounding box of all frames together.
+    pub fn bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(self.frames.iter().flatten().copied())
+    }
+}
+
+impl GeometryNode {
+    /// Read the frames stored in the vertex stack, from `sequence` up to the first missing layer.
+    pub fn vertex_sequence(&self) -> Result<VertexSequence> {
+        let mut sequence = VertexSequence::new(self.vertex_count());
+        while let Some(layer) = self
+            .vertex_stack
+            .find(&VertexSequence::layer_name(sequence.len()))
+        {
+            sequence.push_frame(layer.to_vec3()?)?;
+        }
+
+        Ok(sequence)
+    }
+
+    /// Write the frames into the vertex stack, replacing any frames already stored.
+    ///
+    /// Frames are stored with the same type as the vertex positions.
+    pub fn set_vertex_sequence(&mut self, sequence: &VertexSequence) -> Result<()> {
+        if sequence.vertex_count() != self.vertex_count() {
+            return Err(Error::LayerLength {
+                name: convention::LAYER_SEQUENCE0.to_string(),
+                expected: self.vertex_count(),
+                actual: sequence.vertex_count(),
+            });
+        }
+        let double = matches!(self.vertex_layer()?.data, LayerData::Double(_));
+        let mut frame = 0;
+        while self
+            .vertex_stack
+            .remove(&VertexSequence::layer_name(frame))
+            .is_some()
+        {
+            frame += 1;
+        }
+        for (frame, positions) in sequence.frames().enumerate() {
+            let values = positions.iter().flatten().copied();
+            let data = if double {
+                LayerData::Double(values.collect())
+            } else {
+                LayerData::Float(values.map(|v| v as f32).collect())
+            };
+            self.vertex_stack
+                .push(Layer::new(VertexSequence::layer_name(frame), 3, data));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+
+    #[test]
+    fn frames_round_trip() {
+        let mut node = quad_and_triangle();
+        let base = node.positions().unwrap();
+        let mut sequence = VertexSequence::new(node.vertex_count());
+        for frame in 0..3 {
+            let positions = base
+                .iter()
+                .map(|&[x, y, z]| [x, y, z + frame as f64])
+                .collect();
+            sequence.push_frame(positions).unwrap();
+        }
+        assert!(sequence.push_frame(base[1..].to_vec()).is_err());
+        node.set_vertex_sequence(&sequence).unwrap();
+        assert!(node.vertex_stack.find("sequence2").is_some());
+        assert_eq!(node.vertex_sequence().unwrap(), sequence);
+
+        // Storing fewer frames drops the old ones.
+        let mut shorter = VertexSequence::new(node.vertex_count());
+        shorter.push_frame(base.clone()).unwrap();
+        node.set_vertex_sequence(&shorter).unwrap();
+        assert_eq!(node.vertex_sequence().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sample_and_bounds() {
+        let mut sequence = VertexSequence::new(2);
+        assert_eq!(sequence.sample(0.0), None);
+        sequence
+            .push_frame(vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]])
+            .unwrap();
+        assert_eq!(sequence.sample(0.5).unwrap(), sequence.frame(0));
+        assert_eq!(sequence.sample(f64::NAN), None);
+        sequence
+            .push_frame(vec![[0.0, 2.0, 0.0], [1.0, 2.0, 4.0]])
+            .unwrap();
+
+        assert_eq!(
+            sequence.sample(0.25).unwrap(),
+            [[0.0, 0.5, 0.0], [1.0, 0.5, 1.0]]
+        );
+        assert_eq!(sequence.sample(7.0).unwrap(), sequence.frame(1));
+        assert_eq!(sequence.sample(-1.0).unwrap(), sequence.frame(0));
+        assert_eq!(sequence.sample(f64::INFINITY).unwrap(), sequence.frame(1));
+        assert_eq!(sequence.sample(f64::NAN), None);
+        assert_eq!(sequence.frame_bounds(0).unwrap().max, [1.0, 0.0, 0.0]);
+        let bounds = sequence.bounds().unwrap();
+        assert_eq!((bounds.min, bounds.max), ([0.0; 3], [1.0, 2.0, 4.0]));
+    }
+}
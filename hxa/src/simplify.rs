@@ -0,0 +1,791 @@
+//! Mesh simplification and level of detail generation.
+//!
+//! Geometry is simplified with quadric error metric edge collapses. Collapses move a vertex onto
+//! one of its neighbours, so every remaining vertex keeps its own position and vertex layers,
+//! like skin weights, and every remaining corner keeps values of an existing corner.
+//!
+//! Feature edges are preserved. These are open borders, seams where corner layers like `uv`
+//! differ between the two faces of an edge, borders between faces with different face layer
+//! values like `material`, and edges with a `creases` value above zero. Vertices on exactly two
+//! feature edges can only slide along them, and vertices where features meet or end are locked.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::math::{cross, dot, length, normalize, sub, triangle_normal, Vec3};
+use crate::{convention, File, GeometryNode, Meta, MetaValue, Result};
+
+/// Limits of a simplification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimplifyOptions {
+    /// Stop once the node has at most this many triangles.
+    pub target_face_count: usize,
+    /// Stop before a collapse would have a larger error.
+    ///
+    /// The error of a collapse is the square root of a weighted sum of squared distances: from
+    /// the kept vertex to the planes of the original faces around both vertices, with weight 1,
+    /// and to the planes along their feature edges, with weight `feature_weight`. It is not a
+    /// distance between the surfaces, but it is at least the distance from the kept vertex to
+    /// any of those face planes.
+    pub max_error: f64,
+    /// Weight of the planes keeping feature edges in place, relative to the face planes.
+    pub feature_weight: f64,
+}
+
+impl SimplifyOptions {
+    /// Simplify down to `target_face_count` triangles, regardless of the error.
+    pub fn new(target_face_count: usize) -> Self {
+        Self {
+            target_face_count,
+            max_error: f64::INFINITY,
+            feature_weight: 10.0,
+        }
+    }
+}
+
+impl GeometryNode {
+    /// Reduce the number of faces with quadric error metric edge collapses.
+    ///
+    /// The node is triangulated first. Returns the largest error of the performed collapses, as
+    /// described for [`SimplifyOptions::max_error`].
+    pub fn simplify(&mut self, options: &SimplifyOptions) -> Result<f64> {
+        let mut node = self.clone();
+        node.triangulate()?;
+        let mut simplifier = Simplifier::new(&node, options.feature_weight)?;
+        let error = simplifier.run(options);
+        *self = simplifier.build(&node)?;
+
+        Ok(error)
+    }
+}
+
+impl File {
+    /// Append a chain of levels of detail of a geometry node, each simplified from the previous.
+    ///
+    /// Every level copies the meta of the node, gets a name with a `_lod1`, `_lod2`, ... suffix
+    /// if the node has one, and a `lod_error` double meta with its error. The `children` and
+    /// `lod` references are not copied, every level gets the parent of the node and the bounds
+    /// and surface meta are recomputed for it. The node gets a `lod` node reference meta listing
+    /// the levels. Returns the indices of the new nodes.
+    pub fn generate_lods(&mut self, node: usize, levels: &[SimplifyOptions]) -> Result<Vec<usize>> {
+        let mut geometry = self.unquantized_geometry(node)?.clone();
+        let name = self.nodes[node].name().map(str::to_string);
+
+        let mut error: f64 = 0.0;
+        let mut lods = Vec::with_capacity(levels.len());
+        for (level, options) in levels.iter().enumerate() {
+            error = error.max(geometry.simplify(options)?);
+            let mut lod = self.derived_node(node, geometry.clone())?;
+            if let Some(name) = &name {
+                lod.set_name(format!("{}_lod{}", name, level + 1));
+            }
+            lod.set_meta(Meta::new(
+                convention::LOD_ERROR,
+                MetaValue::Double(vec![error]),
+            ));
+            lods.push(self.nodes.len());
+            self.nodes.push(lod);
+        }
+        self.nodes[node].set_meta(Meta::new(
+            convention::LOD,
+            MetaValue::Node(lods.iter().map(|&lod| lod as u32).collect()),
+        ));
+
+        Ok(lods)
+    }
+}
+
+/// A symmetric 4x4 error quadric, stored as the upper triangle of the 3x3 matrix, the vector and
+/// the constant.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: Vec3, point: Vec3, weight: f64) -> Self {
+        let [a, b, c] = normal;
+        let d = -dot(normal, point);
+        Self(
+            [
+                a * a,
+                a * b,
+                a * c,
+                b * b,
+                b * c,
+                c * c,
+                a * d,
+                b * d,
+                c * d,
+                d * d,
+            ]
+            .map(|v| v * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, [x, y, z]: Vec3) -> f64 {
+        let q = &self.0;
+        let error = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + q[3] * y * y
+            + 2.0 * q[4] * y * z
+            + q[5] * z * z
+            + 2.0 * (q[6] * x + q[7] * y + q[8] * z)
+            + q[9];
+        error.max(0.0)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Candidate {
+    cost: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// Working state of a simplification of a triangulated node.
+///
+/// Corners with the same vertex and corner values are grouped in wedges. Faces reference wedges,
+/// and a collapse moves every wedge of the removed vertex to a wedge of the kept vertex.
+struct Simplifier {
+    positions: Vec<Vec3>,
+    /// The three wedges of each face.
+    faces: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    alive_count: usize,
+    wedge_vertex: Vec<usize>,
+    /// A corner of the node holding the values of each wedge.
+    wedge_corner: Vec<usize>,
+    vertex_faces: Vec<Vec<usize>>,
+    removed: Vec<bool>,
+    locked: Vec<bool>,
+    feature_edges: HashSet<(usize, usize)>,
+    feature_count: Vec<usize>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    /// A corner of the node holding the edge layer values of each directed edge.
+    edge_corner: HashMap<(usize, usize), usize>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Simplifier {
+    fn new(node: &GeometryNode, feature_weight: f64) -> Result<Self> {
+        let positions = node.positions()?;
+        let corner_vertices = node.corner_vertices()?;
+        let vertex_count = positions.len();
+        let face_count = corner_vertices.len() / 3;
+
+        let mut wedge_keys: HashMap<Vec<u64>, usize> = HashMap::new();
+        let mut wedge_vertex = Vec::new();
+        let mut wedge_corner = Vec::new();
+        let mut corner_wedge = Vec::with_capacity(corner_vertices.len());
+        for (corner, &vertex) in corner_vertices.iter().enumerate() {
+            let mut key = vec![vertex as u64];
+            for layer in node.corner_stack.iter().skip(1) {
+                layer
+                    .data
+                    .push_element_bits(layer.components as usize, corner, &mut key);
+            }
+            let wedge = *wedge_keys.entry(key).or_insert_with(|| {
+                wedge_vertex.push(vertex);
+                wedge_corner.push(corner);
+                wedge_vertex.len() - 1
+            });
+            corner_wedge.push(wedge);
+        }
+        let faces: Vec<[usize; 3]> = corner_wedge
+            .chunks_exact(3)
+            .map(|w| [w[0], w[1], w[2]])
+            .collect();
+
+        let mut vertex_faces = vec![Vec::new(); vertex_count];
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut edge_corner = HashMap::new();
+        for face in 0..face_count {
+            for k in 0..3 {
+                let a = corner_vertices[face * 3 + k];
+                let b = corner_vertices[face * 3 + (k + 1) % 3];
+                vertex_faces[a].push(face);
+                edge_faces.entry(edge_key(a, b)).or_default().push(face);
+                edge_corner.insert((a, b), face * 3 + k);
+            }
+        }
+
+        let creases = node.edge_creases();
+        let mut locked = vec![false; vertex_count];
+        let mut feature_edges = HashSet::new();
+        for (&(a, b), edge_faces) in &edge_faces {
+            let feature = match edge_faces[..] {
+                [_] => true,
+                [f, g] => {
+                    let wedge = |face: usize, vertex: usize| {
+                        (0..3)
+                            .map(|k| corner_wedge[face * 3 + k])
+                            .find(|&w| wedge_vertex[w] == vertex)
+                    };
+                    let crease = |face: usize| {
+                        (0..3).any(|k| {
+                            let corner = face * 3 + k;
+                            let next = face * 3 + (k + 1) % 3;
+                            edge_key(corner_vertices[corner], corner_vertices[next])
+                                == edge_key(a, b)
+                                && creases[corner] > 0.0
+                        })
+                    };
+                    wedge(f, a) != wedge(g, a)
+                        || wedge(f, b) != wedge(g, b)
+                        || node
+                            .face_stack
+                            .iter()
+                            .any(|layer| !layer.data.element_eq(layer.components as usize, f, g))
+                        || crease(f)
+                        || crease(g)
+                }
+                _ => {
+                    locked[a] = true;
+                    locked[b] = true;
+                    false
+                }
+            };
+            if feature {
+                feature_edges.insert((a, b));
+            }
+        }
+        let mut feature_count = vec![0; vertex_count];
+        for &(a, b) in &feature_edges {
+            feature_count[a] += 1;
+            feature_count[b] += 1;
+        }
+        for vertex in 0..vertex_count {
+            if feature_count[vertex] != 0 && feature_count[vertex] != 2 {
+                locked[vertex] = true;
+            }
+        }
+
+        let mut quadrics = vec![Quadric::default(); vertex_count];
+        let mut face_normals = Vec::with_capacity(face_count);
+        for face in 0..face_count {
+            let [a, b, c] = [0, 1, 2].map(|k| corner_vertices[face * 3 + k]);
+            let normal = normalize(triangle_normal(positions[a], positions[b], positions[c]));
+            let quadric = Quadric::plane(normal, positions[a], 1.0);
+            for vertex in [a, b, c] {
+                quadrics[vertex].add(&quadric);
+            }
+            face_normals.push(normal);
+        }
+        for &(a, b) in &feature_edges {
+            for &face in &edge_faces[&(a, b)] {
+                let direction = sub(positions[b], positions[a]);
+                let normal = normalize(cross(direction, face_normals[face]));
+                let quadric = Quadric::plane(normal, positions[a], feature_weight);
+                quadrics[a].add(&quadric);
+                quadrics[b].add(&quadric);
+            }
+        }
+
+        let mut simplifier = Self {
+            positions,
+            faces,
+            alive: vec![true; face_count],
+            alive_count: face_count,
+            wedge_vertex,
+            wedge_corner,
+            vertex_faces,
+            removed: vec![false; vertex_count],
+            locked,
+            feature_edges,
+            feature_count,
+            quadrics,
+            versions: vec![0; vertex_count],
+            edge_corner,
+            heap: BinaryHeap::new(),
+        };
+        for vertex in 0..vertex_count {
+            simplifier.push_candidates(vertex);
+        }
+
+        Ok(simplifier)
+    }
+
+    fn is_feature(&self, a: usize, b: usize) -> bool {
+        self.feature_edges.contains(&edge_key(a, b))
+    }
+
+    fn face_vertices(&self, face: usize) -> [usize; 3] {
+        self.faces[face].map(|w| self.wedge_vertex[w])
+    }
+
+    fn alive_faces(&self, vertex: usize) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_faces[vertex]
+            .iter()
+            .copied()
+            .filter(move |&f| self.alive[f] && self.face_vertices(f).contains(&vertex))
+    }
+
+    fn neighbours(&self, vertex: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self
+            .alive_faces(vertex)
+            .flat_map(|f| self.face_vertices(f))
+            .filter(|&v| v != vertex)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn may_move(&self, from: usize, to: usize) -> bool {
+        !self.locked[from]
+            && !self.removed[from]
+            && !self.removed[to]
+            && match self.feature_count[from] {
+                0 => true,
+                _ => self.is_feature(from, to),
+            }
+    }
+
+    fn cost(&self, from: usize, to: usize) -> f64 {
+        let mut quadric = self.quadrics[from];
+        quadric.add(&self.quadrics[to]);
+        quadric.error(self.positions[to])
+    }
+
+    fn push_candidates(&mut self, vertex: usize) {
+        for neighbour in self.neighbours(vertex) {
+            for (from, to) in [(vertex, neighbour), (neighbour, vertex)] {
+                if self.may_move(from, to) {
+                    self.heap.push(Candidate {
+                        cost: self.cost(from, to),
+                        from,
+                        to,
+                        from_version: self.versions[from],
+                        to_version: self.versions[to],
+                    });
+                }
+            }
+        }
+    }
+
+    /// The wedge of `to` replacing each wedge of `from`, if the collapse keeps the mesh valid.
+    fn wedge_map(&self, from: usize, to: usize) -> Option<HashMap<usize, usize>> {
+        let faces: Vec<usize> = self.alive_faces(from).collect();
+        let (shared, others): (Vec<usize>, Vec<usize>) = faces
+            .into_iter()
+            .partition(|&f| self.face_vertices(f).contains(&to));
+        // Without other faces the whole fan of the vertex would disappear.
+        if shared.is_empty() || others.is_empty() {
+            return None;
+        }
+
+        // The vertices opposite the collapsed edge have to be the only shared neighbours, or the
+        // collapse would pinch the surface.
+        let mut opposite: Vec<usize> = shared
+            .iter()
+            .flat_map(|&f| self.face_vertices(f))
+            .filter(|&v| v != from && v != to)
+            .collect();
+        opposite.sort_unstable();
+        opposite.dedup();
+        let to_neighbours = self.neighbours(to);
+        let common: Vec<usize> = self
+            .neighbours(from)
+            .into_iter()
+            .filter(|v| to_neighbours.binary_search(v).is_ok())
+            .collect();
+        if common != opposite {
+            return None;
+        }
+
+        let wedge_of = |face: usize, vertex: usize| {
+            self.faces[face]
+                .into_iter()
+                .find(|&w| self.wedge_vertex[w] == vertex)
+                .expect("face uses the vertex")
+        };
+        let mut map = HashMap::new();
+        for &face in &shared {
+            let target = wedge_of(face, to);
+            if *map.entry(wedge_of(face, from)).or_insert(target) != target {
+                return None;
+            }
+        }
+
+        for &face in &others {
+            if !map.contains_key(&wedge_of(face, from)) {
+                return None;
+            }
+            let [a, b, c] = self.face_vertices(face).map(|v| self.positions[v]);
+            let before = triangle_normal(a, b, c);
+            let [a, b, c] = self
+                .face_vertices(face)
+                .map(|v| self.positions[if v == from { to } else { v }]);
+            let after = triangle_normal(a, b, c);
+            if dot(before, after) <= 0.0 || length(after) <= length(before) * 1e-6 {
+                return None;
+            }
+        }
+
+        Some(map)
+    }
+
+    fn collapse(&mut self, from: usize, to: usize, map: &HashMap<usize, usize>) {
+        let neighbours = self.neighbours(from);
+        let faces: Vec<usize> = self.alive_faces(from).collect();
+        let mut dead_edges = HashMap::new();
+        for face in faces {
+            let vertices = self.face_vertices(face);
+            if vertices.contains(&to) {
+                self.alive[face] = false;
+                self.alive_count -= 1;
+                for k in 0..3 {
+                    let edge = (vertices[k], vertices[(k + 1) % 3]);
+                    if let Some(corner) = self.edge_corner.remove(&edge) {
+                        dead_edges.insert(edge, corner);
+                    }
+                }
+            } else {
+                for wedge in &mut self.faces[face] {
+                    if let Some(&target) = map.get(wedge) {
+                        *wedge = target;
+                    }
+                }
+                self.vertex_faces[to].push(face);
+            }
+        }
+
+        let quadric = self.quadrics[from];
+        self.quadrics[to].add(&quadric);
+        self.removed[from] = true;
+        self.versions[to] += 1;
+
+        for &neighbour in &neighbours {
+            // The edges of a removed face merge with the edges of the moved vertex, the values of
+            // a feature edge win.
+            let keep_dead = self.is_feature(to, neighbour) && !self.is_feature(from, neighbour);
+            if self.feature_edges.remove(&edge_key(from, neighbour)) && neighbour != to {
+                self.feature_edges.insert(edge_key(to, neighbour));
+            }
+            for (a, b) in [(from, neighbour), (neighbour, from)] {
+                if let Some(corner) = self.edge_corner.remove(&(a, b)) {
+                    let key = if a == from { (to, b) } else { (a, to) };
+                    if key.0 != key.1 {
+                        let corner = match dead_edges.get(&key) {
+                            Some(&dead) if keep_dead => dead,
+                            _ => corner,
+                        };
+                        self.edge_corner.entry(key).or_insert(corner);
+                    }
+                }
+            }
+        }
+        let mut touched = self.neighbours(to);
+        touched.push(to);
+        for vertex in touched {
+            self.feature_count[vertex] = self
+                .neighbours(vertex)
+                .into_iter()
+                .filter(|&n| self.is_feature(vertex, n))
+                .count();
+        }
+    }
+
+    fn run(&mut self, options: &SimplifyOptions) -> f64 {
+        let max_cost = options.max_error * options.max_error;
+        let mut error: f64 = 0.0;
+        while self.alive_count > options.target_face_count {
+            let Some(candidate) = self.heap.pop() else {
+                break;
+            };
+            let (from, to) = (candidate.from, candidate.to);
+            if self.removed[from]
+                || self.removed[to]
+                || self.versions[from] != candidate.from_version
+                || self.versions[to] != candidate.to_version
+            {
+                continue;
+            }
+            if candidate.cost > max_cost {
+                break;
+            }
+            if !self.may_move(from, to) {
+                continue;
+            }
+            let Some(map) = self.wedge_map(from, to) else {
+                continue;
+            };
+            self.collapse(from, to, &map);
+            error = error.max(candidate.cost);
+            // Rejected candidates around the collapse may have become valid.
+            for vertex in self.neighbours(to) {
+                self.push_candidates(vertex);
+            }
+        }
+
+        error.sqrt()
+    }
+
+    fn build(&self, node: &GeometryNode) -> Result<GeometryNode> {
+        let faces: Vec<(usize, Vec<usize>)> = (0..self.faces.len())
+            .filter(|&f| self.alive[f])
+            .map(|f| (f, self.faces[f].map(|w| self.wedge_corner[w]).to_vec()))
+            .collect();
+        let mut edge_sources = Vec::with_capacity(faces.len() * 3);
+        for &(face, _) in &faces {
+            let vertices = self.face_vertices(face);
+            for k in 0..3 {
+                let edge = (vertices[k], vertices[(k + 1) % 3]);
+                edge_sources.push(self.edge_corner.get(&edge).copied());
+            }
+        }
+
+        let mut result = node.rebuild_faces(&faces)?;
+        result.edge_stack = node.edge_stack.gather_optional(&edge_sources);
+        if result
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            result.generate_neighbours()?;
+        }
+        result.remove_unused_vertices()?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::triangle_grid;
+    use crate::{Layer, LayerData, Node, NodeContent};
+
+    fn grid_with_uv_seam() -> GeometryNode {
+        // The uv jumps by 10 across x = 4.
+        let mut node = triangle_grid(8);
+        let positions = node.positions().unwrap();
+        let corner_faces = node.corner_faces().unwrap();
+        let polygons = node.polygons().unwrap();
+        let mut uv = Vec::new();
+        for (corner, vertex) in node.corner_vertices().unwrap().into_iter().enumerate() {
+            let face = &polygons[corner_faces[corner]];
+            let right = face.iter().any(|&v| positions[v][0] > 4.0);
+            let [x, y, _] = positions[vertex];
+            uv.extend([x as f32 + if right { 10.0 } else { 0.0 }, y as f32]);
+        }
+        node.corner_stack
+            .push(Layer::new("uv", 2, LayerData::Float(uv)));
+        node
+    }
+
+    fn assert_faces_on_one_side(node: &GeometryNode, x: f64) {
+        let positions = node.positions().unwrap();
+        for polygon in node.polygons().unwrap() {
+            let left = polygon.iter().all(|&v| positions[v][0] <= x);
+            let right = polygon.iter().all(|&v| positions[v][0] >= x);
+            assert!(left || right, "{:?}", polygon);
+        }
+    }
+
+    #[test]
+    fn simplify_flat_grid_without_error() {
+        let mut node = triangle_grid(8);
+        let error = node
+            .simplify(&SimplifyOptions {
+                max_error: 1e-6,
+                ..SimplifyOptions::new(2)
+            })
+            .unwrap();
+
+        assert_eq!(node.face_count(), 2);
+        assert!(error < 1e-6);
+        let mut positions = node.positions().unwrap();
+        positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            positions,
+            vec![
+                [0.0, 0.0, 0.0],
+                [0.0, 8.0, 0.0],
+                [8.0, 0.0, 0.0],
+                [8.0, 8.0, 0.0]
+            ]
+        );
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn simplify_stops_at_error() {
+        // A ridge along y, the grid can not become flat without error.
+        let mut node = triangle_grid(8);
+        let positions: Vec<[f64; 3]> = node
+            .positions()
+            .unwrap()
+            .into_iter()
+            .map(|[x, y, _]| [x, y, 4.0 - (x - 4.0).abs()])
+            .collect();
+        node.set_positions(&positions).unwrap();
+        node.simplify(&SimplifyOptions {
+            max_error: 0.01,
+            ..SimplifyOptions::new(0)
+        })
+        .unwrap();
+
+        assert!(node.face_count() >= 4);
+        let positions = node.positions().unwrap();
+        assert!(positions.iter().any(|p| p[0] == 4.0 && p[2] == 4.0));
+    }
+
+    #[test]
+    fn simplify_preserves_uv_seams() {
+        let mut node = grid_with_uv_seam();
+        node.simplify(&SimplifyOptions::new(0)).unwrap();
+
+        assert!(node.face_count() < 20, "{}", node.face_count());
+        assert_faces_on_one_side(&node, 4.0);
+        // Corners keep the uv of their side of the seam.
+        let positions = node.positions().unwrap();
+        let uv = node.corner_stack.get("uv").unwrap();
+        for (corner, vertex) in node.corner_vertices().unwrap().into_iter().enumerate() {
+            let u = uv.get(corner, 0);
+            assert!(u == positions[vertex][0] || u == positions[vertex][0] + 10.0);
+        }
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn simplify_preserves_material_borders_and_vertex_layers() {
+        let mut node = triangle_grid(8);
+        let positions = node.positions().unwrap();
+        let material: Vec<i32> = node
+            .polygons()
+            .unwrap()
+            .iter()
+            .map(|face| face.iter().any(|&v| positions[v][0] > 4.0) as i32)
+            .collect();
+        node.face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(material)));
+        let weights: Vec<f32> = positions
+            .iter()
+            .map(|p| (p[0] + p[1] * 9.0) as f32)
+            .collect();
+        node.vertex_stack.push(Layer::new(
+            convention::LAYER_SKIN_WEIGHT,
+            1,
+            LayerData::Float(weights),
+        ));
+        node.simplify(&SimplifyOptions::new(0)).unwrap();
+
+        assert_faces_on_one_side(&node, 4.0);
+        let positions = node.positions().unwrap();
+        let material = node.face_stack.get("material").unwrap();
+        for (face, polygon) in node.polygons().unwrap().into_iter().enumerate() {
+            let right = polygon.iter().any(|&v| positions[v][0] > 4.0);
+            assert_eq!(material.get(face, 0), right as i32 as f64);
+        }
+        let weights = node
+            .vertex_stack
+            .get(convention::LAYER_SKIN_WEIGHT)
+            .unwrap();
+        for (vertex, p) in positions.iter().enumerate() {
+            assert_eq!(weights.get(vertex, 0), p[0] + p[1] * 9.0);
+        }
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn simplify_preserves_creases() {
+        let mut node = triangle_grid(8);
+        let positions = node.positions().unwrap();
+        let corner_vertices = node.corner_vertices().unwrap();
+        let faces = node.faces().unwrap();
+        let mut creases = vec![0.0f32; corner_vertices.len()];
+        for face in &faces {
+            for corner in face.clone() {
+                let next = crate::geometry::next_corner(face, corner);
+                let (a, b) = (
+                    positions[corner_vertices[corner]],
+                    positions[corner_vertices[next]],
+                );
+                if a[0] == 4.0 && b[0] == 4.0 {
+                    creases[corner] = 1.0;
+                }
+            }
+        }
+        node.edge_stack.push(Layer::new(
+            convention::LAYER_CREASES,
+            1,
+            LayerData::Float(creases),
+        ));
+        node.simplify(&SimplifyOptions::new(0)).unwrap();
+
+        assert_faces_on_one_side(&node, 4.0);
+        let creases = node.edge_creases();
+        assert_eq!(creases.iter().filter(|&&c| c > 0.0).count(), 2);
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn generate_lod_chain() {
+        let mut file = File::new();
+        let mut node: Node = triangle_grid(8).into();
+        node.set_name("ground");
+        node.update_bounds().unwrap();
+        file.nodes.push(node);
+        file.nodes.push(Node::new(NodeContent::MetaOnly));
+        file.set_parent(1, Some(0)).unwrap();
+
+        let lods = file
+            .generate_lods(0, &[SimplifyOptions::new(32), SimplifyOptions::new(8)])
+            .unwrap();
+
+        assert_eq!(lods, vec![2, 3]);
+        assert_eq!(
+            file.nodes[0].find_meta(convention::LOD).unwrap().as_node(),
+            Some(&[2, 3][..])
+        );
+        assert_eq!(file.nodes[2].name(), Some("ground_lod1"));
+        assert_eq!(file.nodes[3].name(), Some("ground_lod2"));
+        assert!(file.nodes[2].geometry().unwrap().face_count() <= 32);
+        assert!(file.nodes[3].geometry().unwrap().face_count() <= 8);
+        assert!(file.nodes[3].find_meta(convention::LOD_ERROR).is_some());
+        assert!(file.nodes[3].find_meta(convention::LOD).is_none());
+        let scene = file.scene().unwrap();
+        assert_eq!(scene.children(0), [1]);
+        for lod in lods {
+            assert_eq!(scene.parent(lod), None);
+            let area = file.nodes[lod].find_meta(convention::SURFACE_AREA).unwrap();
+            let expected = file.geometry(lod).unwrap().surface_area().unwrap();
+            assert_eq!(area.as_double(), Some(&[expected][..]));
+        }
+        assert!(matches!(
+            file.generate_lods(1, &[SimplifyOptions::new(1)]),
+            Err(crate::Error::NodeType { node: 1, .. })
+        ));
+    }
+}
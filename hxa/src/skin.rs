@@ -0,0 +1,423 @@
+//! Skinning weights and linear blend skinning.
+//!
+//! Skinned geometry stores the bones influencing each vertex in an int32 `skining_reference`
+//! vertex layer, as indices of the nodes acting as bones, and their weights in a
+//! `skining_weight` vertex layer with the same number of components. Unused influences have a
+//! bone of -1. Integer weight layers hold unorm values, so a uint8 weight of 255 stands for 1.
+
+use hxa_sys::{HXALayerDataType, HXANodeType};
+
+use crate::{
+    convention, ConvertOptions, Error, File, GeometryNode, Layer, LayerData, Mat4, Normalization,
+    Result, Rounding,
+};
+
+/// Reads and writes integer weights as unorm values.
+const WEIGHT_CONVERSION: ConvertOptions = ConvertOptions {
+    normalization: Normalization::Unorm,
+    rounding: Rounding::Nearest,
+    clamp: None,
+};
+
+/// The skinning influences of every vertex of a geometry node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skin {
+    /// The number of influences stored per vertex.
+    pub influences: usize,
+    /// The bone node of every influence, -1 for unused influences.
+    pub bones: Vec<i32>,
+    pub weights: Vec<f32>,
+}
+
+impl Skin {
+    /// A skin without any influences.
+    pub fn new(influences: usize, vertex_count: usize) -> Self {
+        Self {
+            influences,
+            bones: vec![-1; influences * vertex_count],
+            weights: vec![0.0; influences * vertex_count],
+        }
+    }
+
+    /// The number of vertices with both bones and weights.
+    pub fn vertex_count(&self) -> usize {
+        match self.influences {
+            0 => 0,
+            influences => self.bones.len().min(self.weights.len()) / influences,
+        }
+    }
+
+    /// The bone and weight of every used influence of a vertex. Vertices past the end of the
+    /// bones or weights have no influences.
+    pub fn vertex(&self, vertex: usize) -> impl Iterator<Item = (usize, f32)> + '_ {
+        let range = vertex * self.influences..(vertex + 1) * self.influences;
+        let bones = self.bones.get(range.clone()).unwrap_or_default();
+        let weights = self.weights.get(range).unwrap_or_default();
+        bones
+            .iter()
+            .zip(weights)
+            .filter(|&(&bone, _)| bone >= 0)
+            .map(|(&bone, &weight)| (bone as usize, weight))
+    }
+
+    /// Scale the weights of every vertex to add up to 1. Vertices without weight are left as is.
+    pub fn normalize(&mut self) {
+        for vertex in 0..self.vertex_count() {
+            let range = vertex * self.influences..(vertex + 1) * self.influences;
+            let total: f32 = self.bones[range.clone()]
+                .iter()
+                .zip(&self.weights[range.clone()])
+                .filter(|&(&bone, _)| bone >= 0)
+                .map(|(_, &weight)| weight)
+                .sum();
+            if total > 0.0 {
+                for (bone, weight) in self.bones[range.clone()]
+                    .iter()
+                    .zip(&mut self.weights[range])
+                {
+                    *weight = if *bone >= 0 { *weight / total } else { 0.0 };
+                }
+            }
+        }
+    }
+
+    /// Order the influences of every vertex from the largest to the smallest weight, with the
+    /// unused influences last.
+    pub fn sort(&mut self) {
+        let influences = self.influences;
+        for vertex in 0..self.vertex_count() {
+            let range = vertex * influences..(vertex + 1) * influences;
+            let mut sorted: Vec<(i32, f32)> = self.bones[range.clone()]
+                .iter()
+                .copied()
+                .zip(self.weights[range.clone()].iter().copied())
+                .collect();
+            sorted.sort_by(|a, b| (b.0 >= 0).cmp(&(a.0 >= 0)).then(b.1.total_cmp(&a.1)));
+            for (slot, (bone, weight)) in range.zip(sorted) {
+                self.bones[slot] = bone;
+                self.weights[slot] = weight;
+            }
+        }
+    }
+
+    /// Keep at most `max` influences per vertex by pruning the smallest weights.
+    ///
+    /// The influences are sorted and the remaining weights are normalized again.
+    pub fn limit(&mut self, max: usize) {
+        self.sort();
+        if max < self.influences {
+            let vertex_count = self.vertex_count();
+            let mut bones = Vec::with_capacity(vertex_count * max);
+            let mut weights = Vec::with_capacity(vertex_count * max);
+            for vertex in 0..vertex_count {
+                let start = vertex * self.influences;
+                bones.extend_from_slice(&self.bones[start..start + max]);
+                weights.extend_from_slice(&self.weights[start..start + max]);
+            }
+            self.influences = max;
+            self.bones = bones;
+            self.weights = weights;
+        }
+        self.normalize();
+    }
+
+    /// Check that every bone is a meta only node of the file, and that weights are finite and
+    /// not negative.
+    pub fn validate(&self, file: &File) -> Result<()> {
+        for vertex in 0..self.vertex_count() {
+            for (bone, weight) in self.vertex(vertex) {
+                let valid = file
+                    .nodes
+                    .get(bone)
+                    .is_some_and(|node| node.node_type() == HXANodeType::HXA_NT_META_ONLY);
+                if !valid {
+                    return Err(Error::InvalidBone {
+                        vertex,
+                        bone: bone as i32,
+                    });
+                }
+                if !weight.is_finite() || weight < 0.0 {
+                    return Err(Error::InvalidWeight { vertex });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deform positions with linear blend skinning.
+    ///
+    /// `matrices` holds the skinning matrix of every bone node, indexed by node, moving a point
+    /// from its bind pose to its posed position. Fails with [`Error::InvalidBone`] if a bone has
+    /// no matrix.
+    pub fn deform(&self, positions: &[[f64; 3]], matrices: &[Mat4]) -> Result<Vec<[f64; 3]>> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(vertex, &position)| {
+                let mut total = 0.0;
+                let mut deformed = [0.0; 3];
+                for (bone, weight) in self.vertex(vertex) {
+                    let matrix = matrices.get(bone).ok_or(Error::InvalidBone {
+                        vertex,
+                        bone: bone as i32,
+                    })?;
+                    let moved = matrix.transform_point(position);
+                    for axis in 0..3 {
+                        deformed[axis] += moved[axis] * weight as f64;
+                    }
+                    total += weight as f64;
+                }
+                Ok(if total > 0.0 {
+                    deformed.map(|value| value / total)
+                } else {
+                    position
+                })
+            })
+            .collect()
+    }
+}
+
+impl GeometryNode {
+    /// Read the `skining_reference` and `skining_weight` vertex layers.
+    ///
+    /// Fails with [`Error::LayerLength`] if either layer does not have an element per vertex.
+    pub fn skin(&self) -> Result<Skin> {
+        let references = self.vertex_stack.get(convention::LAYER_SKIN_REFERENCE)?;
+        let weights = self.vertex_stack.get(convention::LAYER_SKIN_WEIGHT)?;
+        let LayerData::Int32(bones) = &references.data else {
+            return Err(Error::LayerType {
+                name: references.name.clone(),
+                expected: HXALayerDataType::HXA_LDT_INT32,
+                actual: references.data_type(),
+            });
+        };
+        weights.expect_components(references.components)?;
+        for layer in [references, weights] {
+            if layer.len() != self.vertex_count() {
+                return Err(Error::LayerLength {
+                    name: layer.name.clone(),
+                    expected: self.vertex_count(),
+                    actual: layer.len(),
+                });
+            }
+        }
+
+        Ok(Skin {
+            influences: references.components as usize,
+            bones: bones.clone(),
+            weights: weights
+                .convert_with(HXALayerDataType::HXA_LDT_DOUBLE, &WEIGHT_CONVERSION)
+                .data
+                .to_f64_vec()
+                .into_iter()
+                .map(|w| w as f32)
+                .collect(),
+        })
+    }
+
+    /// Write the `skining_reference` and `skining_weight` vertex layers.
+    ///
+    /// The weights keep the type of an existing weight layer, and are stored as float otherwise.
+    pub fn set_skin(&mut self, skin: &Skin) -> Result<()> {
+        let weight_type = self
+            .vertex_stack
+            .find(convention::LAYER_SKIN_WEIGHT)
+            .map_or(HXALayerDataType::HXA_LDT_FLOAT, Layer::data_type);
+        self.set_skin_as(skin, weight_type)
+    }
+
+    /// Write the skinning layers, storing the weights as `weight_type`.
+    pub(crate) fn set_skin_as(&mut self, skin: &Skin, weight_type: HXALayerDataType) -> Result<()> {
+        let influences = u8::try_from(skin.influences).map_err(|_| Error::TooManyInfluences {
+            influences: skin.influences,
+        })?;
+        let expected = self.vertex_count() * skin.influences;
+        for (name, len) in [
+            (convention::LAYER_SKIN_REFERENCE, skin.bones.len()),
+            (convention::LAYER_SKIN_WEIGHT, skin.weights.len()),
+        ] {
+            if len != expected {
+                return Err(Error::LayerLength {
+                    name: name.to_string(),
+                    expected: self.vertex_count(),
+                    actual: len / skin.influences.max(1),
+                });
+            }
+        }
+        self.vertex_stack.insert(Layer::new(
+            convention::LAYER_SKIN_REFERENCE,
+            influences,
+            LayerData::Int32(skin.bones.clone()),
+        ));
+        self.vertex_stack.insert(
+            Layer::new(
+                convention::LAYER_SKIN_WEIGHT,
+                influences,
+                LayerData::Float(skin.weights.clone()),
+            )
+            .convert_with(weight_type, &WEIGHT_CONVERSION),
+        );
+
+        Ok(())
+    }
+
+    /// The vertex positions deformed by linear blend skinning. See [`Skin::deform`].
+    pub fn skinned_positions(&self, matrices: &[Mat4]) -> Result<Vec<[f64; 3]>> {
+        self.skin()?.deform(&self.positions()?, matrices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+    use crate::{Node, NodeContent};
+
+    fn skin() -> Skin {
+        Skin {
+            influences: 3,
+            bones: vec![0, 1, -1, 1, 0, 2, 2, -1, -1],
+            weights: vec![1.0, 3.0, 9.0, 0.2, 0.6, 0.2, 2.0, 0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn normalize_sort_and_limit() {
+        let mut skin = skin();
+        skin.normalize();
+        assert_eq!(skin.weights[..3], [0.25, 0.75, 0.0]);
+        assert_eq!(skin.weights[6], 1.0);
+
+        skin.sort();
+        assert_eq!(skin.bones, [1, 0, -1, 0, 1, 2, 2, -1, -1]);
+
+        skin.limit(2);
+        assert_eq!(skin.influences, 2);
+        assert_eq!(skin.bones, [1, 0, 0, 1, 2, -1]);
+        assert_eq!(skin.weights[2..4], [0.75, 0.25]);
+        assert_eq!(skin.vertex(2).collect::<Vec<_>>(), [(2, 1.0)]);
+    }
+
+    #[test]
+    fn layers_round_trip_and_validate() {
+        let mut node = quad_and_triangle();
+        let mut skin = Skin::new(2, 5);
+        skin.bones[0] = 1;
+        skin.weights[0] = 1.0;
+        node.set_skin(&skin).unwrap();
+        assert_eq!(node.skin().unwrap(), skin);
+        assert!(node.set_skin(&Skin::new(2, 4)).is_err());
+
+        let mut file = File::new();
+        file.nodes.push(node.into());
+        file.nodes.push(Node::new(NodeContent::MetaOnly));
+        skin.validate(&file).unwrap();
+        skin.bones[2] = 0;
+        assert_eq!(
+            skin.validate(&file),
+            Err(Error::InvalidBone { vertex: 1, bone: 0 })
+        );
+        skin.bones[2] = 1;
+        skin.weights[2] = -1.0;
+        assert_eq!(
+            skin.validate(&file),
+            Err(Error::InvalidWeight { vertex: 1 })
+        );
+    }
+
+    #[test]
+    fn weights_keep_their_stored_type() {
+        let mut skin = Skin::new(2, 5);
+        skin.bones[0] = 1;
+        skin.weights[0] = 0.6;
+        skin.weights[1] = 0.4;
+        for data in [
+            LayerData::Uint8(vec![0; 10]),
+            LayerData::Double(vec![0.0; 10]),
+        ] {
+            let mut node = quad_and_triangle();
+            node.vertex_stack
+                .insert(Layer::new(convention::LAYER_SKIN_WEIGHT, 2, data));
+            node.set_skin(&skin).unwrap();
+            let weights = node
+                .vertex_stack
+                .find(convention::LAYER_SKIN_WEIGHT)
+                .unwrap();
+            match &weights.data {
+                LayerData::Uint8(values) => assert_eq!(values[..2], [153, 102]),
+                LayerData::Double(values) => assert_eq!(values[0], 0.6f32 as f64),
+                _ => panic!("weights changed type"),
+            }
+            let read = node.skin().unwrap();
+            assert!((read.weights[0] - 0.6).abs() < 1e-6);
+            assert!((read.weights[1] - 0.4).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mismatched_skins_are_rejected() {
+        let mut node = quad_and_triangle();
+        assert_eq!(
+            node.set_skin(&Skin::new(300, 5)),
+            Err(Error::TooManyInfluences { influences: 300 })
+        );
+        let mut truncated = Skin::new(2, 5);
+        truncated.weights.truncate(4);
+        assert_eq!(
+            node.set_skin(&truncated),
+            Err(Error::LayerLength {
+                name: convention::LAYER_SKIN_WEIGHT.to_string(),
+                expected: 5,
+                actual: 2
+            })
+        );
+
+        node.set_skin(&Skin::new(2, 5)).unwrap();
+        node.vertex_stack
+            .get_mut(convention::LAYER_SKIN_WEIGHT)
+            .unwrap()
+            .data = LayerData::Float(vec![0.0; 6]);
+        assert_eq!(
+            node.skin(),
+            Err(Error::LayerLength {
+                name: convention::LAYER_SKIN_WEIGHT.to_string(),
+                expected: 5,
+                actual: 3
+            })
+        );
+
+        // Public fields can still disagree, which leaves the missing vertices without influences.
+        let mut short = skin();
+        short.weights.truncate(4);
+        assert_eq!(short.vertex_count(), 1);
+        assert_eq!(short.vertex(2).count(), 0);
+        short.normalize();
+        let deformed = short.deform(&[[1.0; 3]; 3], &[Mat4::IDENTITY; 3]).unwrap();
+        assert_eq!(deformed[2], [1.0; 3]);
+    }
+
+    #[test]
+    fn linear_blend_skinning() {
+        let mut node = quad_and_triangle();
+        let mut skin = Skin::new(2, 5);
+        // Vertex 1 follows bone 0, vertex 2 is split evenly, vertex 4 has no influences.
+        skin.bones[2..6].copy_from_slice(&[0, -1, 0, 1]);
+        skin.weights[2..6].copy_from_slice(&[1.0, 0.0, 0.5, 0.5]);
+        node.set_skin(&skin).unwrap();
+        let matrices = [
+            Mat4::from_translation([0.0, 0.0, 2.0]),
+            Mat4::from_translation([0.0, 0.0, 4.0]),
+        ];
+        let positions = node.positions().unwrap();
+        let skinned = node.skinned_positions(&matrices).unwrap();
+
+        assert_eq!(skinned[1], [positions[1][0], positions[1][1], 2.0]);
+        assert_eq!(skinned[2], [positions[2][0], positions[2][1], 3.0]);
+        assert_eq!(skinned[4], positions[4]);
+        assert_eq!(
+            node.skinned_positions(&matrices[..1]),
+            Err(Error::InvalidBone { vertex: 2, bone: 1 })
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Splitting geometry nodes into parts.
+
+use std::collections::BTreeMap;
+
+use crate::{convention, Error, File, GeometryNode, Result};
+
+/// How to group the faces of a node into parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplitBy {
+    /// One part per value of the `material` face layer, in increasing order.
+    Material,
+    /// One part per value of the `group` face layer, in increasing order.
+    Group,
+    /// One part per set of faces connected through shared vertices, in order of their first face.
+    Connectivity,
+}
+
+impl GeometryNode {
+    /// The part of every face, numbered from 0.
+    pub fn face_parts(&self, by: SplitBy) -> Result<Vec<usize>> {
+        let layer = match by {
+            SplitBy::Material => convention::LAYER_MATERIAL_ID,
+            SplitBy::Group => convention::LAYER_GROUP_ID,
+            SplitBy::Connectivity => return self.connected_faces(),
+        };
+        let layer = self.face_stack.get(layer)?;
+        layer.expect_components(1)?;
+        if layer.len() != self.face_count() {
+            return Err(Error::LayerLength {
+                name: layer.name.clone(),
+                expected: self.face_count(),
+                actual: layer.len(),
+            });
+        }
+        let keys: Vec<i64> = (0..self.face_count())
+            .map(|face| layer.get(face, 0) as i64)
+            .collect();
+        let parts: BTreeMap<i64, usize> = keys
+            .iter()
+            .map(|&key| (key, 0))
+            .collect::<BTreeMap<_, _>>()
+            .into_keys()
+            .enumerate()
+            .map(|(part, key)| (key, part))
+            .collect();
+
+        Ok(keys.iter().map(|key| parts[key]).collect())
+    }
+
+    /// Split the faces into separate nodes.
+    ///
+    /// Every part keeps all layers, with only the vertices its faces use.
+    pub fn split(&self, by: SplitBy) -> Result<Vec<GeometryNode>> {
+        let face_parts = self.face_parts(by)?;
+        let part_count = face_parts.iter().max().map_or(0, |&max| max + 1);
+        let mut faces = vec![Vec::new(); part_count];
+        for (face, range) in self.faces()?.into_iter().enumerate() {
+            faces[face_parts[face]].push((face, range.collect()));
+        }
+
+        faces
+            .iter()
+            .map(|faces| {
+                let mut part = self.rebuild_faces(faces)?;
+                part.remove_unused_vertices()?;
+                Ok(part)
+            })
+            .collect()
+    }
+
+    fn connected_faces(&self) -> Result<Vec<usize>> {
+        let mut roots: Vec<usize> = (0..self.vertex_count()).collect();
+        fn find(roots: &mut [usize], mut vertex: usize) -> usize {
+            while roots[vertex] != vertex {
+                roots[vertex] = roots[roots[vertex]];
+                vertex = roots[vertex];
+            }
+            vertex
+        }
+
+        let polygons = self.polygons()?;
+        for polygon in &polygons {
+            for pair in polygon.windows(2) {
+                let (a, b) = (find(&mut roots, pair[0]), find(&mut roots, pair[1]));
+                roots[a.max(b)] = a.min(b);
+            }
+        }
+        let mut parts = BTreeMap::new();
+        let mut face_parts = Vec::with_capacity(polygons.len());
+        for polygon in &polygons {
+            let root = polygon.first().map_or(usize::MAX, |&v| find(&mut roots, v));
+            let next = parts.len();
+            face_parts.push(*parts.entry(root).or_insert(next));
+        }
+
+        Ok(face_parts)
+    }
+}
+
+impl File {
+    /// Split a geometry node and append the parts as new nodes, returning their indices.
+    ///
+    /// Every part copies the meta of the node, with a `_0`, `_1`, ... suffix added to its name.
+    /// The `children` and `lod` references are not copied, the part gets the parent of the node
+    /// and the bounds and surface meta are recomputed for it. The node itself is left unchanged.
+    pub fn split_node(&mut self, node: usize, by: SplitBy) -> Result<Vec<usize>> {
+        let parts = self.unquantized_geometry(node)?.split(by)?;
+        let name = self.nodes[node].name().map(str::to_string);
+
+        let mut indices = Vec::with_capacity(parts.len());
+        for (index, part) in parts.into_iter().enumerate() {
+            let mut part = self.derived_node(node, part)?;
+            if let Some(name) = &name {
+                part.set_name(format!("{}_{}", name, index));
+            }
+            indices.push(self.nodes.len());
+            self.nodes.push(part);
+        }
+
+        Ok(indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle};
+    use crate::{Error, Layer, LayerData, Mat4, Node, NodeContent};
+
+    #[test]
+    fn split_by_material() {
+        let mut node = cube();
+        node.face_stack.push(Layer::new(
+            convention::LAYER_MATERIAL_ID,
+            1,
+            LayerData::Int32(vec![7, 3, 7, 3, 7, 3]),
+        ));
+        node.corner_stack.push(Layer::new(
+            "uv",
+            1,
+            LayerData::Float((0..24).map(|c| c as f32).collect()),
+        ));
+        assert_eq!(
+            node.face_parts(SplitBy::Material).unwrap(),
+            [1, 0, 1, 0, 1, 0]
+        );
+        let parts = node.split(SplitBy::Material).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        for (part, material) in parts.iter().zip([3.0, 7.0]) {
+            assert_eq!(part.face_count(), 3);
+            part.validate().unwrap();
+            let layer = part.face_stack.get(convention::LAYER_MATERIAL_ID).unwrap();
+            assert!((0..3).all(|face| layer.get(face, 0) == material));
+        }
+        // Corner values follow their faces.
+        assert_eq!(
+            parts[0].corner_stack.get("uv").unwrap().data,
+            LayerData::Float(
+                (4..8)
+                    .chain(12..16)
+                    .chain(20..24)
+                    .map(|c| c as f32)
+                    .collect()
+            )
+        );
+
+        assert!(matches!(
+            node.split(SplitBy::Group),
+            Err(Error::MissingLayer { .. })
+        ));
+    }
+
+    #[test]
+    fn short_part_layers_are_rejected() {
+        let mut node = quad_and_triangle();
+        node.face_stack.push(Layer::new(
+            convention::LAYER_GROUP_ID,
+            1,
+            LayerData::Int32(vec![1]),
+        ));
+        assert_eq!(
+            node.split(SplitBy::Group),
+            Err(Error::LayerLength {
+                name: convention::LAYER_GROUP_ID.to_string(),
+                expected: 2,
+                actual: 1
+            })
+        );
+    }
+
+    #[test]
+    fn split_by_connectivity() {
+        let mut node = cube();
+        let mut moved = quad_and_triangle();
+        moved
+            .apply_transform(&Mat4::from_translation([5.0, 0.0, 0.0]))
+            .unwrap();
+        node.append(&moved).unwrap();
+        node.append(&cube()).unwrap();
+
+        assert_eq!(
+            node.face_parts(SplitBy::Connectivity).unwrap(),
+            [0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 2, 2, 2, 2]
+        );
+        let parts = node.split(SplitBy::Connectivity).unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1], moved);
+        assert_eq!(parts[2], cube());
+    }
+
+    #[test]
+    fn split_node_copies_meta() {
+        let mut file = File::new();
+        let mut node: Node = cube().into();
+        node.set_name("crate");
+        node.set_transform(&Mat4::from_translation([1.0, 0.0, 0.0]));
+        file.nodes.push(node);
+        file.geometry_mut(0).unwrap().append(&cube()).unwrap();
+
+        let parts = file.split_node(0, SplitBy::Connectivity).unwrap();
+        assert_eq!(parts, vec![1, 2]);
+        assert_eq!(file.nodes[2].name(), Some("crate_1"));
+        assert_eq!(file.nodes[2].transform(), file.nodes[0].transform());
+        assert_eq!(file.nodes[0].geometry().unwrap().face_count(), 12);
+    }
+
+    #[test]
+    fn split_parts_keep_the_hierarchy_valid() {
+        let mut file = File::new();
+        file.nodes.push(Node::new(NodeContent::MetaOnly));
+        let mut node: Node = cube().into();
+        node.set_transform(&Mat4::from_translation([1.0, 0.0, 0.0]));
+        file.nodes.push(node);
+        file.nodes.push(Node::new(NodeContent::MetaOnly));
+        file.set_parent(1, Some(0)).unwrap();
+        file.set_parent(2, Some(1)).unwrap();
+        let mut moved = cube();
+        moved
+            .apply_transform(&Mat4::from_translation([5.0, 0.0, 0.0]))
+            .unwrap();
+        file.geometry_mut(1).unwrap().append(&moved).unwrap();
+        file.nodes[1].update_bounds().unwrap();
+
+        let parts = file.split_node(1, SplitBy::Connectivity).unwrap();
+        let scene = file.scene().unwrap();
+        assert_eq!(scene.children(1), [2]);
+        for &part in &parts {
+            assert_eq!(scene.parent(part), Some(0));
+            assert_eq!(scene.world_transform(part), scene.world_transform(1));
+            assert!(file.nodes[part].find_meta(convention::CHILDREN).is_none());
+        }
+        assert_eq!(file.nodes[1].stored_bounds().unwrap().max, [6.0, 1.0, 1.0]);
+        assert_eq!(
+            file.nodes[parts[0]].stored_bounds().unwrap().max,
+            [1.0, 1.0, 1.0]
+        );
+        assert_eq!(
+            file.nodes[parts[1]].stored_bounds().unwrap().min,
+            [5.0, 0.0, 0.0]
+        );
+    }
+}
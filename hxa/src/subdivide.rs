@@ -0,0 +1,525 @@
+//! Subdivision surfaces.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use crate::geometry::next_corner;
+use crate::{convention, encode_reference, Error, GeometryNode, Layer, LayerStack, Result};
+
+/// A subdivision scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubdivisionScheme {
+    /// Catmull-Clark, splitting every polygon into one quad per corner.
+    CatmullClark,
+    /// Loop, splitting every triangle into four. The node has to be triangulated.
+    Loop,
+}
+
+/// Vertices or corners of the source node and their weights.
+type Stencil = Vec<(usize, f64)>;
+
+impl GeometryNode {
+    /// Subdivide the node `levels` times.
+    ///
+    /// Vertex layers are smoothed with the same weights as the positions, corner layers are
+    /// interpolated linearly inside each face and new faces copy the face layers of the face they
+    /// are split from. Halves of split edges keep their edge layer values, edges inside a face get
+    /// zeros.
+    ///
+    /// Open borders are sharp. Edges with a `creases` value of 1 or more are sharp and lower
+    /// values blend between smooth and sharp. Split edges get a crease value lowered by 1, so a
+    /// crease of 2.5 stays sharp for two levels and then relaxes, like the semi-sharp creases of
+    /// OpenSubdiv.
+    pub fn subdivide(&mut self, scheme: SubdivisionScheme, levels: usize) -> Result<()> {
+        for _ in 0..levels {
+            let topology = Topology::new(self)?;
+            let split = match scheme {
+                SubdivisionScheme::CatmullClark => topology.catmull_clark(),
+                SubdivisionScheme::Loop => topology.loop_subdivision()?,
+            };
+            *self = split.build(self)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The edges of a node and the faces and edges around each vertex.
+struct Topology {
+    faces: Vec<Range<usize>>,
+    corner_vertices: Vec<usize>,
+    /// The edge starting at each corner.
+    corner_edge: Vec<usize>,
+    edges: Vec<[usize; 2]>,
+    edge_faces: Vec<Vec<usize>>,
+    /// Crease values, infinite for borders and edges with more than two faces.
+    edge_sharpness: Vec<f64>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+/// The faces of a subdivided node, with the stencils to build its layers from the source node.
+struct Split {
+    vertices: Vec<Stencil>,
+    polygons: Vec<Vec<u32>>,
+    source_faces: Vec<usize>,
+    corners: Vec<Stencil>,
+    /// The corner of the source node each new edge is split from.
+    edge_sources: Vec<Option<usize>>,
+}
+
+impl Topology {
+    fn new(node: &GeometryNode) -> Result<Self> {
+        let faces = node.faces()?;
+        let corner_vertices = node.corner_vertices()?;
+        let creases = node.edge_creases();
+        let vertex_count = node.vertex_count();
+
+        let mut edge_index = HashMap::new();
+        let mut edges = Vec::new();
+        let mut edge_faces: Vec<Vec<usize>> = Vec::new();
+        let mut edge_sharpness = Vec::new();
+        let mut corner_edge = vec![0; corner_vertices.len()];
+        let mut vertex_edges = vec![Vec::new(); vertex_count];
+        let mut vertex_faces = vec![Vec::new(); vertex_count];
+        for (face, range) in faces.iter().enumerate() {
+            for corner in range.clone() {
+                let a = corner_vertices[corner];
+                let b = corner_vertices[next_corner(range, corner)];
+                let edge = *edge_index.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    edges.push([a, b]);
+                    edge_faces.push(Vec::new());
+                    edge_sharpness.push(0.0);
+                    vertex_edges[a].push(edges.len() - 1);
+                    if b != a {
+                        vertex_edges[b].push(edges.len() - 1);
+                    }
+                    edges.len() - 1
+                });
+                corner_edge[corner] = edge;
+                edge_faces[edge].push(face);
+                edge_sharpness[edge] = f64::max(edge_sharpness[edge], creases[corner]);
+                if !vertex_faces[a].contains(&face) {
+                    vertex_faces[a].push(face);
+                }
+            }
+        }
+        for (sharpness, faces) in edge_sharpness.iter_mut().zip(&edge_faces) {
+            if faces.len() != 2 {
+                *sharpness = f64::INFINITY;
+            }
+        }
+
+        Ok(Self {
+            faces,
+            corner_vertices,
+            corner_edge,
+            edges,
+            edge_faces,
+            edge_sharpness,
+            vertex_edges,
+            vertex_faces,
+        })
+    }
+
+    fn other_vertex(&self, edge: usize, vertex: usize) -> usize {
+        let [a, b] = self.edges[edge];
+        if a == vertex {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// The average of the vertices of a face.
+    fn face_stencil(&self, face: usize) -> Stencil {
+        let range = self.faces[face].clone();
+        let weight = 1.0 / range.len() as f64;
+        range
+            .map(|corner| (self.corner_vertices[corner], weight))
+            .collect()
+    }
+
+    fn edge_stencil(&self, edge: usize, smooth: impl FnOnce() -> Stencil) -> Stencil {
+        let [a, b] = self.edges[edge];
+        let sharp = vec![(a, 0.5), (b, 0.5)];
+        match self.edge_sharpness[edge] {
+            s if s >= 1.0 => sharp,
+            s if s > 0.0 => blend(smooth(), sharp, s),
+            _ => smooth(),
+        }
+    }
+
+    /// Pick the smooth, crease or corner rule for a vertex from the sharp edges around it.
+    fn vertex_stencil(&self, vertex: usize, smooth: impl FnOnce() -> Stencil) -> Stencil {
+        let sharp: Vec<usize> = self.vertex_edges[vertex]
+            .iter()
+            .copied()
+            .filter(|&edge| self.edge_sharpness[edge] > 0.0)
+            .collect();
+        if self.vertex_edges[vertex].is_empty() {
+            return vec![(vertex, 1.0)];
+        }
+        if sharp.len() < 2 {
+            return smooth();
+        }
+
+        let rule = match sharp[..] {
+            [a, b] => vec![
+                (vertex, 0.75),
+                (self.other_vertex(a, vertex), 0.125),
+                (self.other_vertex(b, vertex), 0.125),
+            ],
+            _ => vec![(vertex, 1.0)],
+        };
+        let sharpness = sharp
+            .iter()
+            .map(|&edge| self.edge_sharpness[edge].min(1.0))
+            .sum::<f64>()
+            / sharp.len() as f64;
+        if sharpness >= 1.0 {
+            rule
+        } else {
+            blend(smooth(), rule, sharpness)
+        }
+    }
+
+    fn catmull_clark(&self) -> Split {
+        let vertex_count = self.vertex_edges.len();
+        let mut vertices = Vec::with_capacity(vertex_count + self.edges.len() + self.faces.len());
+        for vertex in 0..vertex_count {
+            vertices.push(self.vertex_stencil(vertex, || {
+                let n = self.vertex_edges[vertex].len() as f64;
+                let faces = &self.vertex_faces[vertex];
+                let mut stencil = vec![(vertex, (n - 3.0) / n)];
+                for &face in faces {
+                    let weight = 1.0 / (faces.len() as f64 * n);
+                    stencil.extend(scaled(self.face_stencil(face), weight));
+                }
+                for &edge in &self.vertex_edges[vertex] {
+                    stencil.push((vertex, 1.0 / (n * n)));
+                    stencil.push((self.other_vertex(edge, vertex), 1.0 / (n * n)));
+                }
+                stencil
+            }));
+        }
+        for edge in 0..self.edges.len() {
+            vertices.push(self.edge_stencil(edge, || {
+                let [a, b] = self.edges[edge];
+                let mut stencil = vec![(a, 0.25), (b, 0.25)];
+                for &face in &self.edge_faces[edge] {
+                    stencil.extend(scaled(self.face_stencil(face), 0.25));
+                }
+                stencil
+            }));
+        }
+        for face in 0..self.faces.len() {
+            vertices.push(self.face_stencil(face));
+        }
+
+        let edge_point = |corner: usize| (vertex_count + self.corner_edge[corner]) as u32;
+        let mut split = Split::new(vertices);
+        for (face, range) in self.faces.iter().enumerate() {
+            let face_point = (vertex_count + self.edges.len() + face) as u32;
+            let face_corners = scaled(
+                range.clone().map(|corner| (corner, 1.0)).collect(),
+                1.0 / range.len() as f64,
+            );
+            for corner in range.clone() {
+                let next = next_corner(range, corner);
+                let prev = if corner == range.start {
+                    range.end - 1
+                } else {
+                    corner - 1
+                };
+                split.polygons.push(vec![
+                    self.corner_vertices[corner] as u32,
+                    edge_point(corner),
+                    face_point,
+                    edge_point(prev),
+                ]);
+                split.source_faces.push(face);
+                split.corners.extend([
+                    vec![(corner, 1.0)],
+                    vec![(corner, 0.5), (next, 0.5)],
+                    face_corners.clone(),
+                    vec![(prev, 0.5), (corner, 0.5)],
+                ]);
+                split
+                    .edge_sources
+                    .extend([Some(corner), None, None, Some(prev)]);
+            }
+        }
+
+        split
+    }
+
+    fn loop_subdivision(&self) -> Result<Split> {
+        if let Some(face) = self.faces.iter().position(|range| range.len() != 3) {
+            return Err(Error::NotTriangulated { face });
+        }
+
+        let vertex_count = self.vertex_edges.len();
+        let mut vertices = Vec::with_capacity(vertex_count + self.edges.len());
+        for vertex in 0..vertex_count {
+            vertices.push(self.vertex_stencil(vertex, || {
+                let edges = &self.vertex_edges[vertex];
+                let n = edges.len() as f64;
+                let beta = (0.625 - (0.375 + 0.25 * (2.0 * PI / n).cos()).powi(2)) / n;
+                let mut stencil = vec![(vertex, 1.0 - n * beta)];
+                stencil.extend(
+                    edges
+                        .iter()
+                        .map(|&edge| (self.other_vertex(edge, vertex), beta)),
+                );
+                stencil
+            }));
+        }
+        for edge in 0..self.edges.len() {
+            vertices.push(self.edge_stencil(edge, || {
+                let [a, b] = self.edges[edge];
+                let mut stencil = vec![(a, 0.375), (b, 0.375)];
+                for &face in &self.edge_faces[edge] {
+                    let opposite = self.faces[face]
+                        .clone()
+                        .map(|corner| self.corner_vertices[corner])
+                        .find(|&v| v != a && v != b);
+                    stencil.extend(opposite.map(|v| (v, 0.125)));
+                }
+                stencil
+            }));
+        }
+
+        let mut split = Split::new(vertices);
+        for (face, range) in self.faces.iter().enumerate() {
+            let [c0, c1, c2] = [range.start, range.start + 1, range.start + 2];
+            let [a, b, c] = [c0, c1, c2].map(|corner| self.corner_vertices[corner] as u32);
+            let [ab, bc, ca] =
+                [c0, c1, c2].map(|corner| (vertex_count + self.corner_edge[corner]) as u32);
+            let mid = |x: usize, y: usize| vec![(x, 0.5), (y, 0.5)];
+            split.polygons.extend([
+                vec![a, ab, ca],
+                vec![ab, b, bc],
+                vec![ca, bc, c],
+                vec![ab, bc, ca],
+            ]);
+            split.source_faces.extend([face; 4]);
+            split.corners.extend([
+                vec![(c0, 1.0)],
+                mid(c0, c1),
+                mid(c2, c0),
+                mid(c0, c1),
+                vec![(c1, 1.0)],
+                mid(c1, c2),
+                mid(c2, c0),
+                mid(c1, c2),
+                vec![(c2, 1.0)],
+                mid(c0, c1),
+                mid(c1, c2),
+                mid(c2, c0),
+            ]);
+            split.edge_sources.extend([
+                Some(c0),
+                None,
+                Some(c2),
+                Some(c0),
+                Some(c1),
+                None,
+                None,
+                Some(c1),
+                Some(c2),
+                None,
+                None,
+                None,
+            ]);
+        }
+
+        Ok(split)
+    }
+}
+
+impl Split {
+    fn new(vertices: Vec<Stencil>) -> Self {
+        Self {
+            vertices,
+            polygons: Vec::new(),
+            source_faces: Vec::new(),
+            corners: Vec::new(),
+            edge_sources: Vec::new(),
+        }
+    }
+
+    fn build(&self, node: &GeometryNode) -> Result<GeometryNode> {
+        let mut vertex_stack = LayerStack::new();
+        for layer in &node.vertex_stack {
+            vertex_stack.push(layer.interpolate(&self.vertices));
+        }
+
+        let mut result = GeometryNode::new(
+            vertex_stack.layers.remove(0),
+            encode_reference(&self.polygons),
+        );
+        result.vertex_stack.layers.append(&mut vertex_stack.layers);
+        result.corner_stack.layers[0]
+            .name
+            .clone_from(&node.corner_stack.layers[0].name);
+        for layer in node.corner_stack.iter().skip(1) {
+            result.corner_stack.push(match layer.name.as_str() {
+                convention::LAYER_CREASES => {
+                    relax_creases(layer.gather_optional(&self.edge_sources))
+                }
+                _ => layer.interpolate(&self.corners),
+            });
+        }
+        for layer in &node.edge_stack {
+            result.edge_stack.push(match layer.name.as_str() {
+                convention::LAYER_CREASES => {
+                    relax_creases(layer.gather_optional(&self.edge_sources))
+                }
+                _ => layer.gather_optional(&self.edge_sources),
+            });
+        }
+        result.face_stack = node.face_stack.gather(&self.source_faces);
+        if result
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            result.generate_neighbours()?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Lower every crease value by one level of subdivision.
+fn relax_creases(mut layer: Layer) -> Layer {
+    for element in 0..layer.len() {
+        for component in 0..layer.components as usize {
+            let value = layer.get(element, component);
+            layer.set(element, component, (value - 1.0).max(0.0));
+        }
+    }
+    layer
+}
+
+fn scaled(stencil: Stencil, factor: f64) -> Stencil {
+    stencil
+        .into_iter()
+        .map(|(source, weight)| (source, weight * factor))
+        .collect()
+}
+
+/// Mix two stencils, `t` of 0 giving `a` and 1 giving `b`.
+fn blend(a: Stencil, b: Stencil, t: f64) -> Stencil {
+    let mut stencil = scaled(a, 1.0 - t);
+    stencil.extend(scaled(b, t));
+    stencil
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, triangle_grid};
+    use crate::LayerData;
+
+    fn assert_near(a: [f64; 3], b: [f64; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    fn cube_with_creases(value: f32) -> GeometryNode {
+        let mut node = cube();
+        node.edge_stack.push(Layer::new(
+            convention::LAYER_CREASES,
+            1,
+            LayerData::Float(vec![value; node.corner_count()]),
+        ));
+        node
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let mut node = cube();
+        node.face_stack.push(Layer::new(
+            "material",
+            1,
+            LayerData::Int32(vec![0, 1, 2, 3, 4, 5]),
+        ));
+        node.subdivide(SubdivisionScheme::CatmullClark, 1).unwrap();
+
+        assert_eq!(node.vertex_count(), 26);
+        assert_eq!(node.face_count(), 24);
+        let positions = node.positions().unwrap();
+        assert_near(positions[0], [2.0 / 9.0; 3]);
+        assert_near(positions[6], [7.0 / 9.0; 3]);
+        // Every quad keeps the material of its face and touches the face point last.
+        let material = node.face_stack.get("material").unwrap();
+        for (face, polygon) in node.polygons().unwrap().into_iter().enumerate() {
+            assert_eq!(material.get(face, 0) as usize, face / 4);
+            assert_eq!(polygon[2], 20 + face / 4);
+        }
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn catmull_clark_creases() {
+        // Sharp creases keep the cube corners and edges in place for as many levels as their
+        // value, half sharp creases blend between smooth and sharp.
+        let mut node = cube_with_creases(2.0);
+        node.subdivide(SubdivisionScheme::CatmullClark, 1).unwrap();
+        let positions = node.positions().unwrap();
+        assert_near(positions[0], [0.0; 3]);
+        assert_near(positions[8], [0.0, 0.5, 0.0]);
+        let creases = node.edge_creases();
+        assert_eq!(creases.iter().filter(|&&c| c == 1.0).count(), 48);
+        assert_eq!(creases.iter().filter(|&&c| c == 0.0).count(), 48);
+
+        node.subdivide(SubdivisionScheme::CatmullClark, 1).unwrap();
+        assert_near(node.positions().unwrap()[0], [0.0; 3]);
+        assert!(node.edge_creases().iter().all(|&c| c == 0.0));
+
+        let mut node = cube_with_creases(0.5);
+        node.subdivide(SubdivisionScheme::CatmullClark, 1).unwrap();
+        assert_near(node.positions().unwrap()[0], [1.0 / 9.0; 3]);
+    }
+
+    #[test]
+    fn loop_flat_grid() {
+        let mut node = triangle_grid(2);
+        let uv: Vec<f32> = node
+            .corner_vertices()
+            .unwrap()
+            .into_iter()
+            .flat_map(|vertex| [vertex as f32, 0.0])
+            .collect();
+        node.corner_stack
+            .push(Layer::new("uv", 2, LayerData::Float(uv)));
+        let vertices = node.vertex_count();
+        let original = node.corner_vertices().unwrap();
+        node.subdivide(SubdivisionScheme::Loop, 1).unwrap();
+
+        assert_eq!(node.face_count(), 32);
+        assert_eq!(node.vertex_count(), vertices + 16);
+        let positions = node.positions().unwrap();
+        assert!(positions.iter().all(|p| p[2] == 0.0));
+        // The regular center vertex stays in place, border vertices slide along the border.
+        assert_near(positions[4], [1.0, 1.0, 0.0]);
+        assert_near(positions[1], [1.0, 0.0, 0.0]);
+        // The first corner of the middle triangle lies halfway along the first edge.
+        let uv = node.corner_stack.get("uv").unwrap();
+        assert_eq!(uv.get(9, 0), (original[0] + original[1]) as f64 / 2.0);
+        node.validate().unwrap();
+
+        let mut quads = cube();
+        assert!(matches!(
+            quads.subdivide(SubdivisionScheme::Loop, 1),
+            Err(Error::NotTriangulated { face: 0 })
+        ));
+    }
+}
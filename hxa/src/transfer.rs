@@ -0,0 +1,315 @@
+//! Copying layers between geometry nodes with different topology.
+//!
+//! Every vertex of the target node is matched with the closest point on the surface of the
+//! source node, and the source values are interpolated there with the barycentric weights of
+//! the point. This carries UVs, colors and skinning weights over to a retopologized mesh.
+
+use crate::bake::{Surface, SurfaceHit};
+use crate::{convention, Error, GeometryNode, HXALayerDataType, Layer, LayerData, Result, Skin};
+
+impl GeometryNode {
+    /// Copy vertex and corner layers from a source node onto this node.
+    ///
+    /// Each layer keeps its type and component count, and lands in the same stack it has in the
+    /// source, replacing a layer of the same name in either stack of this node. Float layers are
+    /// interpolated, and so are uint8 layers, which hold normalized values like colors, rounding
+    /// the result. Int32 layers, like ids, take the value of the closest corner of the source
+    /// triangle, as in [`Layer::interpolate`]. Corner layers are sampled at the vertex of each
+    /// corner, so seams in the source are not reproduced.
+    ///
+    /// Naming either skinning layer transfers the whole skin: the influences of the source
+    /// vertices are blended, the largest ones kept and their weights normalized, keeping the type
+    /// of the source weights.
+    ///
+    /// The vertex and reference layers define the topology and are never copied. Fails with
+    /// [`Error::MissingLayer`] if the source has no other vertex or corner layer of a name, and
+    /// with [`Error::EmptySurface`] if it has no triangles.
+    ///
+    /// Closest points are found with a bounding volume hierarchy with the `bvh` feature, and by
+    /// testing every source triangle without it.
+    pub fn transfer_layers(&mut self, source: &GeometryNode, names: &[&str]) -> Result<()> {
+        let surface = Surface::for_queries(source)?;
+        if surface.corners.is_empty() {
+            return Err(Error::EmptySurface);
+        }
+        let hits: Vec<Option<SurfaceHit>> = self
+            .positions()?
+            .into_iter()
+            .map(|position| surface.closest_point(position))
+            .collect();
+        let source_vertices = source.corner_vertices()?;
+        let weights = |hit: &Option<SurfaceHit>, elements: &dyn Fn(usize) -> usize| {
+            hit.iter()
+                .flat_map(|hit| {
+                    surface.corners[hit.triangle]
+                        .into_iter()
+                        .zip(hit.barycentric)
+                })
+                .map(|(corner, weight)| (elements(corner), weight))
+                .collect::<Vec<_>>()
+        };
+        let vertex_stencils: Vec<Vec<(usize, f64)>> = hits
+            .iter()
+            .map(|hit| weights(hit, &|corner| source_vertices[corner]))
+            .collect();
+
+        let mut skinned = false;
+        for &name in names {
+            if name == convention::LAYER_SKIN_REFERENCE || name == convention::LAYER_SKIN_WEIGHT {
+                if !skinned {
+                    let weight_type = source
+                        .vertex_stack
+                        .find(convention::LAYER_SKIN_WEIGHT)
+                        .map_or(HXALayerDataType::HXA_LDT_FLOAT, Layer::data_type);
+                    self.set_skin_as(&blend_skin(&source.skin()?, &vertex_stencils), weight_type)?;
+                    skinned = true;
+                }
+            } else if let Some(layer) = source.vertex_stack.layers[1..]
+                .iter()
+                .find(|layer| layer.name == name)
+            {
+                self.corner_stack.remove(name);
+                self.vertex_stack
+                    .insert(transfer_layer(layer, &vertex_stencils));
+            } else if let Some(layer) = source.corner_stack.layers[1..]
+                .iter()
+                .find(|layer| layer.name == name)
+            {
+                let stencils: Vec<Vec<(usize, f64)>> = self
+                    .corner_vertices()?
+                    .into_iter()
+                    .map(|vertex| weights(&hits[vertex], &|corner| corner))
+                    .collect();
+                self.vertex_stack.remove(name);
+                self.corner_stack.insert(transfer_layer(layer, &stencils));
+            } else {
+                return Err(Error::MissingLayer {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Interpolate a layer with stencils, blending uint8 values and rounding the result.
+fn transfer_layer(layer: &Layer, stencils: &[Vec<(usize, f64)>]) -> Layer {
+    match layer.data {
+        LayerData::Uint8(_) => layer
+            .convert_to(HXALayerDataType::HXA_LDT_DOUBLE)
+            .interpolate(stencils)
+            .convert_to(HXALayerDataType::HXA_LDT_UINT8),
+        _ => layer.interpolate(stencils),
+    }
+}
+
+/// A skin where each vertex blends the influences of source vertices with the weights of a
+/// stencil.
+fn blend_skin(source: &Skin, stencils: &[Vec<(usize, f64)>]) -> Skin {
+    let mut skin = Skin::new(source.influences, stencils.len());
+    for (vertex, stencil) in stencils.iter().enumerate() {
+        let mut blended: Vec<(usize, f32)> = Vec::new();
+        for &(element, weight) in stencil {
+            for (bone, influence) in source.vertex(element) {
+                let influence = influence * weight as f32;
+                match blended.iter_mut().find(|(b, _)| *b == bone) {
+                    Some((_, total)) => *total += influence,
+                    None => blended.push((bone, influence)),
+                }
+            }
+        }
+        blended.retain(|&(_, weight)| weight > 0.0);
+        blended.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let start = vertex * skin.influences;
+        for (slot, (bone, weight)) in (start..).zip(blended.into_iter().take(skin.influences)) {
+            skin.bones[slot] = bone as i32;
+            skin.weights[slot] = weight;
+        }
+    }
+    skin.normalize();
+    skin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{quad_and_triangle, triangle_grid};
+
+    /// The grid with a color following the position, a corner UV and two bones blended along X.
+    fn source() -> GeometryNode {
+        let mut node = triangle_grid(4);
+        let positions = node.positions().unwrap();
+        let colors = positions
+            .iter()
+            .flat_map(|&[x, y, _]| [x / 4.0, y / 4.0, 1.0]);
+        node.vertex_stack.push(Layer::new(
+            convention::LAYER_COLOR,
+            3,
+            LayerData::Double(colors.collect()),
+        ));
+        let uvs = node
+            .corner_vertices()
+            .unwrap()
+            .into_iter()
+            .flat_map(|vertex| {
+                let [x, y, _] = positions[vertex];
+                [x as f32 / 4.0, y as f32 / 4.0]
+            });
+        node.corner_stack
+            .push(Layer::new("uv", 2, LayerData::Float(uvs.collect())));
+
+        let mut skin = Skin::new(2, positions.len());
+        for (vertex, [x, _, _]) in positions.iter().enumerate() {
+            skin.bones[vertex * 2..][..2].copy_from_slice(&[3, 4]);
+            skin.weights[vertex * 2..][..2]
+                .copy_from_slice(&[*x as f32 / 4.0, 1.0 - *x as f32 / 4.0]);
+        }
+        node.set_skin(&skin).unwrap();
+        node
+    }
+
+    #[test]
+    fn layers_are_interpolated_at_closest_points() {
+        let source = source();
+        let mut target = quad_and_triangle();
+        // Lift the target off the source, and give it a stale UV layer per vertex.
+        let lifted: Vec<[f64; 3]> = target
+            .positions()
+            .unwrap()
+            .iter()
+            .map(|&[x, y, _]| [x * 1.25 + 0.5, y * 1.5 + 0.25, 0.5])
+            .collect();
+        target.set_positions(&lifted).unwrap();
+        target
+            .vertex_stack
+            .push(Layer::new("uv", 2, LayerData::Float(vec![0.0; 10])));
+
+        target
+            .transfer_layers(
+                &source,
+                &[convention::LAYER_COLOR, "uv", convention::LAYER_SKIN_WEIGHT],
+            )
+            .unwrap();
+
+        let colors = target.vertex_stack.get(convention::LAYER_COLOR).unwrap();
+        assert_eq!(
+            colors.data_type(),
+            source.vertex_stack.layers[1].data_type()
+        );
+        for (vertex, [x, y, _]) in lifted.iter().enumerate() {
+            assert!((colors.get(vertex, 0) - x / 4.0).abs() < 1e-12);
+            assert!((colors.get(vertex, 1) - y / 4.0).abs() < 1e-12);
+            assert!((colors.get(vertex, 2) - 1.0).abs() < 1e-12);
+        }
+
+        assert!(target.vertex_stack.find("uv").is_none());
+        let uvs = target.corner_stack.get("uv").unwrap();
+        assert_eq!(uvs.len(), target.corner_count());
+        let corner_vertices = target.corner_vertices().unwrap();
+        for (corner, vertex) in corner_vertices.into_iter().enumerate() {
+            assert!((uvs.get(corner, 0) - lifted[vertex][0] / 4.0).abs() < 1e-6);
+        }
+
+        let skin = target.skin().unwrap();
+        for (vertex, [x, _, _]) in lifted.iter().enumerate() {
+            let mut influences: Vec<(usize, f32)> = skin.vertex(vertex).collect();
+            influences.sort_by_key(|&(bone, _)| bone);
+            assert_eq!(influences[0].0, 3);
+            assert!((influences[0].1 as f64 - x / 4.0).abs() < 1e-6);
+            assert!((influences[1].1 as f64 - (1.0 - x / 4.0)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn uint8_layers_are_blended() {
+        let mut source = source();
+        let positions = source.positions().unwrap();
+        let colors = positions
+            .iter()
+            .flat_map(|&[x, _, _]| [(x * 50.0) as u8, 255, 0]);
+        source.vertex_stack.insert(Layer::new(
+            convention::LAYER_COLOR,
+            3,
+            LayerData::Uint8(colors.collect()),
+        ));
+        source.corner_stack.push(Layer::new(
+            convention::LAYER_MATERIAL_ID,
+            1,
+            LayerData::Int32(vec![7; source.corner_count()]),
+        ));
+        let skin = source.skin().unwrap();
+        source.vertex_stack.insert(Layer::new(
+            convention::LAYER_SKIN_WEIGHT,
+            2,
+            LayerData::Uint8(Vec::new()),
+        ));
+        source.set_skin(&skin).unwrap();
+        let mut target = quad_and_triangle();
+        let shifted: Vec<[f64; 3]> = target
+            .positions()
+            .unwrap()
+            .iter()
+            .map(|&[x, y, _]| [x + 0.3, y + 0.5, 0.0])
+            .collect();
+        target.set_positions(&shifted).unwrap();
+
+        target
+            .transfer_layers(
+                &source,
+                &[
+                    convention::LAYER_COLOR,
+                    convention::LAYER_MATERIAL_ID,
+                    convention::LAYER_SKIN_WEIGHT,
+                ],
+            )
+            .unwrap();
+        let colors = target.vertex_stack.get(convention::LAYER_COLOR).unwrap();
+        assert_eq!(colors.data_type(), HXALayerDataType::HXA_LDT_UINT8);
+        for (vertex, [x, _, _]) in shifted.iter().enumerate() {
+            assert_eq!(colors.get(vertex, 0), (x * 50.0).round());
+            assert_eq!(colors.get(vertex, 1), 255.0);
+        }
+        let materials = target
+            .corner_stack
+            .get(convention::LAYER_MATERIAL_ID)
+            .unwrap();
+        assert_eq!(
+            materials.data,
+            LayerData::Int32(vec![7; target.corner_count()])
+        );
+        let weights = target
+            .vertex_stack
+            .get(convention::LAYER_SKIN_WEIGHT)
+            .unwrap();
+        assert_eq!(weights.data_type(), HXALayerDataType::HXA_LDT_UINT8);
+        let skin = target.skin().unwrap();
+        for (vertex, [x, _, _]) in shifted.iter().enumerate() {
+            let weight = skin.vertex(vertex).find(|&(bone, _)| bone == 3).unwrap().1;
+            assert!((weight as f64 - x / 4.0).abs() < 1.0 / 255.0);
+        }
+    }
+
+    #[test]
+    fn topology_layers_are_not_transferred() {
+        let source = source();
+        let mut target = quad_and_triangle();
+        assert_eq!(
+            target.transfer_layers(&source, &[convention::BASE_VERTEX_LAYER_NAME]),
+            Err(Error::MissingLayer {
+                name: convention::BASE_VERTEX_LAYER_NAME.to_string()
+            })
+        );
+        assert!(target.transfer_layers(&source, &["missing"]).is_err());
+    }
+
+    #[test]
+    fn sources_without_triangles_are_rejected() {
+        let source = source().rebuild_faces(&[]).unwrap();
+        let mut target = quad_and_triangle();
+        assert_eq!(
+            target.transfer_layers(&source, &[convention::LAYER_COLOR]),
+            Err(Error::EmptySurface)
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! Affine transforms and the `transform` meta convention.
+
+use std::ops::Mul;
+
+use crate::math::{cross, dot, normalize, Vec3};
+use crate::{convention, GeometryNode, LayerData, Meta, MetaValue, Node, Result};
+
+/// A 4x4 matrix stored in column major order, as in the `transform` meta.
+///
+/// Points are transformed as columns, so the translation is stored in values 12, 13 and 14.
+/// Only affine transforms are supported, the bottom row is expected to be `0, 0, 0, 1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4(pub [f64; 16]);
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4([
+        1.0, 0.0, 0.0, 0.0, //
+        0.0, 1.0, 0.0, 0.0, //
+        0.0, 0.0, 1.0, 0.0, //
+        0.0, 0.0, 0.0, 1.0,
+    ]);
+
+    pub fn from_translation([x, y, z]: [f64; 3]) -> Self {
+        let mut matrix = Self::IDENTITY;
+        matrix.0[12..15].copy_from_slice(&[x, y, z]);
+        matrix
+    }
+
+    pub fn from_scale([x, y, z]: [f64; 3]) -> Self {
+        let mut matrix = Self::IDENTITY;
+        matrix.0[0] = x;
+        matrix.0[5] = y;
+        matrix.0[10] = z;
+        matrix
+    }
+
+    /// A counter clockwise rotation of `angle` radians around `axis`.
+    pub fn from_axis_angle(axis: [f64; 3], angle: f64) -> Self {
+        let [x, y, z] = normalize(axis);
+        let (s, c) = angle.sin_cos();
+        let t = 1.0 - c;
+        Self([
+            t * x * x + c,
+            t * x * y + s * z,
+            t * x * z - s * y,
+            0.0,
+            t * x * y - s * z,
+            t * y * y + c,
+            t * y * z + s * x,
+            0.0,
+            t * x * z + s * y,
+            t * y * z - s * x,
+            t * z * z + c,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ])
+    }
+
+    /// The value at a row and column.
+    pub fn get(&self, row: usize, column: usize) -> f64 {
+        self.0[column * 4 + row]
+    }
+
+    pub fn transform_point(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let m = &self.0;
+        [
+            m[0] * x + m[4] * y + m[8] * z + m[12],
+            m[1] * x + m[5] * y + m[9] * z + m[13],
+            m[2] * x + m[6] * y + m[10] * z + m[14],
+        ]
+    }
+
+    /// Transform a direction, ignoring the translation.
+    pub fn transform_vector(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let m = &self.0;
+        [
+            m[0] * x + m[4] * y + m[8] * z,
+            m[1] * x + m[5] * y + m[9] * z,
+            m[2] * x + m[6] * y + m[10] * z,
+        ]
+    }
+
+    /// The determinant of the linear part. Negative if the transform mirrors.
+    pub fn determinant(&self) -> f64 {
+        let [x, y, z] = self.columns();
+        dot(x, cross(y, z))
+    }
+
+    /// The inverse transform, or `None` if the matrix can not be inverted.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let determinant = self.determinant();
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+        // The rows of the inverse of the linear part are the cross products of its columns.
+        let [x, y, z] = self.columns();
+        let rows = [cross(y, z), cross(z, x), cross(x, y)];
+        let mut inverse = Self::IDENTITY;
+        for (row, values) in rows.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                inverse.0[column * 4 + row] = value / determinant;
+            }
+        }
+        let [tx, ty, tz] = inverse.transform_vector([self.0[12], self.0[13], self.0[14]]);
+        inverse.0[12..15].copy_from_slice(&[-tx, -ty, -tz]);
+
+        Some(inverse)
+    }
+
+    /// Transform a normal with the inverse transpose of the linear part.
+    ///
+    /// The result is not normalized. Uses the cofactor matrix, which is the inverse transpose
+    /// scaled by the determinant, so it also works for transforms that flatten an axis.
+    fn transform_normal(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let [a, b, c] = self.columns();
+        let [ca, cb, cc] = [cross(b, c), cross(c, a), cross(a, b)];
+        let sign = self.determinant().signum();
+        [
+            (ca[0] * x + cb[0] * y + cc[0] * z) * sign,
+            (ca[1] * x + cb[1] * y + cc[1] * z) * sign,
+            (ca[2] * x + cb[2] * y + cc[2] * z) * sign,
+        ]
+    }
+
+    fn columns(&self) -> [Vec3; 3] {
+        let m = &self.0;
+        [[m[0], m[1], m[2]], [m[4], m[5], m[6]], [m[8], m[9], m[10]]]
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    /// Combine two transforms, applying `rhs` first.
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut result = [0.0; 16];
+        for column in 0..4 {
+            for row in 0..4 {
+                result[column * 4 + row] =
+                    (0..4).map(|k| self.get(row, k) * rhs.get(k, column)).sum();
+            }
+        }
+        Mat4(result)
+    }
+}
+
+impl Node {
+    /// The matrix stored in the `transform` meta, if it holds 16 doubles.
+    pub fn transform(&self) -> Option<Mat4> {
+        let values = self.find_meta(convention::TRANSFORM)?.as_double()?;
+        Some(Mat4(values.try_into().ok()?))
+    }
+
+    pub fn set_transform(&mut self, transform: &Mat4) {
+        self.set_meta(Meta::new(
+            convention::TRANSFORM,
+            MetaValue::Double(transform.0.to_vec()),
+        ));
+    }
+}
+
+impl GeometryNode {
+    /// Transform the vertex positions and the `normal`, `tangent` and `binormal` layers.
+    ///
+    /// Normals use the inverse transpose, so they stay perpendicular to the surface under non
+    /// uniform scaling. Directions are normalized again. If the transform mirrors, the winding
+    /// is flipped to keep faces pointing out, and the handedness in the fourth component of a
+    /// `tangent` layer is negated.
+    pub fn apply_transform(&mut self, matrix: &Mat4) -> Result<()> {
+        let positions: Vec<[f64; 3]> = self
+            .positions()?
+            .into_iter()
+            .map(|position| matrix.transform_point(position))
+            .collect();
+        self.set_positions(&positions)?;
+
+        let mirror = matrix.determinant() < 0.0;
+        let stacks = [
+            &mut self.vertex_stack,
+            &mut self.corner_stack,
+            &mut self.face_stack,
+        ];
+        for layer in stacks.into_iter().flat_map(|stack| stack.iter_mut()) {
+            let transform: fn(&Mat4, Vec3) -> Vec3 = match layer.name.as_str() {
+                convention::LAYER_NORMALS => Mat4::transform_normal,
+                convention::LAYER_TANGENT | convention::LAYER_BINORMAL => Mat4::transform_vector,
+                _ => continue,
+            };
+            if layer.components < 3
+                || matches!(layer.data, LayerData::Uint8(_) | LayerData::Int32(_))
+            {
+                continue;
+            }
+            for element in 0..layer.len() {
+                let direction = [0, 1, 2].map(|component| layer.get(element, component));
+                let direction = normalize(transform(matrix, direction));
+                for (component, value) in direction.into_iter().enumerate() {
+                    layer.set(element, component, value);
+                }
+                if mirror && layer.components == 4 && layer.name == convention::LAYER_TANGENT {
+                    layer.set(element, 3, -layer.get(element, 3));
+                }
+            }
+        }
+        if mirror {
+            self.flip_winding()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reverse the order of the corners of every face, keeping the first corner in place.
+    ///
+    /// Corner and edge values move with their corners and edges. Normal layers are not changed.
+    pub fn flip_winding(&mut self) -> Result<()> {
+        let mut faces = Vec::new();
+        let mut edge_sources = Vec::with_capacity(self.corner_count());
+        for (face, range) in self.faces()?.into_iter().enumerate() {
+            let mut corners: Vec<usize> = range.rev().collect();
+            corners.rotate_right(1);
+            // The edge from a corner to the next one was stored on the next corner.
+            edge_sources.extend(corners[1..].iter().chain(&corners[..1]));
+            faces.push((face, corners));
+        }
+        let edge_stack = self.edge_stack.gather(&edge_sources);
+        *self = self.rebuild_faces(&faces)?;
+        self.edge_stack = edge_stack;
+        if self
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            self.generate_neighbours()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle};
+    use crate::math::{polygon_normal, sub};
+    use crate::Layer;
+
+    fn assert_near(a: [f64; 3], b: [f64; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn matrix_inverse_and_product() {
+        let matrix = Mat4::from_translation([1.0, 2.0, 3.0])
+            * Mat4::from_axis_angle([0.0, 0.0, 1.0], std::f64::consts::FRAC_PI_2)
+            * Mat4::from_scale([2.0, 2.0, 2.0]);
+        assert_near(matrix.transform_point([1.0, 0.0, 0.0]), [1.0, 4.0, 3.0]);
+        assert_eq!(matrix.determinant().round(), 8.0);
+
+        let inverse = matrix.inverse().unwrap();
+        assert_near(inverse.transform_point([1.0, 4.0, 3.0]), [1.0, 0.0, 0.0]);
+        assert_eq!(Mat4::from_scale([1.0, 0.0, 1.0]).inverse(), None);
+    }
+
+    #[test]
+    fn transform_meta_round_trip() {
+        let mut node = Node::new(crate::NodeContent::MetaOnly);
+        assert_eq!(node.transform(), None);
+        let matrix = Mat4::from_translation([1.0, 2.0, 3.0]);
+        node.set_transform(&matrix);
+
+        assert_eq!(node.transform(), Some(matrix));
+        assert_eq!(
+            node.find_meta(convention::TRANSFORM)
+                .unwrap()
+                .as_double()
+                .unwrap()[12..15],
+            [1.0, 2.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn normals_use_inverse_transpose() {
+        // A quad on the plane x + y = 1, squashed along x.
+        let mut node = GeometryNode::from_polygons(
+            &[
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 1.0, 1.0],
+                [1.0, 0.0, 1.0],
+            ],
+            &[[0u32, 1, 2, 3]],
+        );
+        let normal = normalize([1.0, 1.0, 0.0]);
+        node.vertex_stack.push(Layer::new(
+            convention::LAYER_NORMALS,
+            3,
+            LayerData::Float(normal.map(|v| v as f32).repeat(4)),
+        ));
+        node.apply_transform(&Mat4::from_scale([0.5, 1.0, 1.0]))
+            .unwrap();
+
+        let positions = node.positions().unwrap();
+        let normals = node.vertex_stack.get(convention::LAYER_NORMALS).unwrap();
+        let normal = [0, 1, 2].map(|c| normals.get(0, c));
+        let edge = sub(positions[1], positions[0]);
+        assert!(dot(normal, edge).abs() < 1e-6);
+        assert_near(normal, normalize([2.0, 1.0, 0.0]).map(|v| v as f32 as f64));
+    }
+
+    #[test]
+    fn mirror_flips_winding() {
+        let mut node = quad_and_triangle();
+        node.corner_stack.push(Layer::new(
+            "uv",
+            1,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        ));
+        node.edge_stack.push(Layer::new(
+            convention::LAYER_CREASES,
+            1,
+            LayerData::Float(vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+        ));
+        node.corner_stack.push(Layer::new(
+            convention::LAYER_TANGENT,
+            4,
+            LayerData::Float([1.0, 0.0, 0.0, 1.0].repeat(7)),
+        ));
+        node.face_stack.push(Layer::new(
+            convention::LAYER_NORMALS,
+            3,
+            LayerData::Double([0.0, 0.0, 1.0].repeat(2)),
+        ));
+        node.apply_transform(&Mat4::from_scale([-1.0, 1.0, 1.0]))
+            .unwrap();
+
+        // The faces still face +z after mirroring along x.
+        let positions = node.positions().unwrap();
+        for polygon in node.polygons().unwrap() {
+            let points: Vec<_> = polygon.iter().map(|&v| positions[v]).collect();
+            assert!(polygon_normal(&points)[2] > 0.0);
+        }
+        assert_eq!(node.reference().unwrap(), &[0, 3, 2, -2, 1, 2, -5]);
+        assert_eq!(
+            node.corner_stack.get("uv").unwrap().data,
+            LayerData::Float(vec![0.0, 3.0, 2.0, 1.0, 4.0, 6.0, 5.0])
+        );
+        // The crease on the edge from vertex 0 to 1 is now on the edge from 1 to 0.
+        assert_eq!(node.edge_creases()[3], 1.0);
+        let tangent = node.corner_stack.get(convention::LAYER_TANGENT).unwrap();
+        assert_eq!(
+            [0, 1, 2, 3].map(|c| tangent.get(0, c)),
+            [-1.0, 0.0, 0.0, -1.0]
+        );
+        let normals = node.face_stack.get(convention::LAYER_NORMALS).unwrap();
+        assert_eq!(normals.data, LayerData::Double([0.0, 0.0, 1.0].repeat(2)));
+        node.validate().unwrap();
+
+        let mut closed = cube();
+        closed
+            .apply_transform(&Mat4::from_scale([1.0, -1.0, 1.0]))
+            .unwrap();
+        assert!(closed.is_closed().unwrap());
+        assert!(closed.volume().unwrap().unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::math::{polygon_normal, Vec3};
+use crate::{convention, Error, GeometryNode, Result};
+
+impl GeometryNode {
+    /// Check if every polygon is a triangle.
+    pub fn is_triangulated(&self) -> Result<bool> {
+        Ok(self.faces()?.iter().all(|face| face.len() == 3))
+    }
+
+    /// The vertices of every triangle, failing with [`Error::NotTriangulated`] if a face is not a
+    /// triangle.
+    pub fn triangles(&self) -> Result<Vec<[usize; 3]>> {
+        self.polygons()?
+            .into_iter()
+            .enumerate()
+            .map(|(face, polygon)| match polygon[..] {
+                [a, b, c] => Ok([a, b, c]),
+                _ => Err(Error::NotTriangulated { face }),
+            })
+            .collect()
+    }
+
+    /// The face and the corners of every triangle the polygons split into, in the same way as
+    /// [`triangulate`](Self::triangulate).
+    pub(crate) fn corner_triangles(&self) -> Result<Vec<(usize, [usize; 3])>> {
+        let positions = self.positions()?;
+        let corner_vertices = self.corner_vertices()?;
+
+        let mut triangles = Vec::new();
+        for (face, range) in self.faces()?.into_iter().enumerate() {
+            let points: Vec<Vec3> = range
+                .clone()
+                .map(|corner| positions[corner_vertices[corner]])
+                .collect();
+            for [a, b, c] in triangulate_polygon(&points) {
+                triangles.push((face, [range.start + a, range.start + b, range.start + c]));
+            }
+        }
+
+        Ok(triangles)
+    }
+
+    /// Split every polygon with more than 3 corners into triangles.
+    ///
+    /// Polygons are split by ear clipping, so concave polygons are handled as long as they do not
+    /// intersect themselves. New triangles keep the winding of their polygon, copy the corner and
+    /// edge values of the corners they use and the face values of their polygon. Edges added
+    /// inside a polygon get zeros in the edge layers. Polygons with less than 3 corners are
+    /// removed.
+    pub fn triangulate(&mut self) -> Result<()> {
+        let positions = self.positions()?;
+        let corner_vertices = self.corner_vertices()?;
+
+        let mut triangles = Vec::new();
+        let mut edge_sources = Vec::new();
+        for (face, range) in self.faces()?.into_iter().enumerate() {
+            let points: Vec<Vec3> = range
+                .clone()
+                .map(|corner| positions[corner_vertices[corner]])
+                .collect();
+            for [a, b, c] in triangulate_polygon(&points) {
+                triangles.push((
+                    face,
+                    vec![range.start + a, range.start + b, range.start + c],
+                ));
+                for (from, to) in [(a, b), (b, c), (c, a)] {
+                    edge_sources
+                        .push(((from + 1) % points.len() == to).then_some(range.start + from));
+                }
+            }
+        }
+        let edge_stack = self.edge_stack.gather_optional(&edge_sources);
+        *self = self.rebuild_faces(&triangles)?;
+        self.edge_stack = edge_stack;
+        if self
+            .edge_stack
+            .find(convention::EDGE_NEIGHBOUR_LAYER_NAME)
+            .is_some()
+        {
+            self.generate_neighbours()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Split a polygon into triangles, returned as indices into `points`.
+pub(crate) fn triangulate_polygon(points: &[Vec3]) -> Vec<[usize; 3]> {
+    match points.len() {
+        0..=2 => return Vec::new(),
+        3 => return vec![[0, 1, 2]],
+        _ => {}
+    }
+
+    let normal = polygon_normal(points);
+    let axis = (0..3)
+        .max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs()))
+        .unwrap_or(2);
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let sign = normal[axis].signum();
+    let point = |i: usize| [points[i][u], points[i][v]];
+
+    let mut ring: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while ring.len() > 3 {
+        let ear = (0..ring.len()).find(|&i| {
+            let prev = ring[(i + ring.len() - 1) % ring.len()];
+            let current = ring[i];
+            let next = ring[(i + 1) % ring.len()];
+            let (a, b, c) = (point(prev), point(current), point(next));
+            if cross2(a, b, c) * sign <= 0.0 {
+                return false;
+            }
+            ring.iter()
+                .filter(|&&other| other != prev && other != current && other != next)
+                .all(|&other| {
+                    let p = point(other);
+                    !(p != a && p != b && p != c && in_triangle(a, b, c, p, sign))
+                })
+        });
+        // Degenerate polygons have no ear, clip the first corner so the loop always progresses.
+        let i = ear.unwrap_or(1);
+        let prev = ring[(i + ring.len() - 1) % ring.len()];
+        let next = ring[(i + 1) % ring.len()];
+        triangles.push([prev, ring[i], next]);
+        ring.remove(i);
+    }
+    triangles.push([ring[0], ring[1], ring[2]]);
+
+    triangles
+}
+
+fn cross2(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn in_triangle(a: [f64; 2], b: [f64; 2], c: [f64; 2], p: [f64; 2], sign: f64) -> bool {
+    cross2(a, b, p) * sign >= 0.0 && cross2(b, c, p) * sign >= 0.0 && cross2(c, a, p) * sign >= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_and_triangle;
+    use crate::{Layer, LayerData};
+
+    #[test]
+    fn triangulate_quad_keeps_layers() {
+        let mut node = quad_and_triangle();
+        node.face_stack
+            .push(Layer::new("material", 1, LayerData::Int32(vec![1, 2])));
+        node.corner_stack.push(Layer::new(
+            "uv",
+            1,
+            LayerData::Float(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        ));
+        assert!(!node.is_triangulated().unwrap());
+        node.triangulate().unwrap();
+
+        assert!(node.is_triangulated().unwrap());
+        assert_eq!(node.face_count(), 3);
+        assert_eq!(
+            node.face_stack.get("material").unwrap().data,
+            LayerData::Int32(vec![1, 1, 2])
+        );
+        // The quad has uv equal to its vertex, the triangle stores 4, 5, 6 for vertex 1, 4, 2.
+        let uv = node.corner_stack.get("uv").unwrap();
+        let material = node.face_stack.get("material").unwrap();
+        for (corner, vertex) in node.corner_vertices().unwrap().into_iter().enumerate() {
+            let expected = match (material.get(corner / 3, 0) as i32, vertex) {
+                (1, vertex) => vertex as f64,
+                (_, 1) => 4.0,
+                (_, 4) => 5.0,
+                _ => 6.0,
+            };
+            assert_eq!(uv.get(corner, 0), expected);
+        }
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn triangulate_zeroes_new_edges() {
+        let mut node = quad_and_triangle();
+        node.edge_stack.push(Layer::new(
+            "creases",
+            1,
+            LayerData::Float(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]),
+        ));
+        node.generate_neighbours().unwrap();
+        node.triangulate().unwrap();
+
+        // Every original edge keeps its value once, the diagonal of the quad gets zeros.
+        let creases = node.edge_stack.get("creases").unwrap();
+        let mut values: Vec<f64> = (0..creases.len()).map(|e| creases.get(e, 0)).collect();
+        values.sort_by(f64::total_cmp);
+        assert_eq!(values, vec![0.0, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        let neighbour = node.edge_stack.get("neighbour").unwrap();
+        assert_eq!(
+            (0..neighbour.len())
+                .filter(|&e| neighbour.get(e, 0) >= 0.0)
+                .count(),
+            4
+        );
+        node.validate().unwrap();
+    }
+
+    #[test]
+    fn triangulate_concave_polygon() {
+        // An L shape, where a fan from the first corner would leave the polygon.
+        let points = [
+            [0.0, 0.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 2.0, 0.0],
+            [0.0, 2.0, 0.0],
+        ];
+        let triangles = triangulate_polygon(&points);
+
+        assert_eq!(triangles.len(), 4);
+        let area: f64 = triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                let (a, b, c) = (points[a], points[b], points[c]);
+                cross2([a[0], a[1]], [b[0], b[1]], [c[0], c[1]]) / 2.0
+            })
+            .sum();
+        assert_eq!(area, 3.0);
+        for &[a, b, c] in &triangles {
+            let (a, b, c) = (points[a], points[b], points[c]);
+            assert!(cross2([a[0], a[1]], [b[0], b[1]], [c[0], c[1]]) > 0.0);
+        }
+    }
+}
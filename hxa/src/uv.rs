@@ -0,0 +1,551 @@
+//! UV sets and utilities working in UV space.
+//!
+//! The first UV set is the `uv` layer, followed by `uv1`, `uv2` and so on. A set can be stored
+//! in the corner stack, which allows seams, or in the vertex stack. The utilities here work on
+//! either and write back to where the set is stored.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::math::{length, polygon_normal, Vec3};
+use hxa_sys::HXALayerDataType;
+
+use crate::{convention, Error, GeometryNode, Layer, LayerData, Result};
+
+/// The name of the layer holding a UV set.
+pub fn uv_layer_name(set: usize) -> String {
+    match set {
+        0 => convention::LAYER_UV0.to_string(),
+        set => format!("{}{}", convention::LAYER_UV0, set),
+    }
+}
+
+/// The texel density of the faces of a node, in texels per unit of length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexelDensity {
+    /// The density of every face, 0 for faces without area.
+    pub faces: Vec<f64>,
+    /// The smallest density of a face with area.
+    pub min: f64,
+    /// The largest density of a face with area.
+    pub max: f64,
+    /// The density of all faces together, as if they were one face.
+    pub mean: f64,
+}
+
+impl GeometryNode {
+    /// The UV sets of the node, from set 0 up to the first set missing from both stacks.
+    pub fn uv_sets(&self) -> Vec<usize> {
+        (0..)
+            .take_while(|&set| {
+                let name = uv_layer_name(set);
+                self.corner_stack.find(&name).is_some() || self.vertex_stack.find(&name).is_some()
+            })
+            .collect()
+    }
+
+    /// The UV of every corner, read from the corner stack or through the vertices of the
+    /// corners from the vertex stack.
+    pub fn corner_uvs(&self, set: usize) -> Result<Vec<[f64; 2]>> {
+        let name = uv_layer_name(set);
+        if let Some(layer) = self.corner_stack.find(&name) {
+            return layer.to_vec2();
+        }
+        let uvs = self.vertex_stack.get(&name)?.to_vec2()?;
+
+        Ok(self
+            .corner_vertices()?
+            .into_iter()
+            .map(|vertex| uvs[vertex])
+            .collect())
+    }
+
+    /// The UV of every vertex.
+    ///
+    /// Sets stored per corner fail with [`Error::Discontinuous`] if they have seams. Vertices
+    /// that are not used by any corner get zeros.
+    pub fn vertex_uvs(&self, set: usize) -> Result<Vec<[f64; 2]>> {
+        let name = uv_layer_name(set);
+        if self.corner_stack.find(&name).is_none() {
+            return self.vertex_stack.get(&name)?.to_vec2();
+        }
+        if !self.is_corner_layer_continuous(&name)? {
+            return Err(Error::Discontinuous { name });
+        }
+        let mut uvs = vec![[0.0; 2]; self.vertex_count()];
+        for (vertex, uv) in self
+            .corner_vertices()?
+            .into_iter()
+            .zip(self.corner_uvs(set)?)
+        {
+            uvs[vertex] = uv;
+        }
+
+        Ok(uvs)
+    }
+
+    /// Store a UV set per corner, replacing the set in either stack.
+    ///
+    /// The set keeps the type of the layer it replaces, and is stored as float otherwise.
+    pub fn set_corner_uvs(&mut self, set: usize, uvs: &[[f64; 2]]) -> Result<()> {
+        let name = uv_layer_name(set);
+        let corner_count = self.reference()?.len();
+        if uvs.len() != corner_count {
+            return Err(Error::LayerLength {
+                name,
+                expected: corner_count,
+                actual: uvs.len(),
+            });
+        }
+        let layer = self.uv_layer(name, uvs);
+        self.vertex_stack.remove(&layer.name);
+        self.corner_stack.insert(layer);
+
+        Ok(())
+    }
+
+    /// Store a UV set per vertex, replacing the set in either stack. The type is kept as in
+    /// [`set_corner_uvs`](Self::set_corner_uvs).
+    pub fn set_vertex_uvs(&mut self, set: usize, uvs: &[[f64; 2]]) -> Result<()> {
+        let name = uv_layer_name(set);
+        if uvs.len() != self.vertex_count() {
+            return Err(Error::LayerLength {
+                name,
+                expected: self.vertex_count(),
+                actual: uvs.len(),
+            });
+        }
+        let layer = self.uv_layer(name, uvs);
+        self.corner_stack.remove(&layer.name);
+        self.vertex_stack.insert(layer);
+
+        Ok(())
+    }
+
+    /// The UV island of every face, numbered in order of their first face.
+    ///
+    /// Faces are in the same island when they share a vertex with the same UV.
+    pub fn uv_islands(&self, set: usize) -> Result<Vec<usize>> {
+        let uvs = self.corner_uvs(set)?;
+        let corner_vertices = self.corner_vertices()?;
+        let faces = self.faces()?;
+
+        let mut roots: Vec<usize> = (0..faces.len()).collect();
+        fn find(roots: &mut [usize], mut face: usize) -> usize {
+            while roots[face] != face {
+                roots[face] = roots[roots[face]];
+                face = roots[face];
+            }
+            face
+        }
+
+        let mut uv_vertices: HashMap<(usize, [u64; 2]), usize> = HashMap::new();
+        for (face, range) in faces.iter().enumerate() {
+            for corner in range.clone() {
+                let key = (corner_vertices[corner], uvs[corner].map(f64::to_bits));
+                let other = *uv_vertices.entry(key).or_insert(face);
+                let (a, b) = (find(&mut roots, face), find(&mut roots, other));
+                roots[a.max(b)] = a.min(b);
+            }
+        }
+        let mut islands = HashMap::new();
+        Ok((0..faces.len())
+            .map(|face| {
+                let root = find(&mut roots, face);
+                let next = islands.len();
+                *islands.entry(root).or_insert(next)
+            })
+            .collect())
+    }
+
+    /// The minimum and maximum UV used by any corner, or `None` if there are no corners.
+    pub fn uv_bounds(&self, set: usize) -> Result<Option<[[f64; 2]; 2]>> {
+        Ok(self.corner_uvs(set)?.into_iter().fold(None, |bounds, uv| {
+            let [min, max] = bounds.unwrap_or([uv, uv]);
+            Some([
+                [min[0].min(uv[0]), min[1].min(uv[1])],
+                [max[0].max(uv[0]), max[1].max(uv[1])],
+            ])
+        }))
+    }
+
+    /// Replace every V with 1 - V, to switch between APIs with the origin at the bottom or at
+    /// the top of the texture.
+    pub fn flip_v(&mut self, set: usize) -> Result<()> {
+        let uvs: Vec<[f64; 2]> = self
+            .corner_uvs(set)?
+            .into_iter()
+            .map(|[u, v]| [u, 1.0 - v])
+            .collect();
+        self.write_uvs(set, &uvs)
+    }
+
+    /// The texel density of every face for a square texture with sides of `texture_size`
+    /// texels.
+    pub fn texel_density(&self, set: usize, texture_size: u32) -> Result<TexelDensity> {
+        let uvs = self.corner_uvs(set)?;
+        let positions = self.positions()?;
+        let corner_vertices = self.corner_vertices()?;
+        let size = texture_size as f64;
+
+        let mut faces = Vec::new();
+        let (mut min, mut max) = (f64::INFINITY, 0.0f64);
+        let (mut total_uv_area, mut total_area) = (0.0, 0.0);
+        for range in self.faces()? {
+            let points: Vec<Vec3> = range
+                .clone()
+                .map(|corner| positions[corner_vertices[corner]])
+                .collect();
+            let area = length(polygon_normal(&points)) / 2.0;
+            let uv_area = uv_polygon_area(&uvs[range]);
+            if area > 0.0 {
+                let density = (uv_area / area).sqrt() * size;
+                min = min.min(density);
+                max = max.max(density);
+                total_uv_area += uv_area;
+                total_area += area;
+                faces.push(density);
+            } else {
+                faces.push(0.0);
+            }
+        }
+
+        Ok(TexelDensity {
+            faces,
+            min: if min.is_finite() { min } else { 0.0 },
+            max,
+            mean: if total_area > 0.0 {
+                (total_uv_area / total_area).sqrt() * size
+            } else {
+                0.0
+            },
+        })
+    }
+
+    /// Move the UV islands next to each other and scale them together to fit in [0, 1].
+    ///
+    /// Islands are placed on shelves from the tallest to the shortest, keeping their relative
+    /// size and orientation, with about `margin` of space around them. See
+    /// [`uv_islands`](Self::uv_islands).
+    pub fn pack_uv_islands(&mut self, set: usize, margin: f64) -> Result<()> {
+        let islands = self.uv_islands(set)?;
+        let faces = self.faces()?;
+        let mut uvs = self.corner_uvs(set)?;
+        pack_islands(&faces, &islands, &mut uvs, margin);
+        self.write_uvs(set, &uvs)
+    }
+
+    /// Write corner UVs back to the stack the set is stored in, keeping its type.
+    ///
+    /// Sets stored per vertex take the value of the last corner of every vertex.
+    /// A layer holding a UV set, with the type of the layer of the same name in either stack.
+    fn uv_layer(&self, name: String, uvs: &[[f64; 2]]) -> Layer {
+        let data_type = self
+            .corner_stack
+            .find(&name)
+            .or_else(|| self.vertex_stack.find(&name))
+            .map_or(HXALayerDataType::HXA_LDT_FLOAT, Layer::data_type);
+        let data = uvs.iter().flatten().copied().collect();
+        Layer::new(name, 2, LayerData::Double(data)).convert_to(data_type)
+    }
+
+    pub(crate) fn write_uvs(&mut self, set: usize, uvs: &[[f64; 2]]) -> Result<()> {
+        let name = uv_layer_name(set);
+        let corner_vertices = self.corner_vertices()?;
+        let (layer, elements) = match self.corner_stack.find_mut(&name) {
+            Some(layer) => (layer, (0..uvs.len()).collect()),
+            None => (self.vertex_stack.get_mut(&name)?, corner_vertices),
+        };
+        layer.expect_components(2)?;
+        for (&element, uv) in elements.iter().zip(uvs) {
+            for (component, &value) in uv.iter().enumerate() {
+                layer.set(element, component, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Move islands of faces next to each other and scale them together to fit in [0, 1], as
+/// described in [`GeometryNode::pack_uv_islands`].
+pub(crate) fn pack_islands(
+    faces: &[Range<usize>],
+    islands: &[usize],
+    uvs: &mut [[f64; 2]],
+    margin: f64,
+) {
+    let bounds = island_bounds(faces, islands, uvs);
+    let sizes: Vec<[f64; 2]> = bounds.iter().map(bounds_size).collect();
+    let area: f64 = sizes.iter().map(|[w, h]| w * h).sum();
+    let side = sizes.iter().map(|size| size[0]).fold(area.sqrt(), f64::max);
+    let (corners, extent) = shelf_pack(&sizes, margin * side, side);
+    if extent <= 0.0 {
+        return;
+    }
+
+    let corners: Vec<[f64; 2]> = corners
+        .iter()
+        .map(|corner| corner.map(|v| v / extent))
+        .collect();
+    place_islands(faces, islands, uvs, &bounds, &corners, 1.0 / extent);
+}
+
+/// Move islands of faces next to each other and scale them together to fit in [0, 1], keeping
+/// `padding` of space between and around them after scaling.
+///
+/// Islands are scaled as much as the padding allows, so the padding can be given in texels of
+/// the final texture.
+pub(crate) fn pack_islands_padded(
+    faces: &[Range<usize>],
+    islands: &[usize],
+    uvs: &mut [[f64; 2]],
+    padding: f64,
+) {
+    let bounds = island_bounds(faces, islands, uvs);
+    let sizes: Vec<[f64; 2]> = bounds.iter().map(bounds_size).collect();
+    let largest = sizes.iter().flatten().copied().fold(0.0, f64::max);
+    if largest <= 0.0 {
+        return;
+    }
+    let side = 1.0 - 2.0 * padding;
+    let pack = |scale: f64| {
+        let scaled: Vec<[f64; 2]> = sizes.iter().map(|size| size.map(|v| v * scale)).collect();
+        shelf_pack(&scaled, padding, side)
+    };
+
+    // Search the largest scale at which the islands fit.
+    let (mut low, mut high) = (0.0, 1.0 / largest);
+    for _ in 0..48 {
+        let middle = (low + high) / 2.0;
+        if pack(middle).1 <= 1.0 {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    let (corners, _) = pack(low);
+    place_islands(faces, islands, uvs, &bounds, &corners, low);
+}
+
+/// The UV bounding box of every island.
+fn island_bounds(
+    faces: &[Range<usize>],
+    islands: &[usize],
+    uvs: &[[f64; 2]],
+) -> Vec<[[f64; 2]; 2]> {
+    let island_count = islands.iter().max().map_or(0, |&max| max + 1);
+    let mut bounds = vec![[[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]]; island_count];
+    for (range, &island) in faces.iter().zip(islands) {
+        let [min, max] = &mut bounds[island];
+        for uv in &uvs[range.clone()] {
+            for axis in 0..2 {
+                min[axis] = min[axis].min(uv[axis]);
+                max[axis] = max[axis].max(uv[axis]);
+            }
+        }
+    }
+    bounds
+}
+
+fn bounds_size([min, max]: &[[f64; 2]; 2]) -> [f64; 2] {
+    [max[0] - min[0], max[1] - min[1]]
+}
+
+/// Place boxes on shelves from the tallest to the shortest, starting a new shelf when a row
+/// grows wider than `side`, with `padding` between and around them.
+///
+/// Returns the lower corner of every box and the size of the square holding them all.
+fn shelf_pack(sizes: &[[f64; 2]], padding: f64, side: f64) -> (Vec<[f64; 2]>, f64) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b][1].total_cmp(&sizes[a][1]));
+
+    let mut corners = vec![[0.0; 2]; sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (padding, padding, 0.0f64);
+    let mut extent = 0.0f64;
+    for index in order {
+        let [width, height] = sizes[index];
+        if x > padding && x + width > side + padding {
+            x = padding;
+            y += shelf_height + padding;
+            shelf_height = 0.0;
+        }
+        corners[index] = [x, y];
+        x += width + padding;
+        shelf_height = shelf_height.max(height);
+        extent = extent.max(x).max(y + shelf_height + padding);
+    }
+    (corners, extent)
+}
+
+/// Scale every island from its lower bound and move it to its corner.
+fn place_islands(
+    faces: &[Range<usize>],
+    islands: &[usize],
+    uvs: &mut [[f64; 2]],
+    bounds: &[[[f64; 2]; 2]],
+    corners: &[[f64; 2]],
+    scale: f64,
+) {
+    for (range, &island) in faces.iter().zip(islands) {
+        for uv in &mut uvs[range.clone()] {
+            for axis in 0..2 {
+                uv[axis] = (uv[axis] - bounds[island][0][axis]) * scale + corners[island][axis];
+            }
+        }
+    }
+}
+
+/// The area of a polygon in UV space.
+fn uv_polygon_area(uvs: &[[f64; 2]]) -> f64 {
+    let twice: f64 = uvs
+        .iter()
+        .zip(uvs.iter().cycle().skip(1))
+        .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+        .sum();
+    twice.abs() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::{cube, quad_and_triangle};
+
+    /// The quad and triangle with a seam between them in uv.
+    fn with_seam() -> GeometryNode {
+        let mut node = quad_and_triangle();
+        let uvs = [
+            [0.0, 0.0],
+            [0.5, 0.0],
+            [0.5, 0.5],
+            [0.0, 0.5],
+            [0.75, 0.0],
+            [1.0, 0.0],
+            [0.75, 0.5],
+        ];
+        node.set_corner_uvs(0, &uvs).unwrap();
+        node
+    }
+
+    #[test]
+    fn uv_sets_and_storage() {
+        let mut node = with_seam();
+        assert_eq!(uv_layer_name(0), "uv");
+        assert_eq!(uv_layer_name(1), "uv1");
+        assert_eq!(node.uv_sets(), vec![0]);
+        assert_eq!(
+            node.vertex_uvs(0),
+            Err(Error::Discontinuous {
+                name: "uv".to_string()
+            })
+        );
+
+        let positions = node.positions().unwrap();
+        let planar: Vec<[f64; 2]> = positions.iter().map(|p| [p[0], p[1]]).collect();
+        node.vertex_stack.push(Layer::new(
+            "uv1",
+            2,
+            LayerData::Double(planar.iter().flatten().copied().collect()),
+        ));
+        assert_eq!(node.uv_sets(), vec![0, 1]);
+        assert_eq!(node.vertex_uvs(1).unwrap(), planar);
+        assert_eq!(node.corner_uvs(1).unwrap()[4], planar[1]);
+
+        node.flip_v(1).unwrap();
+        assert_eq!(node.vertex_uvs(1).unwrap()[3], [0.0, 0.0]);
+        assert!(matches!(
+            node.vertex_stack.get("uv1").unwrap().data,
+            LayerData::Double(_)
+        ));
+    }
+
+    #[test]
+    fn uv_sets_move_between_stacks() {
+        let mut node = quad_and_triangle();
+        let planar: Vec<[f64; 2]> = node
+            .positions()
+            .unwrap()
+            .iter()
+            .map(|p| [p[0] / 2.0, p[1]])
+            .collect();
+        let corner_uvs: Vec<[f64; 2]> = node
+            .corner_vertices()
+            .unwrap()
+            .into_iter()
+            .map(|vertex| planar[vertex])
+            .collect();
+        node.set_corner_uvs(0, &corner_uvs).unwrap();
+        assert!(node.corner_stack.find("uv").is_some());
+
+        let uvs = node.vertex_uvs(0).unwrap();
+        node.set_vertex_uvs(0, &uvs).unwrap();
+        assert!(node.corner_stack.find("uv").is_none());
+        assert_eq!(node.vertex_uvs(0).unwrap(), planar);
+        assert!(node.set_vertex_uvs(0, &uvs[1..]).is_err());
+    }
+
+    #[test]
+    fn replaced_uv_sets_keep_their_type() {
+        let mut node = quad_and_triangle();
+        node.vertex_stack
+            .push(Layer::new("uv", 2, LayerData::Double(vec![0.0; 10])));
+        let precise = [0.1, 1.0 / 3.0];
+        let corner_uvs = vec![precise; node.corner_count()];
+        node.set_corner_uvs(0, &corner_uvs).unwrap();
+        let layer = node.corner_stack.get("uv").unwrap();
+        assert_eq!(layer.data_type(), HXALayerDataType::HXA_LDT_DOUBLE);
+        assert_eq!(node.corner_uvs(0).unwrap(), corner_uvs);
+
+        node.set_vertex_uvs(0, &[precise; 5]).unwrap();
+        let layer = node.vertex_stack.get("uv").unwrap();
+        assert_eq!(layer.data_type(), HXALayerDataType::HXA_LDT_DOUBLE);
+        assert_eq!(node.vertex_uvs(0).unwrap(), [precise; 5]);
+    }
+
+    #[test]
+    fn islands_bounds_and_density() {
+        let node = with_seam();
+        assert_eq!(node.uv_islands(0).unwrap(), [0, 1]);
+        assert_eq!(node.uv_bounds(0).unwrap(), Some([[0.0, 0.0], [1.0, 0.5]]));
+
+        // The quad covers a quarter of the texture with an area of 1.
+        let density = node.texel_density(0, 1024).unwrap();
+        assert_eq!(density.faces[0], 512.0);
+        assert_eq!(density.max, 512.0);
+        assert!(density.min < density.mean && density.mean < density.max);
+
+        let mut cube = cube();
+        let uvs: Vec<[f64; 2]> = cube
+            .corner_vertices()
+            .unwrap()
+            .into_iter()
+            .map(|v| [v as f64, 0.0])
+            .collect();
+        cube.set_corner_uvs(0, &uvs).unwrap();
+        assert_eq!(cube.uv_islands(0).unwrap(), [0; 6]);
+    }
+
+    #[test]
+    fn pack_islands_into_unit_square() {
+        let mut node = with_seam();
+        let uvs: Vec<[f64; 2]> = node
+            .corner_uvs(0)
+            .unwrap()
+            .into_iter()
+            .map(|[u, v]| [u * 8.0 - 3.0, v * 8.0 + 5.0])
+            .collect();
+        node.set_corner_uvs(0, &uvs).unwrap();
+        node.pack_uv_islands(0, 0.01).unwrap();
+
+        let [min, max] = node.uv_bounds(0).unwrap().unwrap();
+        assert!(min.iter().all(|&v| v > 0.0));
+        assert!(max.iter().all(|&v| v < 1.0));
+        assert_eq!(node.uv_islands(0).unwrap(), [0, 1]);
+        // Islands do not overlap and keep their relative size.
+        let uvs = node.corner_uvs(0).unwrap();
+        let quad_width = uvs[1][0] - uvs[0][0];
+        let triangle_width = uvs[5][0] - uvs[4][0];
+        assert!((quad_width - 2.0 * triangle_width).abs() < 1e-6);
+        assert!(uvs[4][0] >= uvs[1][0] || uvs[4][1] >= uvs[2][1]);
+    }
+}